@@ -163,7 +163,7 @@ mod tests {
         };
         let r = query(deps.as_ref(), mock_env(), q).unwrap();
         let acct: ChannelResponse = from_slice(&r).unwrap();
-        assert_eq!(true, acct.creation_time.nanos() > 0);
+        assert!(acct.creation_time.nanos() > 0);
 
         // account should be set up
         let q = QueryMsg::Channel {
@@ -171,7 +171,7 @@ mod tests {
         };
         let r = query(deps.as_ref(), mock_env(), q).unwrap();
         let acct: ChannelResponse = from_slice(&r).unwrap();
-        assert_eq!(true, acct.creation_time.nanos() > 0);
+        assert!(acct.creation_time.nanos() > 0);
     }
 
     #[test]
@@ -181,7 +181,7 @@ mod tests {
         connect(deps.as_mut(), channel_id);
 
         let ack_msg =
-            mock_ibc_packet_ack(channel_id, b"{}", IbcAcknowledgement::new(&[1])).unwrap();
+            mock_ibc_packet_ack(channel_id, b"{}", IbcAcknowledgement::new([1])).unwrap();
         ibc_packet_ack(deps.as_mut(), mock_env(), ack_msg).unwrap_err();
 
         let timeout_msg = mock_ibc_packet_timeout(channel_id, b"{}").unwrap();
@@ -205,7 +205,7 @@ mod tests {
         let rcv_msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
         let res = ibc_packet_receive(deps.as_mut(), mock_env(), rcv_msg).unwrap();
 
-        let error = res.attributes.iter().find(|r| r.key == "error".to_string());
+        let error = res.attributes.iter().find(|r| r.key == "error");
         // TODO: Unsupported query type: Stargate
         assert_eq!("error", error.unwrap().key);
         assert_eq!(0, res.messages.len());
@@ -4,7 +4,8 @@ use std::fs::create_dir_all;
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use cw_ics20_swap::msg::{
-    ChannelResponse, ExecuteMsg, InitMsg, ListChannelsResponse, QueryMsg, TransferMsg,
+    CallbackMsg, ChannelResponse, ExecuteMsg, InitMsg, ListChannelsResponse, MigrateMsg,
+    PendingTransfersResponse, QueryMsg, TransferMsg, TransferResponse,
 };
 
 fn main() {
@@ -14,9 +15,13 @@ fn main() {
     remove_schemas(&out_dir).unwrap();
 
     export_schema(&schema_for!(InitMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(TransferMsg), &out_dir);
+    export_schema(&schema_for!(CallbackMsg), &out_dir);
     export_schema(&schema_for!(ChannelResponse), &out_dir);
     export_schema(&schema_for!(ListChannelsResponse), &out_dir);
+    export_schema(&schema_for!(TransferResponse), &out_dir);
+    export_schema(&schema_for!(PendingTransfersResponse), &out_dir);
 }
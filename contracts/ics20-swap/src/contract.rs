@@ -1,20 +1,35 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Addr, Binary, Deps, DepsMut, Env, IbcMsg, MessageInfo, Order, Response, StdResult,
+    coins, to_binary, Addr, BankMsg, Binary, Coin, Deps, DepsMut, Empty, Env, MessageInfo, Order,
+    Response, StdError, StdResult, Uint128, Uint256, Uint64, WasmMsg,
 };
 
 use cw2::set_contract_version;
+use std::convert::TryFrom;
 
 use crate::amount::Amount;
 use crate::error::ContractError;
+use crate::ibc::{execute_create_pool, execute_pipeline, packet_denom, send_tracked_packet};
 use crate::ibc_msg::Ics20Packet;
 use crate::msg::{
-    ChannelResponse, ConfigResponse, ExecuteMsg, InitMsg, ListChannelsResponse, ListLockupResponse,
-    LockupResponse, QueryMsg, TransferMsg,
+    ChannelResponse, ConfigResponse, ExecuteMsg, ExternalTokenMsg, InitMsg,
+    ListAllowedDenomsResponse, ListAllowedPoolsResponse, ListChannelsResponse,
+    ListExternalTokensResponse, ListLockupResponse, ListRateLimitsResponse,
+    ListUserBalancesResponse, LockupMigrateMsg, LockupResponse, MigrateMsg,
+    PendingTransfersResponse, QueryMsg, RateLimitMsg, RateLimitResponse, ReserveMsg,
+    ReserveResponse, TransferMsg, TransferResponse, TransferWithActionMsg, UserBalanceResponse,
 };
-use crate::state::{increase_channel_balance, Config, CHANNEL_INFO, CHANNEL_STATE, CONFIG, LOCKUP};
-use cw_utils::one_coin;
+use crate::state::{
+    increase_channel_balance, load_open_channel, record_flow, reduce_channel_balance,
+    remove_external_token, reserve_amount, save_external_token, ChannelStatus, Config, Flow,
+    RateLimit, ADMIN, ALLOWED_DENOMS, ALLOWED_POOLS, CHANNEL_INFO, CHANNEL_STATE, CONFIG,
+    EXTERNAL_DENOMS, EXTERNAL_TOKENS, LOCKUP, PENDING_TRANSFERS, RATE_LIMITS, RESERVES,
+    RESERVE_SHARES, TRANSFERS, USER_BALANCES,
+};
+use cw_migrate::{check_migrate_version, Version};
+use cw_storage_plus::Bound;
+use cw_utils::{nonpayable, one_coin};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:cw-ics20-swap";
@@ -22,18 +37,34 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    deps: DepsMut,
+    mut deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     msg: InitMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     let cfg = Config {
         default_timeout: msg.default_timeout,
         lockup_id: msg.lockup_id,
+        restrict_pools: false,
     };
     CONFIG.save(deps.storage, &cfg)?;
 
+    let admin = match msg.gov_contract {
+        Some(gov_contract) => deps.api.addr_validate(&gov_contract)?,
+        None => info.sender,
+    };
+    ADMIN.set(deps.branch(), Some(admin))?;
+
+    for token in msg.external_tokens {
+        save_external_token(
+            deps.storage,
+            &token.channel,
+            &token.denom,
+            &token.local_denom,
+        )?;
+    }
+
     Ok(Response::default())
 }
 
@@ -49,6 +80,53 @@ pub fn execute(
             let coin = one_coin(&info)?;
             execute_transfer(deps, env, msg, Amount::Native(coin), info.sender)
         }
+        ExecuteMsg::TransferWithAction(msg) => {
+            let coin = one_coin(&info)?;
+            execute_transfer_with_action(deps, env, msg, Amount::Native(coin), info.sender)
+        }
+        ExecuteMsg::Pipeline(msg) => execute_pipeline(deps, env, info, msg),
+        ExecuteMsg::CreatePool(msg) => execute_create_pool(deps, env, info, msg),
+        ExecuteMsg::AddReserve(msg) => {
+            let coin = one_coin(&info)?;
+            execute_add_reserve(deps, info.sender, msg, coin)
+        }
+        ExecuteMsg::WithdrawReserve {
+            channel,
+            denom,
+            amount,
+        } => execute_withdraw_reserve(deps, info, channel, denom, amount),
+        ExecuteMsg::AddExternalToken(msg) => execute_add_external_token(deps, info, msg),
+        ExecuteMsg::RemoveExternalToken { channel, denom } => {
+            execute_remove_external_token(deps, info, channel, denom)
+        }
+        ExecuteMsg::UpdateConfig {
+            default_timeout,
+            lockup_id,
+            restrict_pools,
+        } => execute_update_config(deps, info, default_timeout, lockup_id, restrict_pools),
+        ExecuteMsg::UpdateAdmin { admin } => {
+            let admin = deps.api.addr_validate(&admin)?;
+            Ok(ADMIN.execute_update_admin(deps, info, Some(admin))?)
+        }
+        ExecuteMsg::MigrateLockup { channel, owner } => {
+            execute_migrate_lockup(deps, info, channel, owner)
+        }
+        ExecuteMsg::FreezeChannel { channel } => execute_freeze_channel(deps, info, channel, true),
+        ExecuteMsg::UnfreezeChannel { channel } => {
+            execute_freeze_channel(deps, info, channel, false)
+        }
+        ExecuteMsg::SetRateLimit(msg) => execute_set_rate_limit(deps, info, msg),
+        ExecuteMsg::RemoveRateLimit { channel, denom } => {
+            execute_remove_rate_limit(deps, info, channel, denom)
+        }
+        ExecuteMsg::AllowPools { pool_ids } => execute_allow_pools(deps, info, pool_ids, true),
+        ExecuteMsg::DisallowPools { pool_ids } => execute_allow_pools(deps, info, pool_ids, false),
+        ExecuteMsg::AllowDenoms { channel, denoms } => {
+            execute_allow_denoms(deps, info, channel, denoms, true)
+        }
+        ExecuteMsg::DisallowDenoms { channel, denoms } => {
+            execute_allow_denoms(deps, info, channel, denoms, false)
+        }
     }
 }
 
@@ -58,43 +136,104 @@ pub fn execute_transfer(
     msg: TransferMsg,
     amount: Amount,
     sender: Addr,
+) -> Result<Response, ContractError> {
+    let callback = msg
+        .callback
+        .map(|callback| deps.api.addr_validate(&callback))
+        .transpose()?;
+
+    // build ics20 packet
+    let denom = packet_denom(deps.storage, &env, &msg.channel, &amount.denom())?;
+    let packet = Ics20Packet::new(amount.amount(), denom, sender.as_ref(), &msg.remote_address);
+
+    send_packet(
+        deps,
+        env,
+        msg.channel,
+        msg.timeout,
+        amount,
+        packet,
+        callback,
+    )
+}
+
+pub fn execute_transfer_with_action(
+    deps: DepsMut,
+    env: Env,
+    msg: TransferWithActionMsg,
+    amount: Amount,
+    sender: Addr,
+) -> Result<Response, ContractError> {
+    let callback = msg
+        .callback
+        .map(|callback| deps.api.addr_validate(&callback))
+        .transpose()?;
+
+    // build ics20 packet, the counterpart contract runs the action
+    let denom = packet_denom(deps.storage, &env, &msg.channel, &amount.denom())?;
+    let mut packet = Ics20Packet::new(amount.amount(), denom, sender.as_ref(), &msg.remote_address);
+    let action = msg.action.name();
+    packet.action = Some(msg.action);
+
+    let res = send_packet(
+        deps,
+        env,
+        msg.channel,
+        msg.timeout,
+        amount,
+        packet,
+        callback,
+    )?;
+    Ok(res.add_attribute("packet_action", action))
+}
+
+fn send_packet(
+    deps: DepsMut,
+    env: Env,
+    channel: String,
+    timeout: Option<u64>,
+    amount: Amount,
+    packet: Ics20Packet,
+    callback: Option<Addr>,
 ) -> Result<Response, ContractError> {
     if amount.is_empty() {
         return Err(ContractError::NoFunds {});
     }
-    // ensure the requested channel is registered
-    if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
-        return Err(ContractError::NoSuchChannel { id: msg.channel });
-    }
+    // ensure the requested channel is registered and open
+    load_open_channel(deps.storage, &channel)?;
 
     // delta from user is in seconds
-    let timeout_delta = match msg.timeout {
+    let timeout_delta = match timeout {
         Some(t) => t,
         None => CONFIG.load(deps.storage)?.default_timeout,
     };
     // timeout is in nanoseconds
     let timeout = env.block.time.plus_seconds(timeout_delta);
 
-    // build ics20 packet
-    let packet = Ics20Packet::new(
+    record_flow(
+        deps.storage,
+        env.block.time,
+        &channel,
+        &amount.denom(),
         amount.amount(),
-        amount.denom(),
-        sender.as_ref(),
-        &msg.remote_address,
-    );
-
-    increase_channel_balance(deps.storage, &msg.channel, &amount.denom(), amount.amount())?;
-
-    // prepare ibc message
-    let msg = IbcMsg::SendPacket {
-        channel_id: msg.channel,
-        data: to_binary(&packet)?,
-        timeout: timeout.into(),
-    };
+        Flow::Out,
+    )?;
+    increase_channel_balance(deps.storage, &channel, &amount.denom(), amount.amount())?;
+
+    // prepare ibc message, tracked by its sequence
+    let submsg = send_tracked_packet(
+        deps.storage,
+        &channel,
+        &packet,
+        &amount.denom(),
+        timeout,
+        callback,
+        None,
+    )?;
 
     // send response
     let res = Response::new()
-        .add_message(msg)
+        .add_submessage(submsg)
         .add_attribute("action", "transfer")
         .add_attribute("sender", &packet.sender)
         .add_attribute("receiver", &packet.receiver)
@@ -104,14 +243,428 @@ pub fn execute_transfer(
     Ok(res)
 }
 
+pub fn execute_add_reserve(
+    deps: DepsMut,
+    provider: Addr,
+    msg: ReserveMsg,
+    coin: Coin,
+) -> Result<Response, ContractError> {
+    if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
+        return Err(ContractError::NoSuchChannel { id: msg.channel });
+    }
+    let remote_denom = EXTERNAL_DENOMS
+        .may_load(deps.storage, (&msg.channel, &coin.denom))?
+        .ok_or(ContractError::NoAllowedToken {})?;
+
+    // the provider shares are priced with the reserve left after the payouts
+    let reserve = reserve_amount(deps.storage, &msg.channel, &coin.denom)?;
+    let total_shares = RESERVE_SHARES
+        .may_load(deps.storage, (&msg.channel, &coin.denom))?
+        .unwrap_or_default();
+    let shares = reserve_shares(coin.amount, total_shares, reserve, false)?;
+
+    increase_channel_balance(deps.storage, &msg.channel, &coin.denom, coin.amount)?;
+    RESERVE_SHARES.save(
+        deps.storage,
+        (&msg.channel, &coin.denom),
+        &(total_shares + shares),
+    )?;
+    RESERVES.update(
+        deps.storage,
+        (&msg.channel, &coin.denom, &provider),
+        |orig| -> StdResult<_> { Ok(orig.unwrap_or_default() + shares) },
+    )?;
+
+    let res = Response::new()
+        .add_attribute("action", "add_reserve")
+        .add_attribute("channel", msg.channel)
+        .add_attribute("provider", provider)
+        .add_attribute("denom", coin.denom)
+        .add_attribute("remote_denom", remote_denom)
+        .add_attribute("amount", coin.amount);
+    Ok(res)
+}
+
+pub fn execute_withdraw_reserve(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    denom: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+    if amount.is_zero() {
+        return Err(ContractError::NoFunds {});
+    }
+
+    // the provider withdraws up to the value of their shares in the reserve left
+    let key = (channel.as_str(), denom.as_str(), &info.sender);
+    let shares = RESERVES.may_load(deps.storage, key)?.unwrap_or_default();
+    let reserve = reserve_amount(deps.storage, &channel, &denom)?;
+    let total_shares = RESERVE_SHARES
+        .may_load(deps.storage, (&channel, &denom))?
+        .unwrap_or_default();
+    if amount > reserve_value(shares, total_shares, reserve)? {
+        return Err(ContractError::InsufficientReserve {});
+    }
+    // rounded up, so that the shares left are never worth more than the reserve left
+    let burnt = reserve_shares(amount, total_shares, reserve, true)?;
+
+    reduce_channel_balance(deps.storage, &channel, &denom, amount)?;
+    let total_key = (channel.as_str(), denom.as_str());
+    RESERVE_SHARES.save(deps.storage, total_key, &(total_shares - burnt))?;
+    if shares == burnt {
+        RESERVES.remove(deps.storage, key);
+    } else {
+        RESERVES.save(deps.storage, key, &(shares - burnt))?;
+    }
+
+    let msg = BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: coins(amount.u128(), &denom),
+    };
+    let res = Response::new()
+        .add_message(msg)
+        .add_attribute("action", "withdraw_reserve")
+        .add_attribute("channel", channel)
+        .add_attribute("provider", info.sender)
+        .add_attribute("denom", denom)
+        .add_attribute("amount", amount);
+    Ok(res)
+}
+
+// The shares are priced with a virtual share and token, so that an empty reserve has a price
+fn reserve_shares(
+    amount: Uint128,
+    total_shares: Uint128,
+    reserve: Uint128,
+    round_up: bool,
+) -> Result<Uint128, ContractError> {
+    let numerator = amount.full_mul(total_shares + Uint128::new(1));
+    let denominator = Uint256::from(reserve + Uint128::new(1));
+    let mut shares = numerator / denominator;
+    if round_up && shares * denominator != numerator {
+        shares += Uint256::from(1u128);
+    }
+    Uint128::try_from(shares).map_err(|_| ContractError::AmountOverflow {})
+}
+
+fn reserve_value(
+    shares: Uint128,
+    total_shares: Uint128,
+    reserve: Uint128,
+) -> Result<Uint128, ContractError> {
+    shares
+        .checked_multiply_ratio(reserve + Uint128::new(1), total_shares + Uint128::new(1))
+        .map_err(|_| ContractError::AmountOverflow {})
+}
+
+pub fn execute_add_external_token(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: ExternalTokenMsg,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
+        return Err(ContractError::NoSuchChannel { id: msg.channel });
+    }
+    save_external_token(deps.storage, &msg.channel, &msg.denom, &msg.local_denom)?;
+
+    let res = Response::new()
+        .add_attribute("action", "add_external_token")
+        .add_attribute("channel", msg.channel)
+        .add_attribute("remote_denom", msg.denom)
+        .add_attribute("denom", msg.local_denom);
+    Ok(res)
+}
+
+pub fn execute_remove_external_token(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    denom: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let local_denom = remove_external_token(deps.storage, &channel, &denom)?;
+
+    let res = Response::new()
+        .add_attribute("action", "remove_external_token")
+        .add_attribute("channel", channel)
+        .add_attribute("remote_denom", denom)
+        .add_attribute("denom", local_denom);
+    Ok(res)
+}
+
+pub fn execute_update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    default_timeout: Option<u64>,
+    lockup_id: Option<u64>,
+    restrict_pools: Option<bool>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let mut cfg = CONFIG.load(deps.storage)?;
+    if let Some(default_timeout) = default_timeout {
+        cfg.default_timeout = default_timeout;
+    }
+    if let Some(lockup_id) = lockup_id {
+        cfg.lockup_id = lockup_id;
+    }
+    if let Some(restrict_pools) = restrict_pools {
+        cfg.restrict_pools = restrict_pools;
+    }
+    CONFIG.save(deps.storage, &cfg)?;
+
+    let res = Response::new()
+        .add_attribute("action", "update_config")
+        .add_attribute("default_timeout", cfg.default_timeout.to_string())
+        .add_attribute("lockup_id", cfg.lockup_id.to_string())
+        .add_attribute("restrict_pools", cfg.restrict_pools.to_string());
+    Ok(res)
+}
+
+// wasmd rejects the migration of the lockup accounts created without the contract as admin
+pub fn execute_migrate_lockup(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    owner: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let lockup = LOCKUP
+        .load(deps.storage, (&channel, &owner))
+        .map_err(|_| ContractError::LockupNotFound {})?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let msg = WasmMsg::Migrate {
+        contract_addr: lockup.clone(),
+        new_code_id: cfg.lockup_id,
+        msg: to_binary(&LockupMigrateMsg {})?,
+    };
+
+    let res = Response::new()
+        .add_message(msg)
+        .add_attribute("action", "migrate_lockup")
+        .add_attribute("lockup", lockup)
+        .add_attribute("code_id", cfg.lockup_id.to_string());
+    Ok(res)
+}
+
+pub fn execute_freeze_channel(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    freeze: bool,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let mut channel_info = CHANNEL_INFO
+        .may_load(deps.storage, &channel)?
+        .ok_or_else(|| ContractError::NoSuchChannel {
+            id: channel.clone(),
+        })?;
+    if channel_info.status == ChannelStatus::Closed {
+        return Err(ContractError::ChannelClosed { id: channel });
+    }
+    channel_info.status = if freeze {
+        ChannelStatus::Frozen
+    } else {
+        ChannelStatus::Open
+    };
+    CHANNEL_INFO.save(deps.storage, &channel, &channel_info)?;
+
+    let action = if freeze {
+        "freeze_channel"
+    } else {
+        "unfreeze_channel"
+    };
+    let res = Response::new()
+        .add_attribute("action", action)
+        .add_attribute("channel", channel);
+    Ok(res)
+}
+
+pub fn execute_set_rate_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: RateLimitMsg,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
+        return Err(ContractError::NoSuchChannel { id: msg.channel });
+    }
+    if msg.period == 0 {
+        return Err(ContractError::InvalidRateLimitPeriod {});
+    }
+
+    let key = (msg.channel.as_str(), msg.denom.as_str());
+    let limit = match RATE_LIMITS.may_load(deps.storage, key)? {
+        Some(limit) => RateLimit {
+            max_inflow: msg.max_inflow,
+            max_outflow: msg.max_outflow,
+            period: msg.period,
+            ..limit
+        },
+        None => RateLimit::new(msg.max_inflow, msg.max_outflow, msg.period),
+    };
+    RATE_LIMITS.save(deps.storage, key, &limit)?;
+
+    let res = Response::new()
+        .add_attribute("action", "set_rate_limit")
+        .add_attribute("channel", msg.channel)
+        .add_attribute("denom", msg.denom)
+        .add_attribute("max_inflow", msg.max_inflow)
+        .add_attribute("max_outflow", msg.max_outflow)
+        .add_attribute("period", msg.period.to_string());
+    Ok(res)
+}
+
+pub fn execute_remove_rate_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    denom: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    RATE_LIMITS.remove(deps.storage, (&channel, &denom));
+
+    let res = Response::new()
+        .add_attribute("action", "remove_rate_limit")
+        .add_attribute("channel", channel)
+        .add_attribute("denom", denom);
+    Ok(res)
+}
+
+pub fn execute_allow_pools(
+    deps: DepsMut,
+    info: MessageInfo,
+    pool_ids: Vec<Uint64>,
+    allow: bool,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    for pool_id in &pool_ids {
+        if allow {
+            ALLOWED_POOLS.save(deps.storage, pool_id.u64(), &Empty {})?;
+        } else {
+            ALLOWED_POOLS.remove(deps.storage, pool_id.u64());
+        }
+    }
+
+    let action = if allow {
+        "allow_pools"
+    } else {
+        "disallow_pools"
+    };
+    let pool_ids: Vec<_> = pool_ids.iter().map(|id| id.to_string()).collect();
+    let res = Response::new()
+        .add_attribute("action", action)
+        .add_attribute("pool_ids", pool_ids.join(","));
+    Ok(res)
+}
+
+pub fn execute_allow_denoms(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    denoms: Vec<String>,
+    allow: bool,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if !CHANNEL_INFO.has(deps.storage, &channel) {
+        return Err(ContractError::NoSuchChannel { id: channel });
+    }
+    for denom in &denoms {
+        if allow {
+            ALLOWED_DENOMS.save(deps.storage, (&channel, denom), &Empty {})?;
+        } else {
+            ALLOWED_DENOMS.remove(deps.storage, (&channel, denom));
+        }
+    }
+
+    let action = if allow {
+        "allow_denoms"
+    } else {
+        "disallow_denoms"
+    };
+    let res = Response::new()
+        .add_attribute("action", action)
+        .add_attribute("channel", channel)
+        .add_attribute("denoms", denoms.join(","));
+    Ok(res)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let previous = check_migrate_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // 0.2.0 added the admin allowed to update the config
+    if previous < Version::new(0, 2, 0) {
+        let gov_contract = msg
+            .gov_contract
+            .ok_or(ContractError::MissingGovContract {})?;
+        let admin = deps.api.addr_validate(&gov_contract)?;
+        ADMIN.set(deps.branch(), Some(admin))?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let res = Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", previous.to_string())
+        .add_attribute("to_version", CONTRACT_VERSION);
+    Ok(res)
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::ListChannels {} => to_binary(&query_list(deps)?),
         QueryMsg::Channel { id } => to_binary(&query_channel(deps, id)?),
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Admin {} => to_binary(&ADMIN.query_admin(deps)?),
         QueryMsg::Lockup { channel, owner } => to_binary(&query_lockup(deps, channel, owner)?),
         QueryMsg::AllLockups { channel } => to_binary(&query_all_lockup(deps, channel)?),
+        QueryMsg::UserBalance { channel, sender } => {
+            to_binary(&query_user_balance(deps, channel, sender)?)
+        }
+        QueryMsg::ListUserBalances { channel } => {
+            to_binary(&query_list_user_balances(deps, channel)?)
+        }
+        QueryMsg::ListExternalTokens {} => to_binary(&query_list_external_tokens(deps)?),
+        QueryMsg::Reserve {
+            channel,
+            denom,
+            provider,
+        } => to_binary(&query_reserve(deps, channel, denom, provider)?),
+        QueryMsg::PendingTransfers {
+            sender,
+            start_after,
+            limit,
+        } => to_binary(&query_pending_transfers(deps, sender, start_after, limit)?),
+        QueryMsg::Transfer { channel, sequence } => {
+            to_binary(&query_transfer(deps, channel, sequence)?)
+        }
+        QueryMsg::RateLimit { channel, denom } => {
+            let limit = RATE_LIMITS.load(deps.storage, (&channel, &denom))?;
+            to_binary(&rate_limit_response(&env, channel, denom, limit))
+        }
+        QueryMsg::ListRateLimits { channel } => {
+            to_binary(&query_list_rate_limits(deps, env, channel)?)
+        }
+        QueryMsg::ListAllowedPools { start_after, limit } => {
+            to_binary(&query_list_allowed_pools(deps, start_after, limit)?)
+        }
+        QueryMsg::ListAllowedDenoms {
+            channel,
+            start_after,
+            limit,
+        } => to_binary(&query_list_allowed_denoms(
+            deps,
+            channel,
+            start_after,
+            limit,
+        )?),
     }
 }
 
@@ -150,8 +703,12 @@ pub fn query_channel(deps: Deps, id: String) -> StdResult<ChannelResponse> {
 
 fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
     let cfg = CONFIG.load(deps.storage)?;
+    let admin = ADMIN.get(deps)?.unwrap_or_else(|| Addr::unchecked(""));
     let res = ConfigResponse {
         default_timeout: cfg.default_timeout,
+        lockup_id: cfg.lockup_id,
+        gov_contract: admin.into(),
+        restrict_pools: cfg.restrict_pools,
     };
     Ok(res)
 }
@@ -176,14 +733,194 @@ fn query_all_lockup(deps: Deps, channel_id: String) -> StdResult<ListLockupRespo
     Ok(ListLockupResponse { lockups })
 }
 
+fn query_list_external_tokens(deps: Deps) -> StdResult<ListExternalTokensResponse> {
+    let tokens = EXTERNAL_TOKENS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|r| {
+            r.map(|((channel, denom), info)| ExternalTokenMsg {
+                channel,
+                denom,
+                local_denom: info.denom,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListExternalTokensResponse { tokens })
+}
+
+fn query_reserve(
+    deps: Deps,
+    channel: String,
+    denom: String,
+    provider: String,
+) -> StdResult<ReserveResponse> {
+    let provider = deps.api.addr_validate(&provider)?;
+    let shares = RESERVES
+        .may_load(deps.storage, (&channel, &denom, &provider))?
+        .unwrap_or_default();
+    let reserve = reserve_amount(deps.storage, &channel, &denom)?;
+    let total_shares = RESERVE_SHARES
+        .may_load(deps.storage, (&channel, &denom))?
+        .unwrap_or_default();
+    let amount = reserve_value(shares, total_shares, reserve)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    Ok(ReserveResponse {
+        provider: provider.into(),
+        amount: Amount::from_parts(denom, amount),
+        shares,
+    })
+}
+
+// Shows the quotas left over the rolling period
+fn rate_limit_response(
+    env: &Env,
+    channel: String,
+    denom: String,
+    mut limit: RateLimit,
+) -> RateLimitResponse {
+    limit.update_period(env.block.time);
+    RateLimitResponse {
+        channel,
+        denom,
+        max_inflow: limit.max_inflow,
+        max_outflow: limit.max_outflow,
+        period: limit.period,
+        remaining_inflow: limit.remaining_inflow(),
+        remaining_outflow: limit.remaining_outflow(),
+    }
+}
+
+fn query_list_rate_limits(
+    deps: Deps,
+    env: Env,
+    channel: String,
+) -> StdResult<ListRateLimitsResponse> {
+    let limits = RATE_LIMITS
+        .prefix(&channel)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|r| r.map(|(denom, limit)| rate_limit_response(&env, channel.clone(), denom, limit)))
+        .collect::<StdResult<_>>()?;
+    Ok(ListRateLimitsResponse { limits })
+}
+
+fn query_transfer(deps: Deps, channel: String, sequence: u64) -> StdResult<TransferResponse> {
+    let info = TRANSFERS.load(deps.storage, (&channel, sequence))?;
+    Ok(TransferResponse {
+        channel,
+        sequence,
+        info,
+    })
+}
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+fn query_pending_transfers(
+    deps: Deps,
+    sender: String,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<PendingTransfersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .as_ref()
+        .map(|(channel, sequence)| Bound::exclusive((channel.as_str(), *sequence)));
+
+    let transfers = PENDING_TRANSFERS
+        .sub_prefix(&sender)
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (channel, sequence) = item?;
+            query_transfer(deps, channel, sequence)
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(PendingTransfersResponse { transfers })
+}
+
+fn query_list_allowed_pools(
+    deps: Deps,
+    start_after: Option<Uint64>,
+    limit: Option<u32>,
+) -> StdResult<ListAllowedPoolsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(id.u64()));
+
+    let pool_ids = ALLOWED_POOLS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(Uint64::new))
+        .collect::<StdResult<_>>()?;
+    Ok(ListAllowedPoolsResponse {
+        restricted: CONFIG.load(deps.storage)?.restrict_pools,
+        pool_ids,
+    })
+}
+
+fn query_list_allowed_denoms(
+    deps: Deps,
+    channel: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ListAllowedDenomsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+
+    let denoms = ALLOWED_DENOMS
+        .prefix(&channel)
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(ListAllowedDenomsResponse { denoms })
+}
+
+fn query_user_balance(
+    deps: Deps,
+    channel_id: String,
+    sender: String,
+) -> StdResult<UserBalanceResponse> {
+    let balances = USER_BALANCES
+        .prefix((channel_id.as_str(), sender.as_str()))
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|r| r.map(|(denom, amount)| Amount::from_parts(denom, amount)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(UserBalanceResponse { sender, balances })
+}
+
+fn query_list_user_balances(deps: Deps, channel_id: String) -> StdResult<ListUserBalancesResponse> {
+    let mut users: Vec<UserBalanceResponse> = vec![];
+    for item in USER_BALANCES.sub_prefix(channel_id.as_str()).range(
+        deps.storage,
+        None,
+        None,
+        Order::Ascending,
+    ) {
+        let ((sender, denom), amount) = item?;
+        let balance = Amount::from_parts(denom, amount);
+        match users.last_mut() {
+            Some(user) if user.sender == sender => user.balances.push(balance),
+            _ => users.push(UserBalanceResponse {
+                sender,
+                balances: vec![balance],
+            }),
+        }
+    }
+
+    Ok(ListUserBalancesResponse { users })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::state::increase_user_balance;
     use crate::test_helpers::*;
+    use cw2::get_contract_version;
 
-    use cosmwasm_std::testing::{mock_env, mock_info};
-    use cosmwasm_std::{coin, coins, from_binary, CosmosMsg, IbcMsg, StdError, Uint128};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, CosmosMsg, IbcMsg, Storage};
 
+    use cw_controllers::{AdminError, AdminResponse};
     use cw_utils::PaymentError;
 
     #[test]
@@ -223,6 +960,200 @@ mod test {
         );
     }
 
+    #[test]
+    fn update_config_and_admin() {
+        let mut deps = setup(&["channel-3"]);
+
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let cfg: ConfigResponse = from_binary(&raw).unwrap();
+        let expected = ConfigResponse {
+            default_timeout: DEFAULT_TIMEOUT,
+            lockup_id: 1,
+            gov_contract: GOV_CONTRACT.to_string(),
+            restrict_pools: false,
+        };
+        assert_eq!(cfg, expected);
+
+        // only the admin can update the config
+        let msg = ExecuteMsg::UpdateConfig {
+            default_timeout: None,
+            lockup_id: Some(7),
+            restrict_pools: None,
+        };
+        let info = mock_info("anyone", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        let info = mock_info(GOV_CONTRACT, &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let cfg: ConfigResponse = from_binary(&raw).unwrap();
+        assert_eq!(cfg.default_timeout, DEFAULT_TIMEOUT);
+        assert_eq!(cfg.lockup_id, 7);
+
+        // hand over the admin role
+        let msg = ExecuteMsg::UpdateAdmin {
+            admin: "new-gov".to_string(),
+        };
+        let info = mock_info(GOV_CONTRACT, &[]);
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::Admin {}).unwrap();
+        let admin: AdminResponse = from_binary(&raw).unwrap();
+        assert_eq!(admin.admin, Some("new-gov".to_string()));
+    }
+
+    #[test]
+    fn instantiate_default_admin() {
+        let mut deps = mock_dependencies();
+        let msg = InitMsg {
+            default_timeout: DEFAULT_TIMEOUT,
+            lockup_id: 1,
+            gov_contract: None,
+            external_tokens: vec![],
+        };
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let cfg: ConfigResponse = from_binary(&raw).unwrap();
+        assert_eq!(cfg.gov_contract, "creator");
+    }
+
+    #[test]
+    fn migrate_contract() {
+        let mut deps = setup(&["channel-3"]);
+
+        // deployments before 0.2.0 have no admin
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.1.0").unwrap();
+        ADMIN.set(deps.as_mut(), None).unwrap();
+        let msg = MigrateMsg { gov_contract: None };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, ContractError::MissingGovContract {});
+
+        let msg = MigrateMsg {
+            gov_contract: Some("new-gov".to_string()),
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.version, CONTRACT_VERSION);
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::Admin {}).unwrap();
+        let admin: AdminResponse = from_binary(&raw).unwrap();
+        assert_eq!(admin.admin, Some("new-gov".to_string()));
+
+        // the 0.1.0 layout is still read, the new fields take their defaults
+        let mut deps = setup(&[]);
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.1.0").unwrap();
+        ADMIN.set(deps.as_mut(), None).unwrap();
+        let config = br#"{"default_timeout":300,"lockup_id":1}"#;
+        deps.storage.set(b"ics20_config", config);
+        let channel = br#"{"id":"channel-3","counterparty_endpoint":{"port_id":"transfer","channel_id":"channel-35"},"connection_id":"connection-2"}"#;
+        let key = CHANNEL_INFO.key("channel-3");
+        deps.storage.set(&key, channel);
+        let msg = MigrateMsg {
+            gov_contract: Some(GOV_CONTRACT.to_string()),
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert!(!cfg.restrict_pools);
+        let state = query_channel(deps.as_ref(), "channel-3".to_string()).unwrap();
+        assert_eq!(state.info.status, ChannelStatus::Open);
+
+        // cannot downgrade
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let msg = MigrateMsg { gov_contract: None };
+        let err = migrate(deps.as_mut(), mock_env(), msg.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrateVersion {
+                previous_version: "99.0.0".to_string()
+            }
+        );
+
+        set_contract_version(deps.as_mut().storage, "crates.io:cw20-ics20-2", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate {
+                previous_contract: "crates.io:cw20-ics20-2".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn query_user_balances() {
+        let mut deps = setup(&["channel-3", "channel-7"]);
+
+        let balances = [
+            ("channel-3", "sender-1", "uatom", 100u128),
+            ("channel-3", "sender-1", "uosmo", 200u128),
+            ("channel-3", "sender-2", "uosmo", 300u128),
+            ("channel-7", "sender-1", "uosmo", 400u128),
+        ];
+        for (channel, sender, denom, amount) in balances {
+            USER_BALANCES
+                .save(
+                    deps.as_mut().storage,
+                    (channel, sender, denom),
+                    &Uint128::new(amount),
+                )
+                .unwrap();
+        }
+
+        let raw = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::UserBalance {
+                channel: "channel-3".to_string(),
+                sender: "sender-1".to_string(),
+            },
+        )
+        .unwrap();
+        let res: UserBalanceResponse = from_binary(&raw).unwrap();
+        assert_eq!(
+            res.balances,
+            vec![Amount::native(100, "uatom"), Amount::native(200, "uosmo")]
+        );
+
+        let raw = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::UserBalance {
+                channel: "channel-7".to_string(),
+                sender: "sender-2".to_string(),
+            },
+        )
+        .unwrap();
+        let res: UserBalanceResponse = from_binary(&raw).unwrap();
+        assert!(res.balances.is_empty());
+
+        let raw = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::ListUserBalances {
+                channel: "channel-3".to_string(),
+            },
+        )
+        .unwrap();
+        let res: ListUserBalancesResponse = from_binary(&raw).unwrap();
+        assert_eq!(
+            res.users,
+            vec![
+                UserBalanceResponse {
+                    sender: "sender-1".to_string(),
+                    balances: vec![Amount::native(100, "uatom"), Amount::native(200, "uosmo")],
+                },
+                UserBalanceResponse {
+                    sender: "sender-2".to_string(),
+                    balances: vec![Amount::native(300, "uosmo")],
+                },
+            ]
+        );
+    }
+
     #[test]
     fn proper_checks_on_execute_native() {
         let send_channel = "channel-5";
@@ -232,6 +1163,7 @@ mod test {
             channel: send_channel.to_string(),
             remote_address: "foreign-address".to_string(),
             timeout: None,
+            callback: None,
         };
 
         // works with proper funds
@@ -322,8 +1254,8 @@ mod test {
         )
         .unwrap();
         let lockup: LockupResponse = from_binary(&raw_lockup).unwrap();
-        assert_eq!(false, lockup.owner.is_empty());
-        assert_eq!(true, lockup.address.is_empty());
+        assert!(!lockup.owner.is_empty());
+        assert!(lockup.address.is_empty());
 
         // Query valid lockup
         let raw_lockup = query(
@@ -336,8 +1268,8 @@ mod test {
         )
         .unwrap();
         let lockup: LockupResponse = from_binary(&raw_lockup).unwrap();
-        assert_eq!(false, lockup.owner.is_empty());
-        assert_eq!(false, lockup.address.is_empty());
+        assert!(!lockup.owner.is_empty());
+        assert!(!lockup.address.is_empty());
         assert_eq!(lockup_contract, lockup.address);
 
         let raw_list = query(
@@ -351,4 +1283,215 @@ mod test {
         let list_res: ListLockupResponse = from_binary(&raw_list).unwrap();
         assert_eq!(1, list_res.lockups.len());
     }
+
+    #[test]
+    fn migrate_lockup() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&[send_channel]);
+        let sender = "remote-sender";
+        LOCKUP
+            .save(
+                deps.as_mut().storage,
+                (send_channel, sender),
+                &"lockup-addr".to_string(),
+            )
+            .unwrap();
+        let msg = ExecuteMsg::UpdateConfig {
+            default_timeout: None,
+            lockup_id: Some(7),
+            restrict_pools: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(GOV_CONTRACT, &[]), msg).unwrap();
+
+        // only the admin migrates the lockups
+        let migrate = |owner: &str| ExecuteMsg::MigrateLockup {
+            channel: send_channel.to_string(),
+            owner: owner.to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            migrate(sender),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), migrate("unknown")).unwrap_err();
+        assert_eq!(err, ContractError::LockupNotFound {});
+
+        // the lockup moves to the lockup code ID of the config
+        let res = execute(deps.as_mut(), mock_env(), info, migrate(sender)).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Migrate {
+                contract_addr: "lockup-addr".to_string(),
+                new_code_id: 7,
+                msg: to_binary(&LockupMigrateMsg {}).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn external_tokens_and_reserves() {
+        let channel = "channel-9";
+        let mut deps = setup(&["channel-3", channel]);
+        let local_denom = "ibc/46B44899322F3CD854D2D46DEEF881958467CDD4B3B10086DA49296BBED94BED";
+
+        // only the admin can add external tokens, on a known channel
+        let add = |channel: &str| {
+            ExecuteMsg::AddExternalToken(ExternalTokenMsg {
+                channel: channel.to_string(),
+                denom: "ujuno".to_string(),
+                local_denom: local_denom.to_string(),
+            })
+        };
+        let info = mock_info("anyone", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, add(channel)).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), add("channel-5")).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NoSuchChannel {
+                id: "channel-5".to_string()
+            }
+        );
+        execute(deps.as_mut(), mock_env(), info, add(channel)).unwrap();
+
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListExternalTokens {}).unwrap();
+        let res: ListExternalTokensResponse = from_binary(&raw).unwrap();
+        assert_eq!(
+            res.tokens,
+            vec![ExternalTokenMsg {
+                channel: channel.to_string(),
+                denom: "ujuno".to_string(),
+                local_denom: local_denom.to_string(),
+            }]
+        );
+
+        // the reserve is only accepted on the channel of the token
+        let reserve = |channel: &str| {
+            ExecuteMsg::AddReserve(ReserveMsg {
+                channel: channel.to_string(),
+            })
+        };
+        let info = mock_info("provider", &coins(1000, local_denom));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            reserve("channel-3"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoAllowedToken {});
+        execute(deps.as_mut(), mock_env(), info, reserve(channel)).unwrap();
+        let info = mock_info("other-provider", &coins(300, local_denom));
+        execute(deps.as_mut(), mock_env(), info, reserve(channel)).unwrap();
+
+        let query_reserve = |deps: Deps, provider: &str| -> Uint128 {
+            let msg = QueryMsg::Reserve {
+                channel: channel.to_string(),
+                denom: local_denom.to_string(),
+                provider: provider.to_string(),
+            };
+            let res: ReserveResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            res.amount.amount()
+        };
+        assert_eq!(query_reserve(deps.as_ref(), "provider"), Uint128::new(1000));
+        assert_eq!(query_reserve(deps.as_ref(), "nobody"), Uint128::zero());
+
+        // providers withdraw up to what they added
+        let withdraw = |amount: u128| ExecuteMsg::WithdrawReserve {
+            channel: channel.to_string(),
+            denom: local_denom.to_string(),
+            amount: Uint128::new(amount),
+        };
+        let info = mock_info("other-provider", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), withdraw(301)).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve {});
+        let res = execute(deps.as_mut(), mock_env(), info, withdraw(100)).unwrap();
+        let expected = BankMsg::Send {
+            to_address: "other-provider".to_string(),
+            amount: coins(100, local_denom),
+        };
+        assert_eq!(res.messages[0].msg, expected.into());
+        assert_eq!(
+            query_reserve(deps.as_ref(), "other-provider"),
+            Uint128::new(200)
+        );
+
+        // the payouts are taken from all the providers in proportion of their shares
+        reduce_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            local_denom,
+            Uint128::new(600),
+        )
+        .unwrap();
+        assert_eq!(query_reserve(deps.as_ref(), "provider"), Uint128::new(500));
+        assert_eq!(
+            query_reserve(deps.as_ref(), "other-provider"),
+            Uint128::new(100)
+        );
+        let info = mock_info("provider", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), withdraw(501)).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientReserve {});
+
+        // the funds held for the remote senders are not part of the reserve
+        increase_user_balance(
+            deps.as_mut().storage,
+            channel,
+            "remote-sender",
+            local_denom,
+            Uint128::new(400),
+        )
+        .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            local_denom,
+            Uint128::new(400),
+        )
+        .unwrap();
+        assert_eq!(query_reserve(deps.as_ref(), "provider"), Uint128::new(500));
+        execute(deps.as_mut(), mock_env(), info, withdraw(500)).unwrap();
+        assert_eq!(query_reserve(deps.as_ref(), "provider"), Uint128::zero());
+
+        // the tokens sent back restore the reserve, the new providers get the shares of its value
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            local_denom,
+            Uint128::new(100),
+        )
+        .unwrap();
+        assert_eq!(
+            query_reserve(deps.as_ref(), "other-provider"),
+            Uint128::new(200)
+        );
+        let info = mock_info("provider", &coins(200, local_denom));
+        execute(deps.as_mut(), mock_env(), info, reserve(channel)).unwrap();
+        assert_eq!(query_reserve(deps.as_ref(), "provider"), Uint128::new(200));
+        let info = mock_info("other-provider", &[]);
+        execute(deps.as_mut(), mock_env(), info, withdraw(200)).unwrap();
+        let state = query_channel(deps.as_ref(), channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(600, local_denom)]);
+
+        // removed tokens are no longer accepted
+        let remove = ExecuteMsg::RemoveExternalToken {
+            channel: channel.to_string(),
+            denom: "ujuno".to_string(),
+        };
+        let info = mock_info("anyone", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, remove.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+        let info = mock_info(GOV_CONTRACT, &[]);
+        execute(deps.as_mut(), mock_env(), info.clone(), remove.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info, remove).unwrap_err();
+        assert_eq!(err, ContractError::NoAllowedToken {});
+        let info = mock_info("provider", &coins(1000, local_denom));
+        let err = execute(deps.as_mut(), mock_env(), info, reserve(channel)).unwrap_err();
+        assert_eq!(err, ContractError::NoAllowedToken {});
+    }
 }
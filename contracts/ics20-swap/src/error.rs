@@ -2,7 +2,9 @@ use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
-use cosmwasm_std::StdError;
+use cosmwasm_std::{OverflowError, StdError, Uint128};
+use cw_controllers::AdminError;
+use cw_migrate::MigrateError;
 use cw_utils::{ParseReplyError, PaymentError};
 
 /// Never is a placeholder to ensure we don't return any errors
@@ -17,12 +19,43 @@ pub enum ContractError {
     #[error("{0}")]
     Payment(#[from] PaymentError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    Admin(#[from] AdminError),
+
     #[error("{0}")]
     ParseReply(#[from] ParseReplyError),
 
     #[error("Channel doesn't exist: {id}")]
     NoSuchChannel { id: String },
 
+    #[error("Channel is frozen: {id}")]
+    ChannelFrozen { id: String },
+
+    #[error("Channel is closed: {id}")]
+    ChannelClosed { id: String },
+
+    #[error("Pool {pool_id} is not allowed")]
+    PoolNotAllowed { pool_id: u64 },
+
+    #[error("Pools cannot be created while they are restricted")]
+    PoolCreationNotAllowed {},
+
+    #[error("Denom {denom} is not allowed on this channel")]
+    DenomNotAllowed { denom: String },
+
+    #[error("Rate limit period must be greater than zero")]
+    InvalidRateLimitPeriod {},
+
+    #[error("Rate limit exceeded on channel {channel} for {denom}, {remaining} remaining")]
+    RateLimitExceeded {
+        channel: String,
+        denom: String,
+        remaining: Uint128,
+    },
+
     #[error("Didn't send any funds")]
     NoFunds {},
 
@@ -44,6 +77,9 @@ pub enum ContractError {
     #[error("Only accepts tokens that originate on this chain, not native tokens of remote chain")]
     NoForeignTokens {},
 
+    #[error("Only accepts external tokens that have been explicitly allowed")]
+    NoAllowedToken {},
+
     #[error("Parsed port from denom ({port}) doesn't match packet")]
     FromOtherPort { port: String },
 
@@ -59,6 +95,9 @@ pub enum ContractError {
     #[error("Cannot migrate from unsupported version: {previous_version}")]
     CannotMigrateVersion { previous_version: String },
 
+    #[error("Migration must set the gov contract")]
+    MissingGovContract {},
+
     #[error("Got a submessage reply with unknown id: {id}")]
     UnknownReplyId { id: u64 },
 
@@ -71,20 +110,153 @@ pub enum ContractError {
     #[error("x/gamm result not found")]
     GammResultNotFound {},
 
+    #[error("x/gamm result {value} doesn't match the expected denom {denom}")]
+    GammResultMismatch { denom: String, value: String },
+
     #[error("Invalid denom for LP token")]
     InvalidLpToken {},
 
     #[error("Invalid amount value")]
     InvalidAmountValue {},
 
+    #[error("Invalid denom: {denom}")]
+    InvalidDenom { denom: String },
+
+    #[error("Sent packet sequence not found")]
+    PacketSequenceNotFound {},
+
+    #[error("Invalid packet memo: {error}")]
+    InvalidMemo { error: String },
+
     #[error("Only support one lockup account by channel")]
     OnlyLockupByChannel {},
 
     #[error("No found lockup account")]
     LockupNotFound {},
 
+    #[error("Lockup account {version} only claims one denom at a time")]
+    LockupClaimUnsupported { version: String },
+
     #[error("This message does no accept funds")]
     NonPayable {},
+
+    #[error("Max input amount exceeds the received amount")]
+    InvalidMaxAmountIn {},
+
+    #[error("Insufficient funds held for the sender")]
+    InsufficientHeldFunds {},
+
+    #[error("Insufficient reserve added by the sender")]
+    InsufficientReserve {},
+
+    #[error("Pool needs at least two assets and fees lower than 1")]
+    InvalidPoolParams {},
+
+    #[error("Max slippage must be between 0 and 1, and set with the reference price")]
+    InvalidSlippage {},
+
+    #[error("Min output amount or max slippage required")]
+    MissingMinAmount {},
+
+    #[error("Cannot derive the min output amount from the pool")]
+    MinOutputUnavailable {},
+
+    #[error("Pipeline has no steps")]
+    EmptyPipeline {},
+
+    #[error("Action not supported at pipeline step {index}")]
+    InvalidPipelineStep { index: usize },
+
+    #[error("Output amount {amount} is lower than the min amount {min}")]
+    MinAmountNotReached { amount: Uint128, min: Uint128 },
+
+    #[error("Pipeline step {index} failed: {source}")]
+    PipelineStepFailed {
+        index: usize,
+        source: Box<ContractError>,
+    },
+}
+
+/// Stable codes sent in error acks. Never renumber them, only add new ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    Internal = 1,
+    InvalidPacket = 2,
+    NoSuchChannel = 3,
+    InsufficientFunds = 4,
+    InsufficientHeldFunds = 5,
+    LockupNotFound = 6,
+    LockupExists = 7,
+    SlippageExceeded = 8,
+    PoolUnavailable = 9,
+    ActionFailed = 10,
+    ChannelNotOpen = 11,
+    RateLimitExceeded = 12,
+    NotAllowed = 13,
+}
+
+impl ErrorCode {
+    /// Classifies the error returned by a failed submessage. The chain redacts it to
+    /// `codespace: <module>, code: <code>`, so only the module errors can be told apart, the
+    /// errors of the contracts called are all `codespace: wasm, code: 5`.
+    pub fn from_submsg_error(err: &str) -> Self {
+        match parse_redacted_error(err) {
+            // x/gamm ErrLimitMaxAmount, ErrLimitMinAmount
+            Some(("gamm", 6)) | Some(("gamm", 7)) => ErrorCode::SlippageExceeded,
+            // x/gamm ErrPoolNotFound, ErrPoolLocked
+            Some(("gamm", 1)) | Some(("gamm", 3)) => ErrorCode::PoolUnavailable,
+            // sdk ErrInsufficientFunds
+            Some(("sdk", 5)) => ErrorCode::InsufficientFunds,
+            _ => ErrorCode::ActionFailed,
+        }
+    }
+}
+
+// codespace and code of a redacted error
+fn parse_redacted_error(err: &str) -> Option<(&str, u32)> {
+    let (codespace, code) = err.strip_prefix("codespace: ")?.split_once(", code: ")?;
+    Some((codespace, code.parse().ok()?))
+}
+
+impl ContractError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ContractError::NoFunds {}
+            | ContractError::AmountOverflow {}
+            | ContractError::NoForeignTokens {}
+            | ContractError::NoAllowedToken {}
+            | ContractError::FromOtherPort { .. }
+            | ContractError::FromOtherChannel { .. }
+            | ContractError::InvalidLpToken {}
+            | ContractError::InvalidAmountValue {}
+            | ContractError::NonPayable {}
+            | ContractError::InvalidMaxAmountIn {}
+            | ContractError::InvalidSlippage {}
+            | ContractError::MissingMinAmount {}
+            | ContractError::InvalidMemo { .. }
+            | ContractError::EmptyPipeline {}
+            | ContractError::InvalidPipelineStep { .. }
+            | ContractError::LockupClaimUnsupported { .. } => ErrorCode::InvalidPacket,
+            ContractError::NoSuchChannel { .. } => ErrorCode::NoSuchChannel,
+            ContractError::ChannelFrozen { .. } | ContractError::ChannelClosed { .. } => {
+                ErrorCode::ChannelNotOpen
+            }
+            ContractError::RateLimitExceeded { .. } => ErrorCode::RateLimitExceeded,
+            ContractError::PoolNotAllowed { .. }
+            | ContractError::PoolCreationNotAllowed {}
+            | ContractError::DenomNotAllowed { .. } => ErrorCode::NotAllowed,
+            ContractError::InsufficientFunds {} => ErrorCode::InsufficientFunds,
+            ContractError::InsufficientHeldFunds {} => ErrorCode::InsufficientHeldFunds,
+            ContractError::LockupNotFound {} => ErrorCode::LockupNotFound,
+            ContractError::OnlyLockupByChannel {} => ErrorCode::LockupExists,
+            ContractError::GammResultNotFound {} | ContractError::MinOutputUnavailable {} => {
+                ErrorCode::PoolUnavailable
+            }
+            ContractError::MinAmountNotReached { .. } => ErrorCode::SlippageExceeded,
+            ContractError::PipelineStepFailed { source, .. } => source.code(),
+            _ => ErrorCode::Internal,
+        }
+    }
 }
 
 impl From<FromUtf8Error> for ContractError {
@@ -98,3 +270,17 @@ impl From<TryFromIntError> for ContractError {
         ContractError::AmountOverflow {}
     }
 }
+
+impl From<MigrateError> for ContractError {
+    fn from(err: MigrateError) -> Self {
+        match err {
+            MigrateError::Std(err) => ContractError::Std(err),
+            MigrateError::CannotMigrate { previous_contract } => {
+                ContractError::CannotMigrate { previous_contract }
+            }
+            MigrateError::CannotMigrateVersion { previous_version } => {
+                ContractError::CannotMigrateVersion { previous_version }
+            }
+        }
+    }
+}
@@ -1,24 +1,43 @@
 use cosmwasm_std::{
-    attr, coins, entry_point, from_binary, to_binary, BankMsg, Binary, Coin, CosmosMsg, DepsMut,
-    Env, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg,
-    IbcEndpoint, IbcOrder, IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg,
-    IbcReceiveResponse, Reply, Response, SubMsg, SubMsgResult, WasmMsg,
+    attr, entry_point, from_binary, from_slice, to_binary, to_vec, Addr, BankMsg, Binary, Coin,
+    CosmosMsg, Decimal, Deps, DepsMut, Env, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
+    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcMsg, IbcOrder, IbcPacket,
+    IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo,
+    Order, Reply, ReplyOn, Response, StdError, StdResult, Storage, SubMsg, SubMsgResponse,
+    SubMsgResult, Timestamp, Uint128, WasmMsg,
 };
 
 use crate::amount::Amount;
-use crate::error::{ContractError, Never};
+use crate::error::{ContractError, ErrorCode, Never};
 use crate::ibc_msg::{
-    AmountResultAck, ClaimPacket, ExitPoolPacket, Ics20Ack, Ics20Packet, JoinPoolPacket,
-    LockPacket, LockupAck, OsmoPacket, SwapPacket, UnlockPacket, Voucher,
+    AmountResultAck, CreateBalancerPoolPacket, CreatePoolResultAck, ErrorAck, ExitPoolPacket,
+    ForwardMemo, Ics20Ack, Ics20Packet, JoinPoolPacket, LockPacket, LockupAck, MultiExitPoolPacket,
+    MultiJoinPoolPacket, OsmoPacket, PacketMemo, StepResultAck, SwapExactOutPacket, SwapHeldPacket,
+    SwapPacket, UnlockPacket, Voucher, WithdrawPacket, ERROR_ACK_VERSION,
+};
+use crate::msg::{
+    ActionAckMsg, ActionAckResult, CallbackMsg, CreatePoolMsg, ExecuteMsg, IbcTransferCallback,
+    LegacyLockupExecuteMsg, LockupExecuteMsg, LockupInitMsg, PipelineMsg,
 };
-use crate::msg::{LockupExecuteMsg, LockupInitMsg};
 use crate::parse::{
-    parse_gamm_result, parse_pool_id, GammResult, EXIT_POOL_ATTR, EXIT_POOL_EVENT, JOIN_POOL_ATTR,
-    JOIN_POOL_EVENT, SWAP_ATTR, SWAP_EVENT,
+    parse_create_pool_id, parse_event_coins, parse_gamm_result, parse_packet_sequence,
+    parse_pool_id, parse_spent_coins, parse_swap_exact_out_result, pool_share_denom, GammResult,
+    BURN_ATTR, BURN_EVENT, EXIT_POOL_ATTR, EXIT_POOL_EVENT, JOIN_POOL_ATTR, JOIN_POOL_EVENT,
+    POOL_EXITED_ATTR, POOL_EXITED_EVENT, POOL_JOINED_ATTR, POOL_JOINED_EVENT, SWAP_ATTR,
+    SWAP_EVENT,
+};
+use crate::slippage::{
+    check_min_amount, estimate_exit_amount, estimate_join_shares, estimate_swap,
+    estimate_swap_at_spot, min_amount,
 };
 use crate::state::{
-    increase_channel_balance, reduce_channel_balance, restore_balance_reply, ChannelInfo,
-    ReplyArgs, CHANNEL_INFO, CONFIG, LOCKUP, REPLY_ARGS,
+    finish_transfer, increase_channel_balance, increase_user_balance, load_open_channel,
+    load_transfer, record_flow, reduce_channel_balance, reduce_user_balance, restore_balance_reply,
+    restore_held_funds, save_transfer, take_reply_args, undo_flow, undo_flow_at,
+    undo_reduce_channel_balance, ChannelInfo, ChannelStatus, Flow, HeldBy, PendingPacket,
+    PipelineState, ReplyArgs, TransferInfo, TransferStatus, ACK_CALLBACKS, ALLOWED_DENOMS,
+    ALLOWED_POOLS, CHANNEL_INFO, CONFIG, CREATE_POOL_FUNDS, EXTERNAL_DENOMS, EXTERNAL_TOKENS,
+    LOCKUP, PENDING_PACKETS, PIPELINE, REPLY_ARGS, REPLY_SEQ, USER_BALANCES,
 };
 use cw_osmo_proto::osmosis::gamm::v1beta1::{
     MsgExitSwapShareAmountInResponse as ExitResponse,
@@ -43,86 +62,485 @@ fn ack_success() -> Binary {
     to_binary(&res).unwrap()
 }
 
-// create a serialized error message
-fn ack_fail(err: String) -> Binary {
-    let res = Ics20Ack::Error(err);
+// create a serialized error message for a failed submessage
+fn ack_fail(action: &str, err: String) -> Binary {
+    let code = ErrorCode::from_submsg_error(&err);
+    ack_fail_with_code(action, code, err, None)
+}
+
+// create a serialized error message for a contract error
+fn ack_error(action: &str, err: &ContractError) -> Binary {
+    let step = match err {
+        ContractError::PipelineStepFailed { index, .. }
+        | ContractError::InvalidPipelineStep { index } => Some(*index as u32),
+        _ => None,
+    };
+    ack_fail_with_code(action, err.code(), err.to_string(), step)
+}
+
+// the error ack is JSON encoded in the ics20 error, so relayers still see a failure
+fn ack_fail_with_code(action: &str, code: ErrorCode, details: String, step: Option<u32>) -> Binary {
+    let error = ErrorAck {
+        version: ERROR_ACK_VERSION,
+        code: code as u32,
+        action: action.to_string(),
+        details,
+        step,
+    };
+    let error = String::from_utf8(to_vec(&error).unwrap()).unwrap();
+    let res = Ics20Ack::Error(error);
     to_binary(&res).unwrap()
 }
 
 const RECEIVE_ID: u64 = 1337;
 const SWAP_ID: u64 = 0xcb37;
+const SWAP_EXACT_OUT_ID: u64 = 0xcb38;
+const SWAP_HELD_ID: u64 = 0xcb39;
 const JOIN_POOL_ID: u64 = 0xad54;
 const EXIT_POOL_ID: u64 = 0xfa61;
+const MULTI_JOIN_POOL_ID: u64 = 0xad55;
+const MULTI_EXIT_POOL_ID: u64 = 0xfa62;
+const CREATE_POOL_ID: u64 = 0xc4ea;
+const CREATE_POOL_CHECK_ID: u64 = 0xc4eb;
 const ACK_FAILURE_ID: u64 = 0xfa17;
 const LOCKUP_ID: u64 = 0xdf16;
 const LOCK_TOKEN_ID: u64 = 0xbc42;
 const CLAIM_TOKEN_ID: u64 = 0x1654;
+const CLAIM_ALL_ID: u64 = 0x1655;
 const UNLOCK_TOKEN_ID: u64 = 0x6f11;
+const EXTEND_LOCK_ID: u64 = 0x6f12;
+const UNLOCK_ALL_ID: u64 = 0x6f13;
+const PIPELINE_ID: u64 = 0x7a11;
+const PIPELINE_STEP_ID: u64 = 0x7a12;
+const SEND_PACKET_ID: u64 = 0x5e4d;
+const CALLBACK_ID: u64 = 0xca11;
+
+// Gas available to the callback contract on ack or timeout
+const CALLBACK_GAS_LIMIT: u64 = 500_000;
+
+// Reply ids keep the handler in the low bits, the high bits are a sequence so that every
+// submessage has its own reply context
+const REPLY_KIND_BITS: u32 = 16;
+
+fn reply_kind(id: u64) -> u64 {
+    id & ((1 << REPLY_KIND_BITS) - 1)
+}
+
+// action reported in the error ack of a failed reply
+fn reply_action(id: u64) -> &'static str {
+    match reply_kind(id) {
+        SWAP_ID => "swap",
+        SWAP_EXACT_OUT_ID => "swap_exact_out",
+        SWAP_HELD_ID => "swap_held",
+        JOIN_POOL_ID => "join_pool",
+        EXIT_POOL_ID => "exit_pool",
+        MULTI_JOIN_POOL_ID => "multi_join_pool",
+        MULTI_EXIT_POOL_ID => "multi_exit_pool",
+        CREATE_POOL_ID | CREATE_POOL_CHECK_ID => "create_balancer_pool",
+        LOCKUP_ID => "lockup_account",
+        LOCK_TOKEN_ID => "lock",
+        CLAIM_TOKEN_ID => "claim",
+        CLAIM_ALL_ID => "claim_all",
+        UNLOCK_TOKEN_ID => "unlock",
+        EXTEND_LOCK_ID => "extend_lock",
+        UNLOCK_ALL_ID => "unlock_all",
+        PIPELINE_ID | PIPELINE_STEP_ID => "pipeline",
+        ACK_FAILURE_ID => "refund",
+        CALLBACK_ID => "callback",
+        _ => "transfer",
+    }
+}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, ContractError> {
-    match reply.id {
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    match reply_kind(reply.id) {
         RECEIVE_ID => reply_receive(deps, reply),
-        SWAP_ID => reply_gamm_result::<SwapResponse>(deps, reply, SWAP_EVENT, SWAP_ATTR),
+        SWAP_ID => reply_gamm_result::<SwapResponse>(deps, env, reply, SWAP_EVENT, SWAP_ATTR),
+        SWAP_EXACT_OUT_ID => reply_swap_exact_out(deps, reply),
+        SWAP_HELD_ID => reply_swap_held(deps, env, reply),
         JOIN_POOL_ID => {
-            reply_gamm_result::<JoinResponse>(deps, reply, JOIN_POOL_EVENT, JOIN_POOL_ATTR)
+            reply_gamm_result::<JoinResponse>(deps, env, reply, JOIN_POOL_EVENT, JOIN_POOL_ATTR)
         }
         EXIT_POOL_ID => {
-            reply_gamm_result::<ExitResponse>(deps, reply, EXIT_POOL_EVENT, EXIT_POOL_ATTR)
+            reply_gamm_result::<ExitResponse>(deps, env, reply, EXIT_POOL_EVENT, EXIT_POOL_ATTR)
         }
+        MULTI_JOIN_POOL_ID => reply_held_result(
+            deps,
+            reply,
+            (POOL_JOINED_EVENT, POOL_JOINED_ATTR),
+            (JOIN_POOL_EVENT, JOIN_POOL_ATTR),
+        ),
+        MULTI_EXIT_POOL_ID => reply_held_result(
+            deps,
+            reply,
+            (BURN_EVENT, BURN_ATTR),
+            (POOL_EXITED_EVENT, POOL_EXITED_ATTR),
+        ),
+        CREATE_POOL_ID => reply_create_pool(deps, env, reply),
+        CREATE_POOL_CHECK_ID => reply_create_pool_check(deps, env, reply),
         LOCKUP_ID => reply_lockup_account(deps, reply),
         LOCK_TOKEN_ID => reply_ack_from_data(deps, reply),
-        CLAIM_TOKEN_ID => reply_claim_result(deps, reply),
+        CLAIM_TOKEN_ID | CLAIM_ALL_ID => reply_claim_result(deps, env, reply),
         UNLOCK_TOKEN_ID => reply_ack_on_error(reply),
+        EXTEND_LOCK_ID | UNLOCK_ALL_ID => reply_ack_from_data(deps, reply),
+        PIPELINE_ID => reply_pipeline(deps, env, reply),
+        PIPELINE_STEP_ID => reply_pipeline_step(deps, env, reply),
         ACK_FAILURE_ID => reply_ack_on_error(reply),
+        SEND_PACKET_ID => reply_send_packet(deps, env, reply),
+        CALLBACK_ID => reply_callback(reply),
         _ => Err(ContractError::UnknownReplyId { id: reply.id }),
     }
 }
 
 pub fn reply_gamm_result<M: GammResult + cw_osmo_proto::Message + std::default::Default>(
     deps: DepsMut,
+    env: Env,
     reply: Reply,
     event: &str,
     attribute: &str,
 ) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
     match reply.result {
         SubMsgResult::Ok(tx) => {
-            let gamm_res = parse_gamm_result::<M>(tx, event, attribute);
+            let gamm_res = denom_out(&reply_args)
+                .and_then(|denom| parse_gamm_result::<M>(tx, &denom, Some((event, attribute))));
             match gamm_res {
                 Ok(ack) => {
-                    let reply_args = REPLY_ARGS.load(deps.storage)?;
                     // increase gamm amount out
-                    increase_channel_balance(
-                        deps.storage,
-                        &reply_args.channel,
-                        &ack.denom,
-                        ack.amount,
-                    )?;
+                    let return_msgs =
+                        credit_amount_out(deps.storage, &env, &reply_args, &ack.denom, ack.amount)?;
                     let data = to_binary(&ack).unwrap();
-                    Ok(Response::new().set_data(ack_success_with_body(data)))
+                    Ok(Response::new()
+                        .set_data(ack_success_with_body(data))
+                        .add_submessages(return_msgs))
                 }
                 Err(err) => {
-                    restore_balance_reply(deps.storage)?;
-                    Ok(Response::new().set_data(ack_fail(err.to_string())))
+                    restore_balance_reply(deps.storage, &reply_args)?;
+                    Ok(Response::new().set_data(ack_error(action, &err)))
                 }
             }
         }
         SubMsgResult::Err(err) => {
-            restore_balance_reply(deps.storage)?;
-            Ok(Response::new().set_data(ack_fail(err)))
+            restore_balance_reply(deps.storage, &reply_args)?;
+            Ok(Response::new().set_data(ack_fail(action, err)))
+        }
+    }
+}
+
+// output denom of the x/gamm action, taken from the request
+fn denom_out(reply_args: &ReplyArgs) -> Result<String, ContractError> {
+    reply_args
+        .action
+        .as_ref()
+        .and_then(|action| action.token_out_denom())
+        .ok_or(ContractError::GammResultNotFound {})
+}
+
+// the exact output requested by the swap
+fn swap_exact_out_token(reply_args: &ReplyArgs) -> Result<Coin, ContractError> {
+    match &reply_args.action {
+        Some(OsmoPacket::SwapExactOut(swap)) => Ok(Coin {
+            denom: swap.token_out_denom.clone(),
+            amount: swap.token_out_amount,
+        }),
+        _ => Err(ContractError::GammResultNotFound {}),
+    }
+}
+
+pub fn reply_swap_exact_out(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
+    match reply.result {
+        SubMsgResult::Ok(tx) => match swap_exact_out_token(&reply_args)
+            .and_then(|token_out| parse_swap_exact_out_result(tx, token_out))
+        {
+            Ok((token_out, token_in_amount)) => {
+                // increase gamm amount out
+                increase_channel_balance(
+                    deps.storage,
+                    &reply_args.channel,
+                    &token_out.denom,
+                    token_out.amount,
+                )?;
+                increase_user_balance(
+                    deps.storage,
+                    &reply_args.channel,
+                    &reply_args.sender,
+                    &token_out.denom,
+                    token_out.amount,
+                )?;
+
+                // keep the unused input for the sender
+                let spent = vec![Coin {
+                    denom: reply_args.denom.clone(),
+                    amount: token_in_amount,
+                }];
+                let refund = hold_unused_funds(deps.storage, &reply_args, &spent)?
+                    .pop()
+                    .unwrap_or(AmountResultAck {
+                        amount: Uint128::zero(),
+                        denom: reply_args.denom,
+                    });
+
+                let data = to_binary(&vec![token_out, refund]).unwrap();
+                Ok(Response::new().set_data(ack_success_with_body(data)))
+            }
+            Err(err) => {
+                restore_balance_reply(deps.storage, &reply_args)?;
+                Ok(Response::new().set_data(ack_error(action, &err)))
+            }
+        },
+        SubMsgResult::Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            Ok(Response::new().set_data(ack_fail(action, err)))
+        }
+    }
+}
+
+pub fn reply_swap_held(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
+    let tx = match reply.result {
+        SubMsgResult::Ok(tx) => tx,
+        SubMsgResult::Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            return Ok(Response::new().set_data(ack_fail(action, err)));
+        }
+    };
+
+    let token_out = match denom_out(&reply_args).and_then(|denom| {
+        parse_gamm_result::<SwapResponse>(tx, &denom, Some((SWAP_EVENT, SWAP_ATTR)))
+    }) {
+        Ok(token_out) => token_out,
+        Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            return Ok(Response::new().set_data(ack_error(action, &err)));
+        }
+    };
+    let return_msgs = credit_amount_out(
+        deps.storage,
+        &env,
+        &reply_args,
+        &token_out.denom,
+        token_out.amount,
+    )?;
+
+    let mut acks = vec![token_out];
+    acks.extend(hold_unused_funds(
+        deps.storage,
+        &reply_args,
+        &reply_args.spent,
+    )?);
+
+    let data = to_binary(&acks).unwrap();
+    Ok(Response::new()
+        .set_data(ack_success_with_body(data))
+        .add_submessages(return_msgs))
+}
+
+// Keeps the action output and the unused input in the sender held balance.
+// `spent` and `received` are the (event, attribute) listing the coins moved by the action.
+pub fn reply_held_result(
+    deps: DepsMut,
+    reply: Reply,
+    spent: (&str, &str),
+    received: (&str, &str),
+) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
+    let tx = match reply.result {
+        SubMsgResult::Ok(tx) => tx,
+        SubMsgResult::Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            return Ok(Response::new().set_data(ack_fail(action, err)));
+        }
+    };
+
+    let result = parse_event_coins(tx.events.clone(), spent.0, spent.1).and_then(|spent_coins| {
+        let received_coins = parse_event_coins(tx.events, received.0, received.1)?;
+        Ok((spent_coins, received_coins))
+    });
+    let (spent_coins, received_coins) = match result {
+        Ok(coins) => coins,
+        Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            return Ok(Response::new().set_data(ack_error(action, &err)));
+        }
+    };
+    let mut acks = vec![];
+    for coin in received_coins {
+        increase_user_balance(
+            deps.storage,
+            &reply_args.channel,
+            &reply_args.sender,
+            &coin.denom,
+            coin.amount,
+        )?;
+        increase_channel_balance(deps.storage, &reply_args.channel, &coin.denom, coin.amount)?;
+        acks.push(AmountResultAck {
+            amount: coin.amount,
+            denom: coin.denom,
+        });
+    }
+
+    acks.extend(hold_unused_funds(deps.storage, &reply_args, &spent_coins)?);
+
+    let data = to_binary(&acks).unwrap();
+    Ok(Response::new().set_data(ack_success_with_body(data)))
+}
+
+pub fn reply_create_pool(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
+    let tx = match reply.result {
+        SubMsgResult::Ok(tx) => tx,
+        SubMsgResult::Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            return Ok(Response::new().set_data(ack_fail(action, err)));
+        }
+    };
+
+    // the pool creation response is the data of the call to ourselves
+    let mut tx = tx;
+    tx.data = tx
+        .data
+        .and_then(|data| parse_execute_response_data(data.as_slice()).ok())
+        .and_then(|response| response.data);
+    let result = parse_create_pool_id(&tx).and_then(|pool_id| {
+        let shares_denom = pool_share_denom(pool_id);
+        let shares = parse_event_coins(tx.events.clone(), JOIN_POOL_EVENT, JOIN_POOL_ATTR)?
+            .into_iter()
+            .find(|c| c.denom == shares_denom)
+            .ok_or(ContractError::GammResultNotFound {})?;
+        Ok((pool_id, shares))
+    });
+    let (pool_id, shares) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            return Ok(Response::new().set_data(ack_error(action, &err)));
+        }
+    };
+
+    // the sender funds covered the spent coins, checked before the call to ourselves returns
+    let mut spent = vec![];
+    for coin in parse_spent_coins(&tx.events, env.contract.address.as_str())? {
+        add_coin(&mut spent, coin);
+    }
+
+    increase_user_balance(
+        deps.storage,
+        &reply_args.channel,
+        &reply_args.sender,
+        &shares.denom,
+        shares.amount,
+    )?;
+    increase_channel_balance(
+        deps.storage,
+        &reply_args.channel,
+        &shares.denom,
+        shares.amount,
+    )?;
+    hold_unused_funds(deps.storage, &reply_args, &spent)?;
+
+    let ack = CreatePoolResultAck {
+        pool_id: pool_id.into(),
+        shares: AmountResultAck {
+            amount: shares.amount,
+            denom: shares.denom,
+        },
+    };
+    let data = to_binary(&ack).unwrap();
+    Ok(Response::new().set_data(ack_success_with_body(data)))
+}
+
+// x/gamm charges the pool creation fee to the contract, the sender funds must cover all of it.
+// Failing reverts the call to ourselves rather than paying with the funds of other senders.
+fn reply_create_pool_check(
+    deps: DepsMut,
+    env: Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    let funds_in = CREATE_POOL_FUNDS.load(deps.storage, reply.id)?;
+    CREATE_POOL_FUNDS.remove(deps.storage, reply.id);
+    let tx = reply.result.into_result().map_err(StdError::generic_err)?;
+
+    let mut spent = vec![];
+    for coin in parse_spent_coins(&tx.events, env.contract.address.as_str())? {
+        add_coin(&mut spent, coin);
+    }
+    for coin in &spent {
+        let available: Uint128 = funds_in
+            .iter()
+            .filter(|c| c.denom == coin.denom)
+            .map(|c| c.amount)
+            .sum();
+        if coin.amount > available {
+            return Err(ContractError::InsufficientHeldFunds {});
+        }
+    }
+
+    let mut res = Response::new();
+    if let Some(data) = tx.data {
+        res = res.set_data(data);
+    }
+    Ok(res)
+}
+
+// Keeps what the action didn't spend from the received and held funds for the sender
+fn hold_unused_funds(
+    storage: &mut dyn Storage,
+    reply_args: &ReplyArgs,
+    spent: &[Coin],
+) -> Result<Vec<AmountResultAck>, ContractError> {
+    let mut funds_in = reply_args.held.clone();
+    add_coin(
+        &mut funds_in,
+        Coin {
+            denom: reply_args.denom.clone(),
+            amount: reply_args.amount,
+        },
+    );
+
+    let mut acks = vec![];
+    for coin in funds_in {
+        let used: Uint128 = spent
+            .iter()
+            .filter(|c| c.denom == coin.denom)
+            .map(|c| c.amount)
+            .sum();
+        let unused = coin.amount.checked_sub(used)?;
+        if unused.is_zero() {
+            continue;
         }
+
+        increase_user_balance(
+            storage,
+            &reply_args.channel,
+            &reply_args.sender,
+            &coin.denom,
+            unused,
+        )?;
+        undo_reduce_channel_balance(storage, &reply_args.channel, &coin.denom, unused)?;
+        acks.push(AmountResultAck {
+            amount: unused,
+            denom: coin.denom,
+        });
     }
+
+    Ok(acks)
 }
 
 pub fn reply_lockup_account(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
     match reply.result.clone() {
         SubMsgResult::Ok(_) => {
             let res = parse_reply_instantiate_data(reply);
 
             match res {
                 Ok(data) => {
-                    let reply_args = REPLY_ARGS.load(deps.storage)?;
-
                     LOCKUP.save(
                         deps.storage,
                         (&reply_args.channel, &reply_args.sender),
@@ -135,14 +553,151 @@ pub fn reply_lockup_account(deps: DepsMut, reply: Reply) -> Result<Response, Con
 
                     Ok(Response::new().set_data(ack_success_with_body(data)))
                 }
-                Err(err) => Ok(Response::new().set_data(ack_fail(err.to_string()))),
+                Err(err) => Ok(Response::new().set_data(ack_error(action, &err.into()))),
+            }
+        }
+        SubMsgResult::Err(err) => Ok(Response::new().set_data(ack_fail(action, err))),
+    }
+}
+
+pub fn reply_claim_result(
+    deps: DepsMut,
+    env: Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
+    match reply.result {
+        SubMsgResult::Ok(tx) => {
+            let mut return_msgs = vec![];
+            let mut ack = vec![];
+            let claimed = parse_claimed_coins(tx).and_then(|tokens| {
+                for token in tokens {
+                    return_msgs.extend(credit_amount_out(
+                        deps.storage,
+                        &env,
+                        &reply_args,
+                        &token.denom,
+                        token.amount,
+                    )?);
+                    ack.push(AmountResultAck {
+                        denom: token.denom,
+                        amount: token.amount,
+                    });
+                }
+                Ok(())
+            });
+
+            // the transfers of the coins credited before an error are still sent
+            let res = Response::new().add_submessages(return_msgs);
+            match claimed {
+                Ok(()) => {
+                    let data = to_binary(&ack).unwrap();
+                    Ok(res.set_data(ack_success_with_body(data)))
+                }
+                Err(err) => {
+                    restore_balance_reply(deps.storage, &reply_args)?;
+                    Ok(res.set_data(ack_error(action, &err)))
+                }
             }
         }
-        SubMsgResult::Err(err) => Ok(Response::new().set_data(ack_fail(err))),
+        SubMsgResult::Err(err) => {
+            restore_balance_reply(deps.storage, &reply_args)?;
+            Ok(Response::new().set_data(ack_fail(action, err)))
+        }
+    }
+}
+
+// Coins sent by the lockup claim, the lockup accounts before 0.2.0 return the single coin claimed
+fn parse_claimed_coins(tx: SubMsgResponse) -> Result<Vec<Coin>, ContractError> {
+    let data = tx.data.ok_or(ContractError::MissingReplyData {})?;
+    let data = parse_execute_response_data(data.as_slice())?
+        .data
+        .ok_or(ContractError::MissingReplyData {})?;
+
+    let tokens = from_binary(&data).or_else(|_| from_binary(&data).map(|coin| vec![coin]))?;
+    Ok(tokens)
+}
+
+// Credits the action output to the sender held balance, or sends it back to the remote sender
+// when the packet asked for it (`return_to`).
+fn credit_amount_out(
+    storage: &mut dyn Storage,
+    env: &Env,
+    reply_args: &ReplyArgs,
+    denom: &str,
+    amount: Uint128,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let return_to = match &reply_args.return_to {
+        Some(return_to) => return_to,
+        None => return hold_amount_out(storage, reply_args, denom, amount),
+    };
+
+    // the outputs over the outflow limit of the return channel are held for the sender instead
+    let now = env.block.time;
+    match record_flow(storage, now, &return_to.channel, denom, amount, Flow::Out) {
+        Err(ContractError::RateLimitExceeded { .. }) => {
+            return hold_amount_out(storage, reply_args, denom, amount)
+        }
+        res => res?,
     }
+
+    // delta from user is in seconds
+    let timeout_delta = match return_to.timeout {
+        Some(t) => t,
+        None => CONFIG.load(storage)?.default_timeout,
+    };
+    // timeout is in nanoseconds
+    let timeout = env.block.time.plus_seconds(timeout_delta);
+
+    // the contract is the sender, failed transfers are credited back to the remote sender
+    let packet = Ics20Packet::new(
+        amount,
+        packet_denom(storage, env, &return_to.channel, denom)?,
+        env.contract.address.as_str(),
+        &return_to.remote_address,
+    )
+    .with_memo(return_to.memo.clone());
+
+    increase_channel_balance(storage, &return_to.channel, denom, amount)?;
+
+    let refund_to = HeldBy {
+        channel: reply_args.channel.clone(),
+        sender: reply_args.sender.clone(),
+    };
+    let msg = send_tracked_packet(
+        storage,
+        &return_to.channel,
+        &packet,
+        denom,
+        timeout,
+        None,
+        Some(refund_to),
+    )?;
+    Ok(vec![msg])
+}
+
+// Credits the action output to the remote sender held funds
+fn hold_amount_out(
+    storage: &mut dyn Storage,
+    reply_args: &ReplyArgs,
+    denom: &str,
+    amount: Uint128,
+) -> Result<Vec<SubMsg>, ContractError> {
+    increase_channel_balance(storage, &reply_args.channel, denom, amount)?;
+    increase_user_balance(
+        storage,
+        &reply_args.channel,
+        &reply_args.sender,
+        denom,
+        amount,
+    )?;
+    Ok(vec![])
 }
 
-pub fn reply_claim_result(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+pub fn reply_pipeline(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
     match reply.result {
         SubMsgResult::Ok(tx) => {
             let data = tx.data.ok_or(ContractError::MissingReplyData {})?;
@@ -150,30 +705,124 @@ pub fn reply_claim_result(deps: DepsMut, reply: Reply) -> Result<Response, Contr
                 .data
                 .ok_or(ContractError::MissingReplyData {})?;
 
-            let token: Coin = from_binary(&data)?;
-            let reply_args = REPLY_ARGS.load(deps.storage)?;
-            increase_channel_balance(
-                deps.storage,
-                &reply_args.channel,
-                &token.denom,
-                token.amount,
-            )?;
-
-            let ack = AmountResultAck {
-                denom: token.denom,
-                amount: token.amount,
+            // the output of the last step goes to the sender
+            let results: Vec<StepResultAck> = from_binary(&data)?;
+            let return_msgs = match results.last() {
+                Some(StepResultAck::Amount(out)) => {
+                    credit_amount_out(deps.storage, &env, &reply_args, &out.denom, out.amount)?
+                }
+                _ => vec![],
             };
-            let data = to_binary(&ack).unwrap();
-            Ok(Response::new().set_data(ack_success_with_body(data)))
+
+            let data = to_binary(&results)?;
+            Ok(Response::new()
+                .set_data(ack_success_with_body(data))
+                .add_submessages(return_msgs))
         }
         SubMsgResult::Err(err) => {
-            restore_balance_reply(deps.storage)?;
-            Ok(Response::new().set_data(ack_fail(err)))
+            restore_balance_reply(deps.storage, &reply_args)?;
+            Ok(Response::new().set_data(ack_fail(action, err)))
         }
     }
 }
 
+// Runs inside the pipeline self-call, any error reverts all the steps run so far. The chain
+// redacts the error and reverts this state as well, so the index is only known to the steps
+// failing the checks of `check_pipeline`.
+pub fn reply_pipeline_step(
+    deps: DepsMut,
+    env: Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    let mut state = PIPELINE.load(deps.storage, reply.id)?;
+    PIPELINE.remove(deps.storage, reply.id);
+    let index = state.results.len();
+    let step_failed = |err: ContractError| ContractError::PipelineStepFailed {
+        index,
+        source: Box::new(err),
+    };
+
+    let tx = match reply.result {
+        SubMsgResult::Ok(tx) => tx,
+        SubMsgResult::Err(err) => return Err(step_failed(StdError::generic_err(err).into())),
+    };
+    let result = parse_step_result(&state.steps[index], index, tx).map_err(step_failed)?;
+    state.results.push(result.clone());
+
+    let next = index + 1;
+    if next == state.steps.len() {
+        return Ok(Response::new().set_data(to_binary(&state.results)?));
+    }
+
+    let token_in = match result {
+        StepResultAck::Amount(out) => Coin {
+            denom: out.denom,
+            amount: out.amount,
+        },
+        StepResultAck::Lock(_) => return Err(ContractError::InvalidPipelineStep { index: next }),
+    };
+    let submsg = pipeline_step_msg(
+        deps.as_ref(),
+        &env,
+        &state.channel,
+        &state.sender,
+        &state.steps[next],
+        next,
+        token_in,
+    )
+    .map_err(|err| ContractError::PipelineStepFailed {
+        index: next,
+        source: Box::new(err),
+    })?;
+    let submsg = save_pipeline_step(deps.storage, submsg, &state)?;
+
+    Ok(Response::new()
+        .add_submessage(submsg)
+        .add_attribute("action", "pipeline_step")
+        .add_attribute("step", next.to_string()))
+}
+
+fn parse_step_result(
+    step: &OsmoPacket,
+    index: usize,
+    tx: SubMsgResponse,
+) -> Result<StepResultAck, ContractError> {
+    let denom_out = || {
+        step.token_out_denom()
+            .ok_or(ContractError::InvalidPipelineStep { index })
+    };
+    let result = match step {
+        OsmoPacket::Swap(_) => StepResultAck::Amount(parse_gamm_result::<SwapResponse>(
+            tx,
+            &denom_out()?,
+            Some((SWAP_EVENT, SWAP_ATTR)),
+        )?),
+        OsmoPacket::JoinPool(_) => StepResultAck::Amount(parse_gamm_result::<JoinResponse>(
+            tx,
+            &denom_out()?,
+            Some((JOIN_POOL_EVENT, JOIN_POOL_ATTR)),
+        )?),
+        OsmoPacket::ExitPool(_) => StepResultAck::Amount(parse_gamm_result::<ExitResponse>(
+            tx,
+            &denom_out()?,
+            Some((EXIT_POOL_EVENT, EXIT_POOL_ATTR)),
+        )?),
+        OsmoPacket::Lock(_) => {
+            let data = tx.data.ok_or(ContractError::MissingReplyData {})?;
+            let data = parse_execute_response_data(data.as_slice())?
+                .data
+                .ok_or(ContractError::MissingReplyData {})?;
+            StepResultAck::Lock(from_binary(&data)?)
+        }
+        _ => return Err(ContractError::InvalidPipelineStep { index }),
+    };
+
+    Ok(result)
+}
+
 pub fn reply_ack_from_data(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
     match reply.result {
         SubMsgResult::Ok(tx) => {
             let data = tx.data.ok_or(ContractError::MissingReplyData {})?;
@@ -184,25 +833,56 @@ pub fn reply_ack_from_data(deps: DepsMut, reply: Reply) -> Result<Response, Cont
             Ok(Response::new().set_data(ack_success_with_body(data)))
         }
         SubMsgResult::Err(err) => {
-            restore_balance_reply(deps.storage)?;
-            Ok(Response::new().set_data(ack_fail(err)))
+            restore_balance_reply(deps.storage, &reply_args)?;
+            Ok(Response::new().set_data(ack_fail(action, err)))
         }
     }
 }
 
 pub fn reply_ack_on_error(reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    match reply.result {
+        SubMsgResult::Ok(_) => Ok(Response::new()),
+        SubMsgResult::Err(err) => Ok(Response::new().set_data(ack_fail(action, err))),
+    }
+}
+
+// Keeps the sent packet and its callback by its sequence, to find them on ack or timeout
+pub fn reply_send_packet(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    let mut pending = PENDING_PACKETS.load(deps.storage, reply.id)?;
+    PENDING_PACKETS.remove(deps.storage, reply.id);
+
+    let tx = reply.result.into_result().map_err(StdError::generic_err)?;
+    let sequence = parse_packet_sequence(tx.events)?;
+    pending.transfer.sent_at = Some(env.block.time);
+    save_transfer(deps.storage, &pending.channel, sequence, &pending.transfer)?;
+
+    let mut res = Response::new().add_attribute("packet_sequence", sequence.to_string());
+    if let Some(callback) = pending.callback {
+        ACK_CALLBACKS.save(deps.storage, (&pending.channel, sequence), &callback)?;
+        res = res.add_attribute("callback", callback);
+    }
+    Ok(res)
+}
+
+// A failing callback contract can't block the ack, its error is only reported
+pub fn reply_callback(reply: Reply) -> Result<Response, ContractError> {
     match reply.result {
         SubMsgResult::Ok(_) => Ok(Response::new()),
-        SubMsgResult::Err(err) => Ok(Response::new().set_data(ack_fail(err))),
+        SubMsgResult::Err(err) => Ok(Response::new()
+            .add_attribute("callback", "failed")
+            .add_attribute("error", err)),
     }
 }
 
 pub fn reply_receive(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    let action = reply_action(reply.id);
+    let reply_args = take_reply_args(deps.storage, reply.id)?;
     match reply.result {
         SubMsgResult::Ok(_) => Ok(Response::new()),
         SubMsgResult::Err(err) => {
-            restore_balance_reply(deps.storage)?;
-            Ok(Response::new().set_data(ack_fail(err)))
+            restore_balance_reply(deps.storage, &reply_args)?;
+            Ok(Response::new().set_data(ack_fail(action, err)))
         }
     }
 }
@@ -234,6 +914,7 @@ pub fn ibc_channel_connect(
         id: channel.endpoint.channel_id,
         counterparty_endpoint: channel.counterparty_endpoint,
         connection_id: channel.connection_id,
+        status: ChannelStatus::Open,
     };
     CHANNEL_INFO.save(deps.storage, &info.id, &info)?;
 
@@ -264,14 +945,26 @@ fn enforce_order_and_version(
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_channel_close(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     channel: IbcChannelCloseMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     match channel {
-        IbcChannelCloseMsg::CloseConfirm { .. } => Ok(IbcBasicResponse::new()),
-        IbcChannelCloseMsg::CloseInit { .. } => Err(ContractError::CannotClose {}),
-        _ => panic!(),
+        IbcChannelCloseMsg::CloseConfirm { channel } => {
+            // the balances are kept, the packets already sent time out and are refunded
+            let id = channel.endpoint.channel_id;
+            let mut info = CHANNEL_INFO
+                .may_load(deps.storage, &id)?
+                .ok_or_else(|| ContractError::NoSuchChannel { id: id.clone() })?;
+            info.status = ChannelStatus::Closed;
+            CHANNEL_INFO.save(deps.storage, &id, &info)?;
+
+            Ok(IbcBasicResponse::new()
+                .add_attribute("action", "close_channel")
+                .add_attribute("channel", id))
+        }
+        // the contract never closes its channels
+        _ => Err(ContractError::CannotClose {}),
     }
 }
 
@@ -282,10 +975,16 @@ pub fn ibc_packet_receive(
     msg: IbcPacketReceiveMsg,
 ) -> Result<IbcReceiveResponse, Never> {
     let packet = msg.packet;
+    let contract = env.contract.address.clone();
 
     do_ibc_packet_receive(deps, env, &packet).or_else(|err| {
+        let action = from_binary::<Ics20Packet>(&packet.data)
+            .ok()
+            .and_then(|msg| packet_action(&msg, contract.as_str()).ok())
+            .and_then(|(action, _)| action)
+            .map_or("transfer", |action| action.name());
         Ok(IbcReceiveResponse::new()
-            .set_ack(ack_fail(err.to_string()))
+            .set_ack(ack_error(action, &err))
             .add_attributes(vec![
                 attr("action", "receive"),
                 attr("success", "false"),
@@ -294,12 +993,25 @@ pub fn ibc_packet_receive(
     })
 }
 
-// Returns local denom if the denom is an encoded voucher from the expected endpoint
-// Otherwise, error
+// Returns local denom if the denom is an encoded voucher from the expected endpoint,
+// or an allowed token native to the remote chain. Otherwise, error
 fn parse_voucher(
+    storage: &dyn Storage,
     voucher_denom: String,
     remote_endpoint: &IbcEndpoint,
+    local_channel: &str,
 ) -> Result<Voucher, ContractError> {
+    let ibc_prefix = format!(
+        "{}/{}/",
+        remote_endpoint.port_id, remote_endpoint.channel_id
+    );
+    if !voucher_denom.starts_with(&ibc_prefix) {
+        let key = (local_channel, voucher_denom.as_str());
+        if let Some(token) = EXTERNAL_TOKENS.may_load(storage, key)? {
+            return Ok(Voucher { denom: token.denom });
+        }
+    }
+
     let split_denom: Vec<&str> = voucher_denom.splitn(3, '/').collect();
     if split_denom.len() != 3 {
         return Err(ContractError::NoForeignTokens {});
@@ -321,63 +1033,128 @@ fn parse_voucher(
     })
 }
 
+// Returns the packet action, set in the action field or in the JSON memo, and the forward
+// metadata of the memo
+fn packet_action(
+    msg: &Ics20Packet,
+    contract: &str,
+) -> Result<(Option<OsmoPacket>, Option<ForwardMemo>), ContractError> {
+    let invalid_memo = |error: String| ContractError::InvalidMemo { error };
+    let memo: PacketMemo = match msg.memo.as_deref().map(str::trim) {
+        Some(memo) if memo.starts_with('{') => {
+            from_slice(memo.as_bytes()).map_err(|err| invalid_memo(err.to_string()))?
+        }
+        _ => PacketMemo::default(),
+    };
+
+    if let Some(wasm) = &memo.wasm {
+        if wasm.contract != contract {
+            return Err(invalid_memo(format!(
+                "unknown wasm contract {}",
+                wasm.contract
+            )));
+        }
+    }
+
+    let mut actions = msg
+        .action
+        .iter()
+        .chain(&memo.osmo_action)
+        .chain(memo.wasm.as_ref().map(|wasm| &wasm.msg));
+    let action = actions.next().cloned();
+    if actions.next().is_some() {
+        return Err(invalid_memo("more than one action".to_string()));
+    }
+
+    Ok((action, memo.forward))
+}
+
 // this does the work of ibc_packet_receive, we wrap it to turn errors into acknowledgements
 fn do_ibc_packet_receive(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     packet: &IbcPacket,
 ) -> Result<IbcReceiveResponse, ContractError> {
     let msg: Ics20Packet = from_binary(&packet.data)?;
     let channel = packet.dest.channel_id.clone();
+    load_open_channel(deps.storage, &channel)?;
+    let (action, forward) = packet_action(&msg, env.contract.address.as_str())?;
 
     // If the token originated on the remote chain, it looks like "ucosm".
     // If it originated on our chain, it looks like "port/channel/ucosm".
-    let voucher = parse_voucher(msg.denom, &packet.src)?;
+    let voucher = parse_voucher(
+        deps.storage,
+        msg.denom,
+        &packet.src,
+        &packet.dest.channel_id,
+    )?;
     let denom = voucher.denom.as_str();
 
-    reduce_channel_balance(deps.storage, &channel, denom, msg.amount)?;
-
+    let to_send = Amount::from_parts(denom.to_string(), msg.amount);
     // we need to save the data to update the balances in reply
-    let reply_args = ReplyArgs {
+    let mut reply_args = ReplyArgs {
         channel: channel.clone(),
         denom: denom.to_string(),
         amount: msg.amount,
         sender: msg.sender.clone(),
+        // an explicit return_to of the action wins over the forward metadata
+        return_to: match action.as_ref().and_then(|a| a.return_to()) {
+            Some(return_to) => Some(return_to),
+            None => forward.map(|f| f.return_to()).transpose()?,
+        },
+        action: action.clone(),
+        held: vec![],
+        spent: vec![],
     };
-    REPLY_ARGS.save(deps.storage, &reply_args)?;
-    let to_send = Amount::from_parts(denom.to_string(), msg.amount);
+    if let Some(return_to) = &reply_args.return_to {
+        load_open_channel(deps.storage, &return_to.channel)?;
+    }
 
-    if let Some(action) = msg.action {
-        let contract = env.contract.address.into();
-        match action {
-            OsmoPacket::Swap(swap) => swap_receive(swap, msg.sender, to_send, contract),
-            OsmoPacket::JoinPool(join_pool) => {
-                receive_join_pool(join_pool, msg.sender, to_send, contract)
-            }
-            OsmoPacket::ExitPool(exit_pool) => {
-                receive_exit_pool(exit_pool, msg.sender, to_send, contract)
-            }
-            OsmoPacket::LockupAccount {} => {
-                nonpayable(&to_send)?;
-                receive_create_lockup(deps, &channel, msg.sender, contract)
-            }
-            OsmoPacket::Lock(lock) => {
-                receive_lock_tokens(deps, &channel, lock, msg.sender, to_send)
-            }
-            OsmoPacket::Claim(claim) => {
-                nonpayable(&to_send)?;
-                receive_claim_tokens(deps, &channel, claim, msg.sender)
-            }
-            OsmoPacket::Unlock(unlock) => {
-                nonpayable(&to_send)?;
-                receive_unlock_tokens(deps, &channel, unlock, msg.sender)
+    reduce_channel_balance(deps.storage, &channel, denom, msg.amount)?;
+    let now = env.block.time;
+    if let Err(err) = record_flow(deps.storage, now, &channel, denom, msg.amount, Flow::In) {
+        // the error ack is committed as well, so give the balance back
+        undo_reduce_channel_balance(deps.storage, &channel, denom, msg.amount)?;
+        return Err(err);
+    }
+
+    let res = if let Some(action) = action {
+        let res = receive_action(
+            deps.branch(),
+            env,
+            &mut reply_args,
+            action,
+            msg.sender.clone(),
+            to_send,
+        );
+        match res {
+            Ok(res) => res,
+            Err(err) => {
+                // the error ack is committed as well, so give the balance back
+                undo_reduce_channel_balance(deps.storage, &channel, denom, msg.amount)?;
+                undo_flow(deps.storage, &channel, denom, msg.amount, Flow::In)?;
+                return Err(err);
             }
         }
+    } else if let Some(return_to) = reply_args.return_to.clone() {
+        // forward the received tokens through another hop
+        let msgs = credit_amount_out(deps.storage, &env, &reply_args, denom, msg.amount)?;
+
+        IbcReceiveResponse::new()
+            .set_ack(ack_success())
+            .add_submessages(msgs)
+            .add_attribute("action", "receive_forward")
+            .add_attribute("sender", msg.sender)
+            .add_attribute("denom", denom)
+            .add_attribute("amount", msg.amount)
+            .add_attribute("forward_channel", return_to.channel)
+            .add_attribute("forward_receiver", return_to.remote_address)
+            .add_attribute("success", "true")
     } else {
         let send = send_amount(to_send, msg.receiver.clone());
-        let submsg = SubMsg::reply_on_error(send, RECEIVE_ID);
+        let submsg = SubMsg::reply_always(send, RECEIVE_ID);
 
-        let res = IbcReceiveResponse::new()
+        IbcReceiveResponse::new()
             .set_ack(ack_success())
             .add_submessage(submsg)
             .add_attribute("action", "receive")
@@ -385,73 +1162,217 @@ fn do_ibc_packet_receive(
             .add_attribute("receiver", msg.receiver)
             .add_attribute("denom", denom)
             .add_attribute("amount", msg.amount)
-            .add_attribute("success", "true");
+            .add_attribute("success", "true")
+    };
 
-        Ok(res)
-    }
+    save_reply_args(deps.storage, res, &reply_args)
 }
 
-fn swap_receive(
-    swap: SwapPacket,
-    sender: String,
-    token_in: Amount,
-    contract: String,
+// Gives every submessage replying always its own reply id, keeping the packet context for its
+// reply handler. Submessages replying only on error don't use the context.
+fn save_reply_args(
+    storage: &mut dyn Storage,
+    mut res: IbcReceiveResponse,
+    reply_args: &ReplyArgs,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgSwapExactAmountIn {
-        sender: contract,
-        token_in: Some(cw_osmo_proto::cosmos::base::v1beta1::Coin {
-            denom: token_in.denom(),
-            amount: token_in.amount().to_string(),
-        }),
-        routes: swap
-            .routes
-            .iter()
-            .map(
-                |r| cw_osmo_proto::osmosis::gamm::v1beta1::SwapAmountInRoute {
-                    token_out_denom: r.token_out_denom.to_owned(),
-                    pool_id: r.pool_id.u64(),
-                },
-            )
-            .collect(),
-        token_out_min_amount: swap.token_out_min_amount.to_string(),
-    };
+    for submsg in res.messages.iter_mut() {
+        if submsg.reply_on != ReplyOn::Always {
+            continue;
+        }
+        submsg.id = next_reply_id(storage, submsg.id)?;
+        REPLY_ARGS.save(storage, submsg.id, reply_args)?;
+    }
+    Ok(res)
+}
 
-    let submsg = SubMsg::reply_always(tx.to_msg()?, SWAP_ID);
+// Reply id of the given kind, unique so that it can index the reply context
+fn next_reply_id(storage: &mut dyn Storage, kind: u64) -> StdResult<u64> {
+    let seq = REPLY_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    REPLY_SEQ.save(storage, &seq)?;
+    Ok(kind | seq << REPLY_KIND_BITS)
+}
 
-    let res = IbcReceiveResponse::new()
-        .set_ack(ack_success())
-        .add_submessage(submsg)
-        .add_attribute("action", "receive_swap")
-        .add_attribute("sender", sender)
-        .add_attribute("denom", token_in.denom())
-        .add_attribute("amount", token_in.amount())
-        .add_attribute("success", "true");
+/// Sends the packet replying with its sequence, so that the transfer is tracked and the callback
+/// contract (if any) is called with the packet ack or timeout. The remote sender `refund_to` is
+/// credited with the tokens if the packet fails.
+pub fn send_tracked_packet(
+    storage: &mut dyn Storage,
+    channel: &str,
+    packet: &Ics20Packet,
+    denom: &str,
+    timeout: Timestamp,
+    callback: Option<Addr>,
+    refund_to: Option<HeldBy>,
+) -> StdResult<SubMsg> {
+    let msg = IbcMsg::SendPacket {
+        channel_id: channel.to_string(),
+        data: to_binary(packet)?,
+        timeout: timeout.into(),
+    };
 
-    Ok(res)
+    let id = next_reply_id(storage, SEND_PACKET_ID)?;
+    let pending = PendingPacket {
+        channel: channel.to_string(),
+        transfer: TransferInfo {
+            sender: packet.sender.clone(),
+            receiver: packet.receiver.clone(),
+            denom: denom.to_string(),
+            amount: packet.amount,
+            timeout,
+            status: TransferStatus::Pending,
+            refund_to,
+            sent_at: None,
+        },
+        callback: callback.map(Addr::into_string),
+    };
+    PENDING_PACKETS.save(storage, id, &pending)?;
+
+    Ok(SubMsg::reply_on_success(msg, id))
 }
 
-fn receive_join_pool(
-    join_pool: JoinPoolPacket,
+fn receive_action(
+    mut deps: DepsMut,
+    env: Env,
+    reply_args: &mut ReplyArgs,
+    action: OsmoPacket,
     sender: String,
-    token_in: Amount,
-    contract: String,
+    to_send: Amount,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgJoinSwapExternAmountIn {
-        sender: contract,
-        token_in: Some(cw_osmo_proto::cosmos::base::v1beta1::Coin {
-            denom: token_in.denom(),
-            amount: token_in.amount().to_string(),
-        }),
-        pool_id: join_pool.pool_id.u64(),
-        share_out_min_amount: join_pool.share_out_min_amount.to_string(),
-    };
+    let channel = reply_args.channel.clone();
+    let contract = env.contract.address.to_string();
+    check_allowed_action(deps.storage, &channel, &action, &to_send.denom())?;
+    match action {
+        OsmoPacket::Swap(swap) => swap_receive(deps.as_ref(), swap, sender, to_send, contract),
+        OsmoPacket::SwapExactOut(swap) => swap_exact_out_receive(swap, sender, to_send, contract),
+        OsmoPacket::JoinPool(join_pool) => {
+            receive_join_pool(deps.as_ref(), join_pool, sender, to_send, contract)
+        }
+        OsmoPacket::ExitPool(exit_pool) => {
+            receive_exit_pool(deps.as_ref(), exit_pool, sender, to_send, contract)
+        }
+        OsmoPacket::MultiJoinPool(join_pool) => {
+            receive_multi_join_pool(deps.branch(), reply_args, join_pool, to_send, contract)
+        }
+        OsmoPacket::MultiExitPool(exit_pool) => {
+            receive_multi_exit_pool(deps.branch(), reply_args, exit_pool, to_send, contract)
+        }
+        OsmoPacket::CreateBalancerPool(create_pool) => {
+            receive_create_balancer_pool(deps.branch(), reply_args, create_pool, to_send, contract)
+        }
+        OsmoPacket::Deposit {} => receive_deposit(deps.branch(), reply_args, to_send),
+        OsmoPacket::SwapHeld(swap) => {
+            receive_swap_held(deps.branch(), reply_args, swap, to_send, contract)
+        }
+        OsmoPacket::Withdraw(withdraw) => {
+            receive_withdraw(deps.branch(), &env, reply_args, withdraw, to_send)
+        }
+        OsmoPacket::Pipeline(steps) => {
+            receive_pipeline(deps.branch(), &env, reply_args, steps, to_send)
+        }
+        OsmoPacket::LockupAccount {} => {
+            nonpayable(&to_send)?;
+            receive_create_lockup(deps.branch(), &channel, sender, contract)
+        }
+        OsmoPacket::Lock(lock) => {
+            receive_lock_tokens(deps.branch(), &channel, lock, sender, to_send)
+        }
+        OsmoPacket::Claim(claim) => {
+            nonpayable(&to_send)?;
+            receive_claim_tokens(deps.branch(), &channel, Some(claim.denoms), sender)
+        }
+        OsmoPacket::ClaimAll(_) => {
+            nonpayable(&to_send)?;
+            receive_claim_tokens(deps.branch(), &channel, None, sender)
+        }
+        OsmoPacket::Unlock(unlock) => {
+            nonpayable(&to_send)?;
+            receive_unlock_tokens(deps.branch(), &channel, unlock, sender)
+        }
+        OsmoPacket::ExtendLock(extend) => {
+            nonpayable(&to_send)?;
+            let lockup_msg = LockupExecuteMsg::ExtendLock {
+                id: extend.id,
+                duration: extend.duration,
+            };
+            receive_lockup_result(deps.branch(), &channel, lockup_msg, sender, EXTEND_LOCK_ID)
+        }
+        OsmoPacket::UnlockAll {} => {
+            nonpayable(&to_send)?;
+            let lockup_msg = LockupExecuteMsg::UnlockAll {};
+            receive_lockup_result(deps.branch(), &channel, lockup_msg, sender, UNLOCK_ALL_ID)
+        }
+    }
+}
+
+// Only the allowed pools can be used, and only the allowed denoms of the channel can be output
+fn check_allowed_action(
+    storage: &dyn Storage,
+    channel: &str,
+    action: &OsmoPacket,
+    denom_in: &str,
+) -> Result<(), ContractError> {
+    // every step is checked with the output denom of the previous one, reporting its index
+    if let OsmoPacket::Pipeline(steps) = action {
+        let mut denom_in = denom_in.to_string();
+        for (index, step) in steps.iter().enumerate() {
+            check_allowed_action(storage, channel, step, &denom_in).map_err(|err| {
+                ContractError::PipelineStepFailed {
+                    index,
+                    source: Box::new(err),
+                }
+            })?;
+            denom_in = step.token_out_denom().unwrap_or_default();
+        }
+        return Ok(());
+    }
+
+    if CONFIG.load(storage)?.restrict_pools {
+        // a new pool cannot be vetted before it exists
+        if let OsmoPacket::CreateBalancerPool(_) = action {
+            return Err(ContractError::PoolCreationNotAllowed {});
+        }
+        let mut pool_ids = action.pool_ids();
+        // the pool exited is the one of the input shares
+        if let OsmoPacket::ExitPool(_) = action {
+            pool_ids.push(parse_pool_id(denom_in)?);
+        }
+        for pool_id in pool_ids {
+            if !ALLOWED_POOLS.has(storage, pool_id) {
+                return Err(ContractError::PoolNotAllowed { pool_id });
+            }
+        }
+    }
+
+    let restrict_denoms = ALLOWED_DENOMS
+        .prefix(channel)
+        .keys_raw(storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if restrict_denoms {
+        for denom in action.denoms_out() {
+            if !ALLOWED_DENOMS.has(storage, (channel, &denom)) {
+                return Err(ContractError::DenomNotAllowed { denom });
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    let submsg = SubMsg::reply_always(tx.to_msg()?, JOIN_POOL_ID);
+fn swap_receive(
+    deps: Deps,
+    swap: SwapPacket,
+    sender: String,
+    token_in: Amount,
+    contract: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let swap_msg = swap_msg(deps, &swap, to_coin(&token_in), contract)?;
+    let submsg = SubMsg::reply_always(swap_msg, SWAP_ID);
 
     let res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "receive_join_pool")
+        .add_attribute("action", "receive_swap")
         .add_attribute("sender", sender)
         .add_attribute("denom", token_in.denom())
         .add_attribute("amount", token_in.amount())
@@ -460,27 +1381,81 @@ fn receive_join_pool(
     Ok(res)
 }
 
-fn receive_exit_pool(
-    exit_pool: ExitPoolPacket,
+fn swap_msg(
+    deps: Deps,
+    swap: &SwapPacket,
+    token_in: Coin,
+    contract: String,
+) -> Result<CosmosMsg, ContractError> {
+    let token_out_min_amount = min_amount(
+        swap.token_out_min_amount,
+        swap.max_slippage,
+        swap.reference_price,
+        token_in.amount,
+        || estimate_swap_at_spot(deps, swap, &token_in),
+    )?;
+    // the pool estimate has the price impact, the swap would fail on dispatch below the minimum
+    if swap.max_slippage.is_some() {
+        let estimate = estimate_swap(deps, &contract, swap, &token_in)?;
+        check_min_amount(estimate, token_out_min_amount)?;
+    }
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgSwapExactAmountIn {
+        sender: contract,
+        token_in: Some(cw_osmo_proto::cosmos::base::v1beta1::Coin {
+            denom: token_in.denom,
+            amount: token_in.amount.to_string(),
+        }),
+        routes: swap
+            .routes
+            .iter()
+            .map(
+                |r| cw_osmo_proto::osmosis::gamm::v1beta1::SwapAmountInRoute {
+                    token_out_denom: r.token_out_denom.to_owned(),
+                    pool_id: r.pool_id.u64(),
+                },
+            )
+            .collect(),
+        token_out_min_amount: token_out_min_amount.to_string(),
+    };
+
+    Ok(tx.to_msg()?)
+}
+
+fn swap_exact_out_receive(
+    swap: SwapExactOutPacket,
     sender: String,
     token_in: Amount,
     contract: String,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let pool_id = parse_pool_id(token_in.denom().as_str())?;
-    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgExitSwapShareAmountIn {
+    if swap.token_in_max_amount > token_in.amount() {
+        return Err(ContractError::InvalidMaxAmountIn {});
+    }
+
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgSwapExactAmountOut {
         sender: contract,
-        pool_id,
-        token_out_denom: exit_pool.token_out_denom,
-        share_in_amount: token_in.amount().to_string(),
-        token_out_min_amount: exit_pool.token_out_min_amount.to_string(),
+        routes: swap
+            .routes
+            .iter()
+            .map(
+                |r| cw_osmo_proto::osmosis::gamm::v1beta1::SwapAmountOutRoute {
+                    token_in_denom: r.token_in_denom.to_owned(),
+                    pool_id: r.pool_id.u64(),
+                },
+            )
+            .collect(),
+        token_in_max_amount: swap.token_in_max_amount.to_string(),
+        token_out: Some(cw_osmo_proto::cosmos::base::v1beta1::Coin {
+            denom: swap.token_out_denom,
+            amount: swap.token_out_amount.to_string(),
+        }),
     };
 
-    let submsg = SubMsg::reply_always(tx.to_msg()?, EXIT_POOL_ID);
+    let submsg = SubMsg::reply_always(tx.to_msg()?, SWAP_EXACT_OUT_ID);
 
     let res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "receive_exit_pool")
+        .add_attribute("action", "receive_swap_exact_out")
         .add_attribute("sender", sender)
         .add_attribute("denom", token_in.denom())
         .add_attribute("amount", token_in.amount())
@@ -489,66 +1464,69 @@ fn receive_exit_pool(
     Ok(res)
 }
 
-fn receive_create_lockup(
-    deps: DepsMut,
-    channel: &str,
+fn receive_join_pool(
+    deps: Deps,
+    join_pool: JoinPoolPacket,
     sender: String,
+    token_in: Amount,
     contract: String,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let lockup_key = (channel, sender.as_str());
-    if LOCKUP.has(deps.storage, lockup_key) {
-        return Err(ContractError::OnlyLockupByChannel {});
-    }
-
-    let config = CONFIG.load(deps.storage)?;
-
-    let admin = LockupInitMsg { admin: contract };
-    let init_msg: CosmosMsg = WasmMsg::Instantiate {
-        admin: None,
-        msg: to_binary(&admin)?,
-        code_id: config.lockup_id,
-        label: format!("Lockup {channel}"),
-        funds: vec![],
-    }
-    .into();
-
-    let submsg = SubMsg::reply_always(init_msg, LOCKUP_ID);
+    let join_msg = join_pool_msg(deps, &join_pool, to_coin(&token_in), contract)?;
+    let submsg = SubMsg::reply_always(join_msg, JOIN_POOL_ID);
 
     let res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "receive_lockup_account")
+        .add_attribute("action", "receive_join_pool")
+        .add_attribute("sender", sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
         .add_attribute("success", "true");
 
     Ok(res)
 }
 
-fn receive_lock_tokens(
-    deps: DepsMut,
-    channel: &str,
-    lock: LockPacket,
+fn join_pool_msg(
+    deps: Deps,
+    join_pool: &JoinPoolPacket,
+    token_in: Coin,
+    contract: String,
+) -> Result<CosmosMsg, ContractError> {
+    let pool_id = join_pool.pool_id.u64();
+    let share_out_min_amount = min_amount(
+        join_pool.share_out_min_amount,
+        join_pool.max_slippage,
+        join_pool.reference_price,
+        token_in.amount,
+        || estimate_join_shares(deps, pool_id, &token_in),
+    )?;
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgJoinSwapExternAmountIn {
+        sender: contract,
+        token_in: Some(cw_osmo_proto::cosmos::base::v1beta1::Coin {
+            denom: token_in.denom,
+            amount: token_in.amount.to_string(),
+        }),
+        pool_id,
+        share_out_min_amount: share_out_min_amount.to_string(),
+    };
+
+    Ok(tx.to_msg()?)
+}
+
+fn receive_exit_pool(
+    deps: Deps,
+    exit_pool: ExitPoolPacket,
     sender: String,
     token_in: Amount,
+    contract: String,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let lock_key = (channel, sender.as_str());
-    let lockup_contract = LOCKUP
-        .load(deps.storage, lock_key)
-        .map_err(|_| ContractError::LockupNotFound {})?;
-
-    let lockup_msg = LockupExecuteMsg::Lock {
-        duration: lock.duration,
-    };
-    let exec_msg = create_lockup_msg(
-        lockup_contract,
-        to_binary(&lockup_msg)?,
-        coins(token_in.amount().u128(), token_in.denom()),
-    );
-    let submsg = SubMsg::reply_always(exec_msg, LOCK_TOKEN_ID);
+    let exit_msg = exit_pool_msg(deps, &exit_pool, to_coin(&token_in), contract)?;
+    let submsg = SubMsg::reply_always(exit_msg, EXIT_POOL_ID);
 
     let res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "receive_lock_tokens")
+        .add_attribute("action", "receive_exit_pool")
         .add_attribute("sender", sender)
         .add_attribute("denom", token_in.denom())
         .add_attribute("amount", token_in.amount())
@@ -557,464 +1535,3555 @@ fn receive_lock_tokens(
     Ok(res)
 }
 
-fn receive_claim_tokens(
+fn exit_pool_msg(
+    deps: Deps,
+    exit_pool: &ExitPoolPacket,
+    token_in: Coin,
+    contract: String,
+) -> Result<CosmosMsg, ContractError> {
+    let pool_id = parse_pool_id(token_in.denom.as_str())?;
+    let token_out_min_amount = min_amount(
+        exit_pool.token_out_min_amount,
+        exit_pool.max_slippage,
+        exit_pool.reference_price,
+        token_in.amount,
+        || estimate_exit_amount(deps, pool_id, token_in.amount, &exit_pool.token_out_denom),
+    )?;
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgExitSwapShareAmountIn {
+        sender: contract,
+        pool_id,
+        token_out_denom: exit_pool.token_out_denom.to_owned(),
+        share_in_amount: token_in.amount.to_string(),
+        token_out_min_amount: token_out_min_amount.to_string(),
+    };
+
+    Ok(tx.to_msg()?)
+}
+
+fn receive_multi_join_pool(
     deps: DepsMut,
-    channel: &str,
-    claim: ClaimPacket,
-    sender: String,
+    reply_args: &mut ReplyArgs,
+    join_pool: MultiJoinPoolPacket,
+    token_in: Amount,
+    contract: String,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let lock_key = (channel, sender.as_str());
-    let lockup_contract = LOCKUP
-        .load(deps.storage, lock_key)
-        .map_err(|_| ContractError::LockupNotFound {})?;
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgJoinPool {
+        sender: contract,
+        pool_id: join_pool.pool_id.u64(),
+        share_out_amount: join_pool.share_out_amount.to_string(),
+        token_in_maxs: join_pool
+            .token_in_maxs
+            .iter()
+            .map(|c| cw_osmo_proto::cosmos::base::v1beta1::Coin {
+                denom: c.denom.to_owned(),
+                amount: c.amount.to_string(),
+            })
+            .collect(),
+    };
+    let submsg = SubMsg::reply_always(tx.to_msg()?, MULTI_JOIN_POOL_ID);
 
-    let lockup_msg = LockupExecuteMsg::Claim { denom: claim.denom };
-    let exec_msg = create_lockup_msg(lockup_contract, to_binary(&lockup_msg)?, vec![]);
-    let submsg = SubMsg::reply_always(exec_msg, CLAIM_TOKEN_ID);
+    reply_args.held =
+        take_held_funds(deps.storage, reply_args, &token_in, join_pool.token_in_maxs)?;
 
     let res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "receive_claim_tokens")
-        .add_attribute("sender", sender)
+        .add_attribute("action", "receive_multi_join_pool")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
         .add_attribute("success", "true");
 
     Ok(res)
 }
 
-fn receive_unlock_tokens(
+fn receive_create_balancer_pool(
     deps: DepsMut,
-    channel: &str,
-    unlock: UnlockPacket,
-    sender: String,
+    reply_args: &mut ReplyArgs,
+    create_pool: CreateBalancerPoolPacket,
+    token_in: Amount,
+    contract: String,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let lock_key = (channel, sender.as_str());
-    let lockup_contract = LOCKUP
-        .load(deps.storage, lock_key)
-        .map_err(|_| ContractError::LockupNotFound {})?;
+    if create_pool.pool_assets.len() < 2
+        || create_pool.swap_fee >= Decimal::one()
+        || create_pool.exit_fee >= Decimal::one()
+    {
+        return Err(ContractError::InvalidPoolParams {});
+    }
 
-    let lockup_msg = LockupExecuteMsg::Unlock { id: unlock.id };
-    let exec_msg = create_lockup_msg(lockup_contract, to_binary(&lockup_msg)?, vec![]);
-    let submsg = SubMsg::reply_on_error(exec_msg, UNLOCK_TOKEN_ID);
+    let funds = create_pool
+        .pool_assets
+        .iter()
+        .map(|asset| asset.token.clone())
+        .chain(create_pool.pool_creation_fee.clone())
+        .collect();
+    reply_args.held = take_held_funds(deps.storage, reply_args, &token_in, funds)?;
+    let mut funds_in = reply_args.held.clone();
+    add_coin(&mut funds_in, to_coin(&token_in));
+
+    // the pool is created in a call to ourselves, so that it is reverted when x/gamm charges
+    // more than the sender funds
+    let msg = ExecuteMsg::CreatePool(CreatePoolMsg {
+        pool: create_pool,
+        funds: funds_in,
+    });
+    let exec_msg = WasmMsg::Execute {
+        contract_addr: contract,
+        msg: to_binary(&msg)?,
+        funds: vec![],
+    };
+    let submsg = SubMsg::reply_always(exec_msg, CREATE_POOL_ID);
 
     let res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "receive_unlock")
-        .add_attribute("sender", sender)
+        .add_attribute("action", "receive_create_balancer_pool")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
         .add_attribute("success", "true");
 
     Ok(res)
 }
 
-fn create_lockup_msg(contract_addr: String, msg: Binary, funds: Vec<Coin>) -> CosmosMsg {
-    WasmMsg::Execute {
-        contract_addr,
-        msg,
-        funds,
+pub fn execute_create_pool(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: CreatePoolMsg,
+) -> Result<Response, ContractError> {
+    if info.sender != env.contract.address {
+        return Err(ContractError::Unauthorized);
     }
-    .into()
-}
 
-fn nonpayable(amount: &Amount) -> Result<(), ContractError> {
-    if amount.is_empty() {
-        Ok(())
-    } else {
-        Err(ContractError::NonPayable {})
-    }
-}
+    let create_pool = msg.pool;
+    let proto_coin = |c: &Coin| cw_osmo_proto::cosmos::base::v1beta1::Coin {
+        denom: c.denom.to_owned(),
+        amount: c.amount.to_string(),
+    };
+    let tx = cw_osmo_proto::osmosis::gamm::poolmodels::balancer::v1beta1::MsgCreateBalancerPool {
+        sender: env.contract.address.to_string(),
+        // sdk.Dec is encoded as its 18 decimals integer
+        pool_params: Some(cw_osmo_proto::osmosis::gamm::v1beta1::PoolParams {
+            swap_fee: create_pool.swap_fee.atomics().to_string(),
+            exit_fee: create_pool.exit_fee.atomics().to_string(),
+            smooth_weight_change_params: None,
+        }),
+        pool_assets: create_pool
+            .pool_assets
+            .iter()
+            .map(|asset| cw_osmo_proto::osmosis::gamm::v1beta1::PoolAsset {
+                token: Some(proto_coin(&asset.token)),
+                weight: asset.weight.to_string(),
+            })
+            .collect(),
+        future_pool_governor: create_pool.future_pool_governor,
+    };
+    let id = next_reply_id(deps.storage, CREATE_POOL_CHECK_ID)?;
+    CREATE_POOL_FUNDS.save(deps.storage, id, &msg.funds)?;
+    let submsg = SubMsg::reply_on_success(tx.to_msg()?, id);
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-/// check if success or failure and update balance, or return funds
-pub fn ibc_packet_ack(
-    deps: DepsMut,
-    _env: Env,
-    msg: IbcPacketAckMsg,
-) -> Result<IbcBasicResponse, ContractError> {
-    let ics20msg: Ics20Ack = from_binary(&msg.acknowledgement.data)?;
-    match ics20msg {
-        Ics20Ack::Result(_) => on_packet_success(msg.original_packet),
-        Ics20Ack::Error(err) => on_packet_failure(deps, msg.original_packet, err),
-    }
+    Ok(Response::new()
+        .add_submessage(submsg)
+        .add_attribute("action", "create_pool"))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-/// return fund to original sender (same as failure in ibc_packet_ack)
-pub fn ibc_packet_timeout(
+fn receive_multi_exit_pool(
     deps: DepsMut,
-    _env: Env,
-    msg: IbcPacketTimeoutMsg,
-) -> Result<IbcBasicResponse, ContractError> {
-    let packet = msg.packet;
-    on_packet_failure(deps, packet, "timeout".to_string())
-}
+    reply_args: &mut ReplyArgs,
+    exit_pool: MultiExitPoolPacket,
+    token_in: Amount,
+    contract: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgExitPool {
+        sender: contract,
+        pool_id: exit_pool.pool_id.u64(),
+        share_in_amount: exit_pool.share_in_amount.to_string(),
+        token_out_mins: exit_pool
+            .token_out_mins
+            .iter()
+            .map(|c| cw_osmo_proto::cosmos::base::v1beta1::Coin {
+                denom: c.denom.to_owned(),
+                amount: c.amount.to_string(),
+            })
+            .collect(),
+    };
+    let submsg = SubMsg::reply_always(tx.to_msg()?, MULTI_EXIT_POOL_ID);
 
-// update the balance stored on this (channel, denom) index
-fn on_packet_success(packet: IbcPacket) -> Result<IbcBasicResponse, ContractError> {
-    let msg: Ics20Packet = from_binary(&packet.data)?;
+    let shares_in = Coin {
+        denom: pool_share_denom(exit_pool.pool_id.u64()),
+        amount: exit_pool.share_in_amount,
+    };
+    reply_args.held = take_held_funds(deps.storage, reply_args, &token_in, vec![shares_in])?;
 
-    // similar event messages like ibctransfer module
-    let attributes = vec![
-        attr("action", "acknowledge"),
-        attr("sender", &msg.sender),
-        attr("receiver", &msg.receiver),
-        attr("denom", &msg.denom),
-        attr("amount", msg.amount),
-        attr("success", "true"),
-    ];
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", "receive_multi_exit_pool")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
+        .add_attribute("success", "true");
 
-    Ok(IbcBasicResponse::new().add_attributes(attributes))
+    Ok(res)
 }
 
-// return the tokens to sender
-fn on_packet_failure(
+fn receive_deposit(
     deps: DepsMut,
-    packet: IbcPacket,
-    err: String,
-) -> Result<IbcBasicResponse, ContractError> {
-    let msg: Ics20Packet = from_binary(&packet.data)?;
+    reply_args: &ReplyArgs,
+    token_in: Amount,
+) -> Result<IbcReceiveResponse, ContractError> {
+    if token_in.is_empty() {
+        return Err(ContractError::NoFunds {});
+    }
 
-    reduce_channel_balance(deps.storage, &packet.src.channel_id, &msg.denom, msg.amount)?;
+    // the tokens stay in the contract, so they still count in the channel balance
+    undo_reduce_channel_balance(
+        deps.storage,
+        &reply_args.channel,
+        &reply_args.denom,
+        reply_args.amount,
+    )?;
+    increase_user_balance(
+        deps.storage,
+        &reply_args.channel,
+        &reply_args.sender,
+        &reply_args.denom,
+        reply_args.amount,
+    )?;
 
-    let to_send = Amount::from_parts(msg.denom.clone(), msg.amount);
-    let send = send_amount(to_send, msg.sender.clone());
-    let submsg = SubMsg::reply_on_error(send, ACK_FAILURE_ID);
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_attribute("action", "receive_deposit")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
+        .add_attribute("success", "true");
 
-    // similar event messages like ibctransfer module
-    let res = IbcBasicResponse::new()
+    Ok(res)
+}
+
+fn receive_swap_held(
+    deps: DepsMut,
+    reply_args: &mut ReplyArgs,
+    swap: SwapHeldPacket,
+    token_in: Amount,
+    contract: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let tx = cw_osmo_proto::osmosis::gamm::v1beta1::MsgSwapExactAmountIn {
+        sender: contract,
+        token_in: Some(cw_osmo_proto::cosmos::base::v1beta1::Coin {
+            denom: swap.token_in.denom.to_owned(),
+            amount: swap.token_in.amount.to_string(),
+        }),
+        routes: swap
+            .routes
+            .iter()
+            .map(
+                |r| cw_osmo_proto::osmosis::gamm::v1beta1::SwapAmountInRoute {
+                    token_out_denom: r.token_out_denom.to_owned(),
+                    pool_id: r.pool_id.u64(),
+                },
+            )
+            .collect(),
+        token_out_min_amount: swap.token_out_min_amount.to_string(),
+    };
+    let submsg = SubMsg::reply_always(tx.to_msg()?, SWAP_HELD_ID);
+
+    reply_args.held = take_held_funds(
+        deps.storage,
+        reply_args,
+        &token_in,
+        vec![swap.token_in.clone()],
+    )?;
+    reply_args.spent = vec![swap.token_in.clone()];
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
         .add_submessage(submsg)
-        .add_attribute("action", "acknowledge")
-        .add_attribute("sender", msg.sender)
-        .add_attribute("receiver", msg.receiver)
-        .add_attribute("denom", msg.denom)
-        .add_attribute("amount", msg.amount.to_string())
-        .add_attribute("success", "false")
-        .add_attribute("error", err);
+        .add_attribute("action", "receive_swap_held")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("denom", swap.token_in.denom)
+        .add_attribute("amount", swap.token_in.amount)
+        .add_attribute("success", "true");
 
     Ok(res)
 }
 
-fn send_amount(amount: Amount, recipient: String) -> CosmosMsg {
-    match amount {
-        Amount::Native(coin) => BankMsg::Send {
-            to_address: recipient,
-            amount: vec![coin],
+fn receive_withdraw(
+    deps: DepsMut,
+    env: &Env,
+    reply_args: &mut ReplyArgs,
+    withdraw: WithdrawPacket,
+    token_in: Amount,
+) -> Result<IbcReceiveResponse, ContractError> {
+    if withdraw.coins.iter().all(|c| c.amount.is_zero()) {
+        return Err(ContractError::NoFunds {});
+    }
+
+    reply_args.held = take_held_funds(deps.storage, reply_args, &token_in, withdraw.coins.clone())?;
+
+    let mut msgs = vec![];
+    for coin in withdraw.coins.iter().filter(|c| !c.amount.is_zero()) {
+        match credit_amount_out(deps.storage, env, reply_args, &coin.denom, coin.amount) {
+            Ok(coin_msgs) => msgs.extend(coin_msgs),
+            Err(err) => {
+                // the error ack is committed as well, so give the held funds back
+                restore_held_funds(deps.storage, reply_args)?;
+                return Err(err);
+            }
         }
-        .into(),
     }
+    hold_unused_funds(deps.storage, reply_args, &withdraw.coins)?;
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessages(msgs)
+        .add_attribute("action", "receive_withdraw")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("receiver", withdraw.return_to.remote_address)
+        .add_attribute("success", "true");
+
+    Ok(res)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::test_helpers::*;
+fn receive_pipeline(
+    deps: DepsMut,
+    env: &Env,
+    reply_args: &ReplyArgs,
+    steps: Vec<OsmoPacket>,
+    token_in: Amount,
+) -> Result<IbcReceiveResponse, ContractError> {
+    if token_in.is_empty() {
+        return Err(ContractError::NoFunds {});
+    }
+    if steps.is_empty() {
+        return Err(ContractError::EmptyPipeline {});
+    }
 
-    use crate::contract::{execute, query_channel};
-    use crate::ibc_msg::{AmountResultAck, SwapAmountInRoute};
-    use crate::msg::{ExecuteMsg, TransferMsg};
-    use cosmwasm_std::testing::{mock_env, mock_info};
-    use cosmwasm_std::{
-        coins, to_vec, Event, IbcEndpoint, ReplyOn, StdError, StdResult, SubMsgResponse, Timestamp,
-        Uint128, Uint64,
+    let last = steps.len() - 1;
+    for (index, step) in steps.iter().enumerate() {
+        let valid = match step {
+            // only the pipeline output can be sent back
+            OsmoPacket::Swap(_) | OsmoPacket::JoinPool(_) | OsmoPacket::ExitPool(_) => {
+                index == last || step.return_to().is_none()
+            }
+            OsmoPacket::Lock(_) => index == last,
+            _ => false,
+        };
+        if !valid {
+            return Err(ContractError::InvalidPipelineStep { index });
+        }
+    }
+    let contract = env.contract.address.as_str();
+    check_pipeline(
+        deps.as_ref(),
+        contract,
+        reply_args,
+        &steps,
+        to_coin(&token_in),
+    )?;
+
+    // the steps run in a call to ourselves, so a failing step reverts the previous ones
+    let msg = ExecuteMsg::Pipeline(PipelineMsg {
+        channel: reply_args.channel.clone(),
+        sender: reply_args.sender.clone(),
+        token_in: to_coin(&token_in),
+        steps,
+    });
+    let exec_msg = WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&msg)?,
+        funds: vec![],
     };
-    use serde::de::DeserializeOwned;
-    use serde::Serialize;
+    let submsg = SubMsg::reply_always(exec_msg, PIPELINE_ID);
 
-    #[test]
-    fn check_ack_json() {
-        let success = Ics20Ack::Result(b"1".into());
-        let fail = Ics20Ack::Error("bad coin".into());
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", "receive_pipeline")
+        .add_attribute("sender", &reply_args.sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
+        .add_attribute("success", "true");
 
-        let success_json = String::from_utf8(to_vec(&success).unwrap()).unwrap();
-        assert_eq!(r#"{"result":"MQ=="}"#, success_json.as_str());
+    Ok(res)
+}
 
-        let fail_json = String::from_utf8(to_vec(&fail).unwrap()).unwrap();
-        assert_eq!(r#"{"error":"bad coin"}"#, fail_json.as_str());
+pub fn execute_pipeline(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: PipelineMsg,
+) -> Result<Response, ContractError> {
+    if info.sender != env.contract.address {
+        return Err(ContractError::Unauthorized);
     }
 
-    #[test]
-    fn check_packet_json() {
-        let packet = Ics20Packet::new(
-            Uint128::new(12345),
-            "ucosm",
-            "cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n",
-            "wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc",
-        );
-        // Example message generated from the SDK
-        let expected = r#"{"amount":"12345","denom":"ucosm","receiver":"wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc","sender":"cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n"}"#;
+    let step = msg.steps.first().ok_or(ContractError::EmptyPipeline {})?;
+    let submsg = pipeline_step_msg(
+        deps.as_ref(),
+        &env,
+        &msg.channel,
+        &msg.sender,
+        step,
+        0,
+        msg.token_in,
+    )?;
+
+    let state = PipelineState {
+        channel: msg.channel,
+        sender: msg.sender,
+        steps: msg.steps,
+        results: vec![],
+    };
+    let submsg = save_pipeline_step(deps.storage, submsg, &state)?;
 
-        let encoded = String::from_utf8(to_vec(&packet).unwrap()).unwrap();
-        assert_eq!(expected, encoded.as_str());
-    }
+    Ok(Response::new()
+        .add_submessage(submsg)
+        .add_attribute("action", "pipeline")
+        .add_attribute("steps", state.steps.len().to_string()))
+}
 
-    #[test]
-    fn check_gamm_packet_json() {
-        let packet = Ics20Packet {
-            sender: "cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n".to_string(),
-            receiver: "wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc".to_string(),
-            amount: Uint128::new(12345),
-            denom: "ucosm".to_string(),
-            action: Some(OsmoPacket::JoinPool(JoinPoolPacket {
-                pool_id: Uint64::new(1),
-                share_out_min_amount: Uint128::new(1),
-            })),
-        };
+fn pipeline_step_msg(
+    deps: Deps,
+    env: &Env,
+    channel: &str,
+    sender: &str,
+    step: &OsmoPacket,
+    index: usize,
+    token_in: Coin,
+) -> Result<SubMsg, ContractError> {
+    let contract = env.contract.address.to_string();
+    let msg = match step {
+        OsmoPacket::Swap(swap) => swap_msg(deps, swap, token_in, contract)?,
+        OsmoPacket::JoinPool(join_pool) => join_pool_msg(deps, join_pool, token_in, contract)?,
+        OsmoPacket::ExitPool(exit_pool) => exit_pool_msg(deps, exit_pool, token_in, contract)?,
+        OsmoPacket::Lock(lock) => {
+            let lockup_contract = LOCKUP
+                .load(deps.storage, (channel, sender))
+                .map_err(|_| ContractError::LockupNotFound {})?;
+            lock_msg(lockup_contract, lock, token_in)?
+        }
+        _ => return Err(ContractError::InvalidPipelineStep { index }),
+    };
 
-        // Example message generated from the SDK
-        let expected = r#"{"amount":"12345","denom":"ucosm","receiver":"wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc","sender":"cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n","action":{"join_pool":{"pool_id":"1","share_out_min_amount":"1"}}}"#;
+    Ok(SubMsg::reply_always(msg, PIPELINE_STEP_ID))
+}
 
-        let encoded = String::from_utf8(to_vec(&packet).unwrap()).unwrap();
-        assert_eq!(expected, encoded.as_str());
-    }
+// Keeps the pipeline progress for the reply of the step
+fn save_pipeline_step(
+    storage: &mut dyn Storage,
+    mut submsg: SubMsg,
+    state: &PipelineState,
+) -> StdResult<SubMsg> {
+    submsg.id = next_reply_id(storage, submsg.id)?;
+    PIPELINE.save(storage, submsg.id, state)?;
+    Ok(submsg)
+}
 
-    fn native_payment(amount: u128, denom: &str, recipient: &str) -> SubMsg {
-        SubMsg::reply_on_error(
-            BankMsg::Send {
-                to_address: recipient.into(),
-                amount: coins(amount, denom),
-            },
-            RECEIVE_ID,
+// The chain redacts the error of the failed pipeline call and reverts everything it saved, so
+// the failing step is found here before dispatching. The pool estimates of this block are exact
+// for swaps, and an upper bound for joins and exits, after which the amounts are unknown.
+fn check_pipeline(
+    deps: Deps,
+    contract: &str,
+    reply_args: &ReplyArgs,
+    steps: &[OsmoPacket],
+    token_in: Coin,
+) -> Result<(), ContractError> {
+    let mut denom_in = token_in.denom.clone();
+    let mut token_in = Some(token_in);
+    for (index, step) in steps.iter().enumerate() {
+        token_in = check_pipeline_step(
+            deps,
+            contract,
+            reply_args,
+            step,
+            &denom_in,
+            token_in.as_ref(),
         )
+        .map_err(|err| ContractError::PipelineStepFailed {
+            index,
+            source: Box::new(err),
+        })?;
+        denom_in = step.token_out_denom().unwrap_or_default();
     }
+    Ok(())
+}
 
-    fn check_gamm_submsg(msg: SubMsg, reply_id: u64, action: &str) -> StdResult<()> {
-        if msg.id != reply_id {
-            return Err(StdError::generic_err("Invalid reply id"));
+// Returns the step output when it is known exactly. The checks of the step message that only
+// depend on the input denom are run even when its amount is unknown, so that they don't fail
+// once dispatched where the chain redacts the error.
+fn check_pipeline_step(
+    deps: Deps,
+    contract: &str,
+    reply_args: &ReplyArgs,
+    step: &OsmoPacket,
+    denom_in: &str,
+    token_in: Option<&Coin>,
+) -> Result<Option<Coin>, ContractError> {
+    match step {
+        OsmoPacket::Lock(_) => {
+            let lock_key = (reply_args.channel.as_str(), reply_args.sender.as_str());
+            if !LOCKUP.has(deps.storage, lock_key) {
+                return Err(ContractError::LockupNotFound {});
+            }
+            return Ok(None);
+        }
+        OsmoPacket::ExitPool(_) => {
+            parse_pool_id(denom_in)?;
         }
+        _ => {}
+    }
+    let token_in = match token_in {
+        Some(token_in) => token_in,
+        None => return Ok(None),
+    };
 
-        if msg.reply_on != ReplyOn::Always {
-            return Err(StdError::generic_err("Invalid reply on"));
+    match step {
+        OsmoPacket::Swap(swap) => {
+            let amount = estimate_swap(deps, contract, swap, token_in)?;
+            let min = min_amount(
+                swap.token_out_min_amount,
+                swap.max_slippage,
+                swap.reference_price,
+                token_in.amount,
+                || estimate_swap_at_spot(deps, swap, token_in),
+            )?;
+            check_min_amount(amount, min)?;
+            let denom = step.denoms_out().pop().unwrap_or_default();
+            Ok(Some(Coin { denom, amount }))
+        }
+        OsmoPacket::JoinPool(join_pool) => {
+            let pool_id = join_pool.pool_id.u64();
+            let shares = estimate_join_shares(deps, pool_id, token_in)?;
+            let min = min_amount(
+                join_pool.share_out_min_amount,
+                join_pool.max_slippage,
+                join_pool.reference_price,
+                token_in.amount,
+                || Ok(shares),
+            )?;
+            check_min_amount(shares, min)?;
+            Ok(None)
+        }
+        OsmoPacket::ExitPool(exit_pool) => {
+            let pool_id = parse_pool_id(token_in.denom.as_str())?;
+            let denom = &exit_pool.token_out_denom;
+            let amount = estimate_exit_amount(deps, pool_id, token_in.amount, denom)?;
+            let min = min_amount(
+                exit_pool.token_out_min_amount,
+                exit_pool.max_slippage,
+                exit_pool.reference_price,
+                token_in.amount,
+                || Ok(amount),
+            )?;
+            check_min_amount(amount, min)?;
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+// Takes the part of `funds` not covered by the received tokens from the sender held balance,
+// and returns what was taken
+fn take_held_funds(
+    storage: &mut dyn Storage,
+    reply_args: &ReplyArgs,
+    token_in: &Amount,
+    funds: Vec<Coin>,
+) -> Result<Vec<Coin>, ContractError> {
+    let mut held: Vec<Coin> = vec![];
+    for coin in funds {
+        add_coin(&mut held, coin);
+    }
+    for coin in held.iter_mut() {
+        if coin.denom == token_in.denom() {
+            coin.amount = coin.amount.saturating_sub(token_in.amount());
+        }
+    }
+    held.retain(|c| !c.amount.is_zero());
+
+    // check all the balances before touching any of them
+    for coin in &held {
+        let balance = USER_BALANCES
+            .may_load(
+                storage,
+                (&reply_args.channel, &reply_args.sender, &coin.denom),
+            )?
+            .unwrap_or_default();
+        if balance < coin.amount {
+            return Err(ContractError::InsufficientHeldFunds {});
+        }
+    }
+
+    for coin in &held {
+        reduce_user_balance(
+            storage,
+            &reply_args.channel,
+            &reply_args.sender,
+            &coin.denom,
+            coin.amount,
+        )?;
+        reduce_channel_balance(storage, &reply_args.channel, &coin.denom, coin.amount)?;
+    }
+
+    Ok(held)
+}
+
+fn add_coin(coins: &mut Vec<Coin>, coin: Coin) {
+    match coins.iter_mut().find(|c| c.denom == coin.denom) {
+        Some(c) => c.amount += coin.amount,
+        None => coins.push(coin),
+    }
+}
+
+fn receive_create_lockup(
+    deps: DepsMut,
+    channel: &str,
+    sender: String,
+    contract: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let lockup_key = (channel, sender.as_str());
+    if LOCKUP.has(deps.storage, lockup_key) {
+        return Err(ContractError::OnlyLockupByChannel {});
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+
+    // the contract is the wasm admin as well, to migrate the lockup accounts
+    let admin = LockupInitMsg {
+        admin: contract.clone(),
+    };
+    let init_msg: CosmosMsg = WasmMsg::Instantiate {
+        admin: Some(contract),
+        msg: to_binary(&admin)?,
+        code_id: config.lockup_id,
+        label: format!("Lockup {channel}"),
+        funds: vec![],
+    }
+    .into();
+
+    let submsg = SubMsg::reply_always(init_msg, LOCKUP_ID);
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", "receive_lockup_account")
+        .add_attribute("success", "true");
+
+    Ok(res)
+}
+
+fn receive_lock_tokens(
+    deps: DepsMut,
+    channel: &str,
+    lock: LockPacket,
+    sender: String,
+    token_in: Amount,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let lock_key = (channel, sender.as_str());
+    let lockup_contract = LOCKUP
+        .load(deps.storage, lock_key)
+        .map_err(|_| ContractError::LockupNotFound {})?;
+
+    let exec_msg = lock_msg(lockup_contract, &lock, to_coin(&token_in))?;
+    let submsg = SubMsg::reply_always(exec_msg, LOCK_TOKEN_ID);
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", "receive_lock_tokens")
+        .add_attribute("sender", sender)
+        .add_attribute("denom", token_in.denom())
+        .add_attribute("amount", token_in.amount())
+        .add_attribute("success", "true");
+
+    Ok(res)
+}
+
+fn lock_msg(
+    lockup_contract: String,
+    lock: &LockPacket,
+    token_in: Coin,
+) -> Result<CosmosMsg, ContractError> {
+    let lockup_msg = LockupExecuteMsg::Lock {
+        duration: lock.duration,
+    };
+
+    Ok(create_lockup_msg(
+        lockup_contract,
+        to_binary(&lockup_msg)?,
+        vec![token_in],
+    ))
+}
+
+// Claims the denoms, every balance if not set. The lockup accounts before 0.2.0 can't be migrated
+// and only claim a single denom.
+fn receive_claim_tokens(
+    deps: DepsMut,
+    channel: &str,
+    denoms: Option<Vec<String>>,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let lock_key = (channel, sender.as_str());
+    let lockup_contract = LOCKUP
+        .load(deps.storage, lock_key)
+        .map_err(|_| ContractError::LockupNotFound {})?;
+
+    let version = cw2::CONTRACT
+        .query(&deps.querier, Addr::unchecked(&lockup_contract))?
+        .version;
+    let legacy = version.starts_with("0.1.");
+    let (lockup_msg, reply_id) = match denoms {
+        Some(mut denoms) if legacy && denoms.len() == 1 => {
+            let denom = denoms.remove(0);
+            let msg = LegacyLockupExecuteMsg::Claim { denom };
+            (to_binary(&msg)?, CLAIM_TOKEN_ID)
         }
+        _ if legacy => return Err(ContractError::LockupClaimUnsupported { version }),
+        Some(denoms) => (
+            to_binary(&LockupExecuteMsg::Claim { denoms })?,
+            CLAIM_TOKEN_ID,
+        ),
+        None => (to_binary(&LockupExecuteMsg::ClaimAll {})?, CLAIM_ALL_ID),
+    };
+
+    let exec_msg = create_lockup_msg(lockup_contract, lockup_msg, vec![]);
+    let submsg = SubMsg::reply_always(exec_msg, reply_id);
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", "receive_claim_tokens")
+        .add_attribute("sender", sender)
+        .add_attribute("success", "true");
+
+    Ok(res)
+}
+
+fn receive_unlock_tokens(
+    deps: DepsMut,
+    channel: &str,
+    unlock: UnlockPacket,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let lock_key = (channel, sender.as_str());
+    let lockup_contract = LOCKUP
+        .load(deps.storage, lock_key)
+        .map_err(|_| ContractError::LockupNotFound {})?;
+
+    let lockup_msg = LockupExecuteMsg::Unlock { id: unlock.id };
+    let exec_msg = create_lockup_msg(lockup_contract, to_binary(&lockup_msg)?, vec![]);
+    let submsg = SubMsg::reply_on_error(exec_msg, UNLOCK_TOKEN_ID);
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", "receive_unlock")
+        .add_attribute("sender", sender)
+        .add_attribute("success", "true");
+
+    Ok(res)
+}
+
+// Runs the lockup message, its typed result is sent in the ack
+fn receive_lockup_result(
+    deps: DepsMut,
+    channel: &str,
+    lockup_msg: LockupExecuteMsg,
+    sender: String,
+    reply_id: u64,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let lock_key = (channel, sender.as_str());
+    let lockup_contract = LOCKUP
+        .load(deps.storage, lock_key)
+        .map_err(|_| ContractError::LockupNotFound {})?;
+
+    let exec_msg = create_lockup_msg(lockup_contract, to_binary(&lockup_msg)?, vec![]);
+    let submsg = SubMsg::reply_always(exec_msg, reply_id);
+
+    let res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(submsg)
+        .add_attribute("action", format!("receive_{}", reply_action(reply_id)))
+        .add_attribute("sender", sender)
+        .add_attribute("success", "true");
+
+    Ok(res)
+}
+
+fn create_lockup_msg(contract_addr: String, msg: Binary, funds: Vec<Coin>) -> CosmosMsg {
+    WasmMsg::Execute {
+        contract_addr,
+        msg,
+        funds,
+    }
+    .into()
+}
+
+fn to_coin(amount: &Amount) -> Coin {
+    match amount {
+        Amount::Native(coin) => coin.clone(),
+    }
+}
+
+fn nonpayable(amount: &Amount) -> Result<(), ContractError> {
+    if amount.is_empty() {
+        Ok(())
+    } else {
+        Err(ContractError::NonPayable {})
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+/// check if success or failure and update balance, or return funds
+pub fn ibc_packet_ack(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketAckMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let ics20msg: Ics20Ack = from_binary(&msg.acknowledgement.data)?;
+    match ics20msg {
+        Ics20Ack::Result(data) => on_packet_success(deps, msg.original_packet, data),
+        Ics20Ack::Error(err) => {
+            // the counterpart contract sends an ErrorAck, other ics20 modules a plain error
+            let result = match from_slice::<ErrorAck>(err.as_bytes()) {
+                Ok(error) => ActionAckResult::Error {
+                    code: Some(error.code),
+                    details: error.details,
+                },
+                Err(_) => ActionAckResult::Error {
+                    code: None,
+                    details: err.clone(),
+                },
+            };
+            on_packet_failure(deps, env, msg.original_packet, err, result)
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+/// return fund to original sender (same as failure in ibc_packet_ack)
+pub fn ibc_packet_timeout(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let packet = msg.packet;
+    let result = ActionAckResult::Timeout {};
+    on_packet_failure(deps, env, packet, "timeout".to_string(), result)
+}
+
+// update the balance stored on this (channel, denom) index
+fn on_packet_success(
+    deps: DepsMut,
+    packet: IbcPacket,
+    data: Binary,
+) -> Result<IbcBasicResponse, ContractError> {
+    let msg: Ics20Packet = from_binary(&packet.data)?;
+    let denom = parse_voucher_ack(deps.storage, msg.denom.clone(), &packet.src)?;
+    let channel = &packet.src.channel_id;
+    let sent = sent_transfer(&packet, &msg, denom);
+    let transfer = load_transfer(deps.storage, channel, packet.sequence, sent)?;
+    finish_transfer(
+        deps.storage,
+        channel,
+        packet.sequence,
+        transfer,
+        TransferStatus::Delivered,
+    )?;
+    let callback = ack_callback(
+        deps.storage,
+        &packet,
+        &msg,
+        ActionAckResult::Success { data },
+    )?;
+
+    // similar event messages like ibctransfer module
+    let attributes = vec![
+        attr("action", "acknowledge"),
+        attr("sender", &msg.sender),
+        attr("receiver", &msg.receiver),
+        attr("denom", &msg.denom),
+        attr("amount", msg.amount),
+        attr("success", "true"),
+    ];
+
+    Ok(IbcBasicResponse::new()
+        .add_submessages(callback)
+        .add_attributes(attributes))
+}
+
+// return the tokens to sender
+fn on_packet_failure(
+    deps: DepsMut,
+    env: Env,
+    packet: IbcPacket,
+    err: String,
+    result: ActionAckResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    let msg: Ics20Packet = from_binary(&packet.data)?;
+    let callback = ack_callback(deps.storage, &packet, &msg, result)?;
+    let denom = parse_voucher_ack(deps.storage, msg.denom.clone(), &packet.src)?;
+
+    let channel = &packet.src.channel_id;
+    let sent = sent_transfer(&packet, &msg, denom.clone());
+    let transfer = load_transfer(deps.storage, channel, packet.sequence, sent)?;
+    let refund_to = transfer.refund_to.clone();
+    let sent_at = transfer.sent_at;
+
+    // action outputs sent back by the contract (return_to) are credited back to the remote
+    // sender held funds, the outputs without a known sender stay in the channel balance
+    let status = match (&refund_to, msg.sender == env.contract.address.as_str()) {
+        (None, true) => TransferStatus::Failed,
+        _ => TransferStatus::Refunded,
+    };
+    finish_transfer(deps.storage, channel, packet.sequence, transfer, status)?;
+
+    if let Some(HeldBy {
+        channel: held_channel,
+        sender,
+    }) = refund_to
+    {
+        reduce_channel_balance(deps.storage, channel, &denom, msg.amount)?;
+        undo_flow_at(
+            deps.storage,
+            sent_at,
+            channel,
+            &denom,
+            msg.amount,
+            Flow::Out,
+        )?;
+        increase_channel_balance(deps.storage, &held_channel, &denom, msg.amount)?;
+        increase_user_balance(deps.storage, &held_channel, &sender, &denom, msg.amount)?;
+
+        let res = IbcBasicResponse::new()
+            .add_attribute("action", "acknowledge")
+            .add_attribute("sender", msg.sender)
+            .add_attribute("receiver", msg.receiver)
+            .add_attribute("denom", denom)
+            .add_attribute("amount", msg.amount.to_string())
+            .add_attribute("success", "false")
+            .add_attribute("error", err)
+            .add_attribute("refund_channel", held_channel)
+            .add_attribute("refund_sender", sender);
+
+        return Ok(res);
+    }
+
+    if status == TransferStatus::Failed {
+        let res = IbcBasicResponse::new()
+            .add_attribute("action", "acknowledge")
+            .add_attribute("sender", msg.sender)
+            .add_attribute("receiver", msg.receiver)
+            .add_attribute("denom", denom)
+            .add_attribute("amount", msg.amount.to_string())
+            .add_attribute("success", "false")
+            .add_attribute("error", err);
+
+        return Ok(res);
+    }
+
+    reduce_channel_balance(deps.storage, &packet.src.channel_id, &denom, msg.amount)?;
+    // the refunded amount was never sent
+    undo_flow_at(
+        deps.storage,
+        sent_at,
+        channel,
+        &denom,
+        msg.amount,
+        Flow::Out,
+    )?;
+
+    let to_send = Amount::from_parts(denom.clone(), msg.amount);
+    let send = send_amount(to_send, msg.sender.clone());
+    let submsg = SubMsg::reply_on_error(send, ACK_FAILURE_ID);
+
+    // similar event messages like ibctransfer module
+    // the callback runs after the refund
+    let res = IbcBasicResponse::new()
+        .add_submessage(submsg)
+        .add_submessages(callback)
+        .add_attribute("action", "acknowledge")
+        .add_attribute("sender", msg.sender)
+        .add_attribute("receiver", msg.receiver)
+        .add_attribute("denom", denom)
+        .add_attribute("amount", msg.amount.to_string())
+        .add_attribute("success", "false")
+        .add_attribute("error", err);
+
+    Ok(res)
+}
+
+// The transfer of a packet sent before the transfers were tracked
+fn sent_transfer(packet: &IbcPacket, msg: &Ics20Packet, denom: String) -> TransferInfo {
+    TransferInfo {
+        sender: msg.sender.clone(),
+        receiver: msg.receiver.clone(),
+        denom,
+        amount: msg.amount,
+        timeout: packet.timeout.timestamp().unwrap_or_default(),
+        status: TransferStatus::Pending,
+        refund_to: None,
+        sent_at: None,
+    }
+}
+
+// Calls the callback contract of the packet with its result, if the sender set one
+fn ack_callback(
+    storage: &mut dyn Storage,
+    packet: &IbcPacket,
+    msg: &Ics20Packet,
+    result: ActionAckResult,
+) -> StdResult<Option<SubMsg>> {
+    let key = (packet.src.channel_id.as_str(), packet.sequence);
+    let callback = match ACK_CALLBACKS.may_load(storage, key)? {
+        Some(callback) => callback,
+        None => return Ok(None),
+    };
+    ACK_CALLBACKS.remove(storage, key);
+
+    let channel = packet.src.channel_id.clone();
+    let callback_msg = match &msg.action {
+        Some(action) => CallbackMsg::ActionAck(ActionAckMsg {
+            channel,
+            packet_sequence: packet.sequence,
+            action: action.name().to_string(),
+            result,
+        }),
+        None => {
+            let (ack_data, error) = match result {
+                ActionAckResult::Success { data } => (Some(data), None),
+                ActionAckResult::Error { details, .. } => (None, Some(details)),
+                ActionAckResult::Timeout {} => (None, Some("timeout".to_string())),
+            };
+            CallbackMsg::IbcTransferCallback(IbcTransferCallback {
+                channel,
+                packet_sequence: packet.sequence,
+                success: ack_data.is_some(),
+                ack_data,
+                error,
+            })
+        }
+    };
+    let execute = WasmMsg::Execute {
+        contract_addr: callback,
+        msg: to_binary(&callback_msg)?,
+        funds: vec![],
+    };
+
+    // the callback can't use up the gas of the relayer ack
+    let submsg = SubMsg::reply_on_error(execute, CALLBACK_ID).with_gas_limit(CALLBACK_GAS_LIMIT);
+    Ok(Some(submsg))
+}
+
+// Returns the local denom of a packet we sent, tokens native to the remote chain are sent
+// prefixed with our endpoint
+fn parse_voucher_ack(
+    storage: &dyn Storage,
+    voucher_denom: String,
+    local_endpoint: &IbcEndpoint,
+) -> Result<String, ContractError> {
+    let ibc_prefix = format!("{}/{}/", local_endpoint.port_id, local_endpoint.channel_id);
+    match voucher_denom.strip_prefix(&ibc_prefix) {
+        Some(remote_denom) => {
+            let token = EXTERNAL_TOKENS
+                .load(storage, (&local_endpoint.channel_id, remote_denom))
+                .map_err(|_| ContractError::NoAllowedToken {})?;
+            Ok(token.denom)
+        }
+        None => Ok(voucher_denom),
+    }
+}
+
+// Returns the denom of the ics20 packet sending the local denom over the channel. Tokens native
+// to the remote chain are prefixed with our endpoint, so the remote chain releases them.
+pub fn packet_denom(
+    storage: &dyn Storage,
+    env: &Env,
+    channel: &str,
+    denom: &str,
+) -> StdResult<String> {
+    match EXTERNAL_DENOMS.may_load(storage, (channel, denom))? {
+        // wasmd binds every ibc enabled contract to the "wasm.<address>" port
+        Some(remote_denom) => Ok(format!(
+            "wasm.{}/{}/{}",
+            env.contract.address, channel, remote_denom
+        )),
+        None => Ok(denom.to_string()),
+    }
+}
+
+fn send_amount(amount: Amount, recipient: String) -> CosmosMsg {
+    match amount {
+        Amount::Native(coin) => BankMsg::Send {
+            to_address: recipient,
+            amount: vec![coin],
+        }
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_helpers::*;
+
+    use crate::contract::{execute, query, query_channel};
+    use crate::ibc_msg::{
+        AmountResultAck, ClaimAllPacket, ClaimPacket, ExtendLockPacket, ExtendLockResultAck,
+        GoDuration, PoolAsset, ReturnTo, SwapAmountInRoute, SwapAmountOutRoute, UnlockAllResultAck,
+        UnlockingLockAck,
+    };
+    use crate::msg::{
+        ExternalTokenMsg, ListAllowedDenomsResponse, ListAllowedPoolsResponse,
+        ListRateLimitsResponse, PendingTransfersResponse, QueryMsg, RateLimitMsg,
+        RateLimitResponse, ReserveMsg, TransferMsg, TransferResponse, TransferWithActionMsg,
+    };
+    use crate::state::{TRANSFERS, USER_BALANCES};
+    use cosmwasm_std::testing::{
+        mock_env, mock_ibc_channel, mock_info, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        coins, from_slice, Decimal, Event, IbcAcknowledgement, IbcEndpoint, OwnedDeps, Querier,
+        ReplyOn, StdError, StdResult, SubMsgResponse, Timestamp, Uint128, Uint64,
+    };
+    use cw_osmo_proto::osmosis::gamm::poolmodels::balancer::v1beta1::MsgCreateBalancerPool;
+    use cw_osmo_proto::osmosis::gamm::v1beta1::{
+        MsgExitSwapShareAmountIn, MsgJoinSwapExternAmountIn, MsgSwapExactAmountIn,
+    };
+    use cw_osmo_proto::proto_ext::proto_decode;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    #[test]
+    fn check_ack_json() {
+        let success = Ics20Ack::Result(b"1".into());
+        let fail = Ics20Ack::Error("bad coin".into());
+
+        let success_json = String::from_utf8(to_vec(&success).unwrap()).unwrap();
+        assert_eq!(r#"{"result":"MQ=="}"#, success_json.as_str());
+
+        let fail_json = String::from_utf8(to_vec(&fail).unwrap()).unwrap();
+        assert_eq!(r#"{"error":"bad coin"}"#, fail_json.as_str());
+    }
+
+    #[test]
+    fn check_packet_json() {
+        let packet = Ics20Packet::new(
+            Uint128::new(12345),
+            "ucosm",
+            "cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n",
+            "wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc",
+        );
+        // Example message generated from the SDK
+        let expected = r#"{"amount":"12345","denom":"ucosm","receiver":"wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc","sender":"cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n"}"#;
+
+        let encoded = String::from_utf8(to_vec(&packet).unwrap()).unwrap();
+        assert_eq!(expected, encoded.as_str());
+    }
+
+    #[test]
+    fn check_gamm_packet_json() {
+        let packet = Ics20Packet {
+            sender: "cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n".to_string(),
+            receiver: "wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc".to_string(),
+            amount: Uint128::new(12345),
+            denom: "ucosm".to_string(),
+            memo: None,
+            action: Some(OsmoPacket::JoinPool(JoinPoolPacket {
+                pool_id: Uint64::new(1),
+                share_out_min_amount: Some(Uint128::new(1)),
+                max_slippage: None,
+                reference_price: None,
+            })),
+        };
+
+        // Example message generated from the SDK
+        let expected = r#"{"amount":"12345","denom":"ucosm","receiver":"wasm1fucynrfkrt684pm8jrt8la5h2csvs5cnldcgqc","sender":"cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n","action":{"join_pool":{"pool_id":"1","share_out_min_amount":"1"}}}"#;
+
+        let encoded = String::from_utf8(to_vec(&packet).unwrap()).unwrap();
+        assert_eq!(expected, encoded.as_str());
+    }
+
+    fn native_payment(id: u64, amount: u128, denom: &str, recipient: &str) -> SubMsg {
+        SubMsg::reply_always(
+            BankMsg::Send {
+                to_address: recipient.into(),
+                amount: coins(amount, denom),
+            },
+            id,
+        )
+    }
+
+    fn check_gamm_submsg(msg: SubMsg, reply_id: u64, action: &str) -> StdResult<()> {
+        if reply_kind(msg.id) != reply_id {
+            return Err(StdError::generic_err("Invalid reply id"));
+        }
+
+        if msg.reply_on != ReplyOn::Always {
+            return Err(StdError::generic_err("Invalid reply on"));
+        }
+
+        match msg.msg {
+            CosmosMsg::Stargate { type_url, .. } => {
+                if !type_url.to_lowercase().contains(action) {
+                    return Err(StdError::generic_err(format!(
+                        "Invalid stargate proto url: {type_url}"
+                    )));
+                }
+            }
+            _ => return Err(StdError::generic_err("Invalid cosmMsg")),
+        };
+
+        Ok(())
+    }
+
+    fn get_ack_result<T: DeserializeOwned>(data: &Binary) -> StdResult<T> {
+        let ack: Ics20Ack = from_binary(data).unwrap();
+        match ack {
+            Ics20Ack::Result(data) => Ok(from_binary(&data).unwrap()),
+            Ics20Ack::Error(err) => Err(StdError::generic_err(err)),
+        }
+    }
+
+    fn mock_reply_msg(id: u64, events: Vec<Event>, data: Option<Binary>) -> Reply {
+        Reply {
+            id,
+            result: SubMsgResult::Ok(SubMsgResponse { events, data }),
+        }
+    }
+
+    // reply id of the last submessage sent with its own context by a received packet
+    fn last_reply_id<Q: Querier>(deps: &OwnedDeps<MockStorage, MockApi, Q>, kind: u64) -> u64 {
+        REPLY_SEQ.load(&deps.storage).unwrap() << REPLY_KIND_BITS | kind
+    }
+
+    fn mock_ics20_data(
+        amount: u128,
+        denom: &str,
+        receiver: &str,
+        action: Option<OsmoPacket>,
+    ) -> Ics20Packet {
+        Ics20Packet {
+            // this is returning a foreign (our) token, thus denom is <port>/<channel>/<denom>
+            denom: format!("{REMOTE_PORT}/channel-1234/{denom}"),
+            amount: amount.into(),
+            sender: "remote-sender".to_string(),
+            receiver: receiver.to_string(),
+            memo: None,
+            action,
+        }
+    }
+
+    fn mock_receive_packet(
+        my_channel: &str,
+        amount: u128,
+        denom: &str,
+        receiver: &str,
+    ) -> IbcPacketReceiveMsg {
+        let data = mock_ics20_data(amount, denom, receiver, None);
+
+        mock_ibc_rcv_packet(my_channel, &data)
+    }
+
+    fn mock_ibc_rcv_packet(my_channel: &str, data: &impl Serialize) -> IbcPacketReceiveMsg {
+        IbcPacketReceiveMsg::new(IbcPacket::new(
+            to_binary(&data).unwrap(),
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: "channel-1234".to_string(),
+            },
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: my_channel.to_string(),
+            },
+            3,
+            Timestamp::from_seconds(1665321069).into(),
+        ))
+    }
+
+    fn mock_rcv_action_packet(
+        action: OsmoPacket,
+        channel: &str,
+        amount: u128,
+        denom: &str,
+    ) -> IbcPacketReceiveMsg {
+        let packet_data = mock_ics20_data(amount, denom, "", Some(action));
+
+        mock_ibc_rcv_packet(channel, &packet_data)
+    }
+
+    fn assert_submsg_wasm<T: DeserializeOwned + std::cmp::PartialEq>(
+        submsg: SubMsg,
+        reply_id: u64,
+        on: ReplyOn,
+        contract: &String,
+        msg_exp: T,
+        mgs_funds: Vec<Coin>,
+    ) {
+        assert!(matches!(submsg, SubMsg {
+            id,
+            ref reply_on,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                ref contract_addr,
+                ref msg,
+                funds,
+            }),
+            ..
+        } if reply_kind(id) == reply_id && reply_on.clone() == on && contract_addr.eq(contract) && funds.eq(&mgs_funds) && msg_exp.eq(&from_binary::<T>(msg).unwrap())));
+    }
+
+    #[test]
+    fn send_receive_native() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", "channel-7", send_channel]);
+
+        let denom = "uatom";
+
+        // prepare some mock packets
+        let recv_packet = mock_receive_packet(send_channel, 876543210, denom, "local-rcpt");
+        let recv_high_packet = mock_receive_packet(send_channel, 1876543210, denom, "local-rcpt");
+
+        // cannot receive this denom yet
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet.clone()).unwrap();
+        assert!(res.messages.is_empty());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let no_funds = error_ack("transfer", ContractError::InsufficientFunds {});
+        assert_eq!(ack, no_funds);
+
+        // we transfer some tokens
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(987654321, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // query channel state|_|
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(987654321, denom)]);
+        assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
+
+        // cannot receive more than we sent
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_high_packet).unwrap();
+        assert!(res.messages.is_empty());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(ack, no_funds);
+
+        // we can receive less than we sent
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(
+            native_payment(
+                last_reply_id(&deps, RECEIVE_ID),
+                876543210,
+                denom,
+                "local-rcpt"
+            ),
+            res.messages[0]
+        );
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+
+        // only need to call reply block on error case
+
+        // query channel state
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(111111111, denom)]);
+        assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
+    }
+
+    #[test]
+    fn receive_swap_action() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", "channel-7", send_channel]);
+        let denom = "uatom";
+        let swap_denom = "uosmo";
+
+        let swap = OsmoPacket::Swap(SwapPacket {
+            routes: vec![SwapAmountInRoute {
+                pool_id: 1u8.into(),
+                token_out_denom: swap_denom.to_string(),
+            }],
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: None,
+        });
+
+        let swap_packet_data = mock_ics20_data(876543210, denom, "", Some(swap));
+
+        // prepare some mock packets
+        let swap_packet = mock_ibc_rcv_packet(send_channel, &swap_packet_data);
+
+        // we transfer some tokens
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(987654321, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // query channel state|_|
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(987654321, denom)]);
+        assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
+
+        // Swap action
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), swap_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+
+        // Simulate swap reply
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_ID), r.events, r.data);
+
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let gamm_ack: AmountResultAck = get_ack_result(&res.data.unwrap()).unwrap();
+        let gamm_ack_exp = AmountResultAck {
+            amount: Uint128::new(36601070u128),
+            denom: swap_denom.to_string(),
+        };
+        assert_eq!(gamm_ack, gamm_ack_exp);
+
+        // query channel state
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(111111111, denom),
+                Amount::native(36601070, swap_denom)
+            ]
+        );
+        assert_eq!(
+            state.total_sent,
+            vec![
+                Amount::native(987654321, denom),
+                Amount::native(36601070, swap_denom)
+            ]
+        );
+
+        // the swap output is held for the sender
+        assert_eq!(36601070, held_balance(&deps, swap_denom));
+    }
+
+    #[test]
+    fn reply_swap_denom_out() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uatom";
+        let swap_denom = "uosmo";
+
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(5000, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let route = |pool_id: u8, token_out_denom: &str| SwapAmountInRoute {
+            pool_id: pool_id.into(),
+            token_out_denom: token_out_denom.to_string(),
+        };
+        let data = Binary::from_base64("CggzNjYwMTA3MA==").unwrap();
+        let mut run_swap = |routes: Vec<SwapAmountInRoute>, tokens_out: &[&str]| {
+            let swap = OsmoPacket::Swap(SwapPacket {
+                routes,
+                token_out_min_amount: Some(1u8.into()),
+                max_slippage: None,
+                reference_price: None,
+                return_to: None,
+            });
+            let packet = mock_rcv_action_packet(swap, send_channel, 1000, denom);
+            ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+            let events = tokens_out
+                .iter()
+                .map(|tokens_out| Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, *tokens_out))
+                .collect();
+            let id = last_reply_id(&deps, SWAP_ID);
+            let reply_msg = mock_reply_msg(id, events, Some(data.clone()));
+            let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+            from_binary::<Ics20Ack>(&res.data.unwrap()).unwrap()
+        };
+
+        // the output denom is read from the request, not from the coins of the events
+        let ack = run_swap(vec![route(1, swap_denom)], &["5uatom,36601070uosmo"]);
+        let expected = AmountResultAck {
+            amount: Uint128::new(36601070),
+            denom: swap_denom.to_string(),
+        };
+        assert_eq!(ack, Ics20Ack::Result(to_binary(&expected).unwrap()));
+
+        // an output in another denom is rejected and the input refunded
+        let ack = run_swap(vec![route(1, swap_denom)], &["36601070uion"]);
+        let err = ContractError::GammResultMismatch {
+            denom: swap_denom.to_string(),
+            value: "36601070uion".to_string(),
+        };
+        assert_eq!(ack, error_ack("swap", err));
+
+        // the events are optional, the response gives the output
+        let ack = run_swap(vec![route(1, swap_denom)], &[""]);
+        assert_eq!(ack, Ics20Ack::Result(to_binary(&expected).unwrap()));
+
+        // a multi-hop swap is checked against the output of the last hop
+        let routes = vec![route(1, "uion"), route(2, swap_denom)];
+        let ack = run_swap(routes.clone(), &["500uion", "36601070uosmo"]);
+        assert_eq!(ack, Ics20Ack::Result(to_binary(&expected).unwrap()));
+        let ack = run_swap(routes, &["500uosmo", "36601070uion"]);
+        let err = ContractError::GammResultMismatch {
+            denom: swap_denom.to_string(),
+            value: "36601070uion".to_string(),
+        };
+        assert_eq!(ack, error_ack("swap", err));
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(2000, denom),
+                Amount::native(109803210, swap_denom)
+            ]
+        );
+        assert_eq!(109803210, held_balance(&deps, swap_denom));
+    }
+
+    #[test]
+    fn receive_swap_action_return_to() {
+        let send_channel = "channel-9";
+        let return_channel = "channel-7";
+        let mut deps = setup(&["channel-1", return_channel, send_channel]);
+        let denom = "uatom";
+        let swap_denom = "uosmo";
+
+        let mut swap = SwapPacket {
+            routes: vec![SwapAmountInRoute {
+                pool_id: 1u8.into(),
+                token_out_denom: swap_denom.to_string(),
+            }],
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: Some(ReturnTo {
+                channel: "channel-45".to_string(),
+                remote_address: "remote-rcpt".to_string(),
+                timeout: None,
+                memo: None,
+            }),
+        };
+
+        // we transfer some tokens
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(987654321, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // reject unknown return channel
+        let swap_packet =
+            mock_rcv_action_packet(OsmoPacket::Swap(swap.clone()), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), swap_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let no_channel = error_ack(
+            "swap",
+            ContractError::NoSuchChannel {
+                id: "channel-45".to_string(),
+            },
+        );
+        assert_eq!(ack, no_channel);
+
+        // Swap action
+        swap.return_to = Some(ReturnTo {
+            channel: return_channel.to_string(),
+            remote_address: "remote-rcpt".to_string(),
+            timeout: Some(600),
+            memo: None,
+        });
+        let swap_packet = mock_rcv_action_packet(OsmoPacket::Swap(swap), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), swap_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+
+        // Simulate swap reply, output is sent back
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_ID), r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let gamm_ack: AmountResultAck = get_ack_result(res.data.as_ref().unwrap()).unwrap();
+        assert_eq!(gamm_ack.amount, Uint128::new(36601070));
+
+        match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            }) => {
+                let expected_timeout = mock_env().block.time.plus_seconds(600);
+                assert_eq!(timeout, &expected_timeout.into());
+                assert_eq!(channel_id.as_str(), return_channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::new(36601070));
+                assert_eq!(packet.denom.as_str(), swap_denom);
+                assert_eq!(packet.sender, mock_env().contract.address.to_string());
+                assert_eq!(packet.receiver.as_str(), "remote-rcpt");
+            }
+            msg => panic!("Unexpected return message: {:?}", msg),
+        };
+        let return_packet = sent_packet(deps.as_mut(), res, return_channel, 4);
+
+        // output is credited to the return channel
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(987653321, denom)]);
+        let state = query_channel(deps.as_ref(), return_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(36601070, swap_denom)]);
+        assert_eq!(0, held_balance(&deps, swap_denom));
+
+        // return transfer timeout credits the output back to the remote sender
+        let timeout_msg = IbcPacketTimeoutMsg::new(return_packet);
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(36601070, held_balance(&deps, swap_denom));
+        let state = query_channel(deps.as_ref(), return_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(0, swap_denom)]);
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(987653321, denom),
+                Amount::native(36601070, swap_denom)
+            ]
+        );
+        let msg = QueryMsg::Transfer {
+            channel: return_channel.to_string(),
+            sequence: 4,
+        };
+        let res: TransferResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.info.status, TransferStatus::Refunded);
+        let refund_to = HeldBy {
+            channel: send_channel.to_string(),
+            sender: "remote-sender".to_string(),
+        };
+        assert_eq!(res.info.refund_to, Some(refund_to));
+    }
+
+    #[test]
+    fn rate_limit_return_to() {
+        let send_channel = "channel-9";
+        let return_channel = "channel-7";
+        let mut deps = setup(&["channel-1", return_channel, send_channel]);
+        let denom = "uatom";
+        let swap_denom = "uosmo";
+
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(987654321, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::SetRateLimit(RateLimitMsg {
+            channel: return_channel.to_string(),
+            denom: swap_denom.to_string(),
+            max_inflow: Uint128::new(1000),
+            max_outflow: Uint128::new(40000000),
+            period: 86400,
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(GOV_CONTRACT, &[]), msg).unwrap();
+        let remaining_outflow = |deps: Deps| -> u128 {
+            let msg = QueryMsg::RateLimit {
+                channel: return_channel.to_string(),
+                denom: swap_denom.to_string(),
+            };
+            let res: RateLimitResponse =
+                from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            res.remaining_outflow.u128()
+        };
+
+        let swap = |deps: DepsMut| {
+            let swap = SwapPacket {
+                routes: vec![SwapAmountInRoute {
+                    pool_id: 1u8.into(),
+                    token_out_denom: swap_denom.to_string(),
+                }],
+                token_out_min_amount: Some(1u8.into()),
+                max_slippage: None,
+                reference_price: None,
+                return_to: Some(ReturnTo {
+                    channel: return_channel.to_string(),
+                    remote_address: "remote-rcpt".to_string(),
+                    timeout: None,
+                    memo: None,
+                }),
+            };
+            let packet = mock_rcv_action_packet(OsmoPacket::Swap(swap), send_channel, 1000, denom);
+            ibc_packet_receive(deps, mock_env(), packet).unwrap();
+        };
+
+        // the output sent back counts in the outflow of the return channel
+        swap(deps.as_mut());
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_ID), r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let return_packet = sent_packet(deps.as_mut(), res, return_channel, 1);
+        assert_eq!(3398930, remaining_outflow(deps.as_ref()));
+
+        // over the limit, the output is held for the remote sender
+        swap(deps.as_mut());
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_ID), r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(36601070, held_balance(&deps, swap_denom));
+        assert_eq!(3398930, remaining_outflow(deps.as_ref()));
+
+        // a failed return transfer gives its outflow back
+        let timeout_msg = IbcPacketTimeoutMsg::new(return_packet);
+        ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        assert_eq!(73202140, held_balance(&deps, swap_denom));
+        assert_eq!(40000000, remaining_outflow(deps.as_ref()));
+    }
+
+    // Replies to the send packet submessage with its sequence, returns the packet sent
+    fn sent_packet(deps: DepsMut, res: Response, channel: &str, sequence: u64) -> IbcPacket {
+        let submsg = res.messages[0].clone();
+        assert_eq!(reply_kind(submsg.id), SEND_PACKET_ID);
+        assert_eq!(submsg.reply_on, ReplyOn::Success);
+        let data = match submsg.msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+            msg => panic!("Unexpected message: {:?}", msg),
+        };
+
+        let event = Event::new("send_packet")
+            .add_attribute("packet_src_channel", channel)
+            .add_attribute("packet_sequence", sequence.to_string());
+        let reply_msg = mock_reply_msg(submsg.id, vec![event], None);
+        reply(deps, mock_env(), reply_msg).unwrap();
+
+        IbcPacket::new(
+            data,
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: "channel-1234".to_string(),
+            },
+            sequence,
+            Timestamp::from_seconds(1665321069).into(),
+        )
+    }
+
+    fn callback_msg(callback: &str, submsg: &SubMsg) -> CallbackMsg {
+        assert_eq!(submsg.reply_on, ReplyOn::Error);
+        assert_eq!(submsg.gas_limit, Some(CALLBACK_GAS_LIMIT));
+        match &submsg.msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            }) => {
+                assert_eq!(contract_addr, callback);
+                from_binary(msg).unwrap()
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn transfer_with_action_callback() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uosmo";
+        let callback = "callback-contract";
+
+        let swap = OsmoPacket::Swap(SwapPacket {
+            routes: vec![SwapAmountInRoute {
+                pool_id: 1u8.into(),
+                token_out_denom: "ujuno".to_string(),
+            }],
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: None,
+        });
+        let transfer = |deps: DepsMut, callback: Option<&str>| {
+            let msg = ExecuteMsg::TransferWithAction(TransferWithActionMsg {
+                channel: send_channel.to_string(),
+                remote_address: "remote-contract".to_string(),
+                timeout: None,
+                action: swap.clone(),
+                callback: callback.map(String::from),
+            });
+            let info = mock_info("local-sender", &coins(1000, denom));
+            execute(deps, mock_env(), info, msg).unwrap()
+        };
+        // the action is sent in the packet
+        let res = transfer(deps.as_mut(), Some(callback));
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 5);
+        let data: Ics20Packet = from_binary(&packet.data).unwrap();
+        assert_eq!(data.action, Some(swap.clone()));
+        assert_eq!(data.sender, "local-sender");
+        assert_eq!(data.receiver, "remote-contract");
+        assert!(ACK_CALLBACKS.has(&deps.storage, (send_channel, 5)));
+
+        // the action result is sent to the callback
+        let result = to_binary(&AmountResultAck {
+            amount: Uint128::new(500),
+            denom: "ujuno".to_string(),
+        })
+        .unwrap();
+        let ack = to_binary(&Ics20Ack::Result(result.clone())).unwrap();
+        let ack_msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack), packet);
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), ack_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let expected = CallbackMsg::ActionAck(ActionAckMsg {
+            channel: send_channel.to_string(),
+            packet_sequence: 5,
+            action: "swap".to_string(),
+            result: ActionAckResult::Success { data: result },
+        });
+        assert_eq!(callback_msg(callback, &res.messages[0]), expected);
+        assert!(!ACK_CALLBACKS.has(&deps.storage, (send_channel, 5)));
+
+        // a failed action is refunded before the callback
+        let res = transfer(deps.as_mut(), Some(callback));
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 6);
+        let ack = ack_error("swap", &ContractError::InsufficientFunds {});
+        let ack_msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack), packet);
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), ack_msg).unwrap();
+        assert_eq!(2, res.messages.len());
+        assert_eq!(
+            res.messages[0].msg,
+            BankMsg::Send {
+                to_address: "local-sender".to_string(),
+                amount: coins(1000, denom),
+            }
+            .into()
+        );
+        let expected = CallbackMsg::ActionAck(ActionAckMsg {
+            channel: send_channel.to_string(),
+            packet_sequence: 6,
+            action: "swap".to_string(),
+            result: ActionAckResult::Error {
+                code: Some(ErrorCode::InsufficientFunds as u32),
+                details: ContractError::InsufficientFunds {}.to_string(),
+            },
+        });
+        assert_eq!(callback_msg(callback, &res.messages[1]), expected);
+
+        // a timeout is refunded as well
+        let res = transfer(deps.as_mut(), Some(callback));
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 7);
+        let timeout_msg = IbcPacketTimeoutMsg::new(packet);
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        assert_eq!(2, res.messages.len());
+        match callback_msg(callback, &res.messages[1]) {
+            CallbackMsg::ActionAck(ack) => assert_eq!(ack.result, ActionAckResult::Timeout {}),
+            msg => panic!("Unexpected callback: {:?}", msg),
+        }
+
+        // a failing callback doesn't fail the ack
+        let reply_msg = Reply {
+            id: CALLBACK_ID,
+            result: SubMsgResult::Err("unknown variant".to_string()),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+
+        // without callback, the packet is only tracked
+        let res = transfer(deps.as_mut(), None);
+        sent_packet(deps.as_mut(), res, send_channel, 8);
+        assert!(TRANSFERS.has(&deps.storage, (send_channel, 8)));
+        assert!(!ACK_CALLBACKS.has(&deps.storage, (send_channel, 8)));
+    }
+
+    #[test]
+    fn transfer_callback() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uosmo";
+        let callback = "vault-contract";
+
+        let transfer = |deps: DepsMut| {
+            let msg = ExecuteMsg::Transfer(TransferMsg {
+                channel: send_channel.to_string(),
+                remote_address: "my-remote-address".to_string(),
+                timeout: None,
+                callback: Some(callback.to_string()),
+            });
+            let info = mock_info("local-sender", &coins(1000, denom));
+            execute(deps, mock_env(), info, msg).unwrap()
+        };
+
+        // the ack data is sent to the callback
+        let res = transfer(deps.as_mut());
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 3);
+        let ack_msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack_success()), packet);
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), ack_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let expected = CallbackMsg::IbcTransferCallback(IbcTransferCallback {
+            channel: send_channel.to_string(),
+            packet_sequence: 3,
+            success: true,
+            ack_data: Some(b"1".into()),
+            error: None,
+        });
+        assert_eq!(callback_msg(callback, &res.messages[0]), expected);
+
+        // the error is sent after the refund
+        let res = transfer(deps.as_mut());
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 4);
+        let ack = ack_fail("transfer", "invalid receiver".to_string());
+        let ack_msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack), packet);
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), ack_msg).unwrap();
+        assert_eq!(2, res.messages.len());
+        match callback_msg(callback, &res.messages[1]) {
+            CallbackMsg::IbcTransferCallback(msg) => {
+                assert_eq!(msg.packet_sequence, 4);
+                assert!(!msg.success);
+                assert_eq!(msg.ack_data, None);
+                assert!(msg.error.unwrap().contains("invalid receiver"));
+            }
+            msg => panic!("Unexpected callback: {:?}", msg),
+        }
+
+        // a timeout is reported as an error
+        let res = transfer(deps.as_mut());
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 5);
+        let timeout_msg = IbcPacketTimeoutMsg::new(packet);
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        assert_eq!(2, res.messages.len());
+        let expected = CallbackMsg::IbcTransferCallback(IbcTransferCallback {
+            channel: send_channel.to_string(),
+            packet_sequence: 5,
+            success: false,
+            ack_data: None,
+            error: Some("timeout".to_string()),
+        });
+        assert_eq!(callback_msg(callback, &res.messages[1]), expected);
+    }
+
+    #[test]
+    fn track_transfers() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uosmo";
+
+        let transfer = |deps: DepsMut, sender: &str, amount: u128| {
+            let msg = ExecuteMsg::Transfer(TransferMsg {
+                channel: send_channel.to_string(),
+                remote_address: "my-remote-address".to_string(),
+                timeout: None,
+                callback: None,
+            });
+            let info = mock_info(sender, &coins(amount, denom));
+            execute(deps, mock_env(), info, msg).unwrap()
+        };
+        let pending = |deps: Deps, sender: &str, start_after: Option<u64>| -> Vec<u64> {
+            let msg = QueryMsg::PendingTransfers {
+                sender: sender.to_string(),
+                start_after: start_after.map(|seq| (send_channel.to_string(), seq)),
+                limit: None,
+            };
+            let res: PendingTransfersResponse =
+                from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            res.transfers.into_iter().map(|t| t.sequence).collect()
+        };
+        let status = |deps: Deps, sequence: u64| -> TransferStatus {
+            let msg = QueryMsg::Transfer {
+                channel: send_channel.to_string(),
+                sequence,
+            };
+            let res: TransferResponse =
+                from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            res.info.status
+        };
+
+        let res = transfer(deps.as_mut(), "local-sender", 1000);
+        let delivered = sent_packet(deps.as_mut(), res, send_channel, 1);
+        let res = transfer(deps.as_mut(), "local-sender", 2000);
+        let refunded = sent_packet(deps.as_mut(), res, send_channel, 2);
+        let res = transfer(deps.as_mut(), "local-sender", 3000);
+        sent_packet(deps.as_mut(), res, send_channel, 3);
+        let res = transfer(deps.as_mut(), "other-sender", 4000);
+        sent_packet(deps.as_mut(), res, send_channel, 4);
+
+        let msg = QueryMsg::Transfer {
+            channel: send_channel.to_string(),
+            sequence: 2,
+        };
+        let res: TransferResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let expected = TransferInfo {
+            sender: "local-sender".to_string(),
+            receiver: "my-remote-address".to_string(),
+            denom: denom.to_string(),
+            amount: Uint128::new(2000),
+            timeout: mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT),
+            status: TransferStatus::Pending,
+            refund_to: None,
+            sent_at: Some(mock_env().block.time),
+        };
+        assert_eq!(res.info, expected);
+        assert_eq!(pending(deps.as_ref(), "local-sender", None), vec![1, 2, 3]);
+        assert_eq!(pending(deps.as_ref(), "local-sender", Some(1)), vec![2, 3]);
+        assert_eq!(pending(deps.as_ref(), "other-sender", None), vec![4]);
+
+        // acked and timed out packets are no longer pending
+        let ack_msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack_success()), delivered);
+        ibc_packet_ack(deps.as_mut(), mock_env(), ack_msg).unwrap();
+        let timeout_msg = IbcPacketTimeoutMsg::new(refunded);
+        ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        assert_eq!(status(deps.as_ref(), 1), TransferStatus::Delivered);
+        assert_eq!(status(deps.as_ref(), 2), TransferStatus::Refunded);
+        assert_eq!(status(deps.as_ref(), 3), TransferStatus::Pending);
+        assert_eq!(pending(deps.as_ref(), "local-sender", None), vec![3]);
+
+        // unknown transfers are not found
+        let msg = QueryMsg::Transfer {
+            channel: send_channel.to_string(),
+            sequence: 5,
+        };
+        query(deps.as_ref(), mock_env(), msg).unwrap_err();
+    }
+
+    #[test]
+    fn channel_status() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uosmo";
+
+        let transfer = |deps: DepsMut| {
+            let msg = ExecuteMsg::Transfer(TransferMsg {
+                channel: send_channel.to_string(),
+                remote_address: "my-remote-address".to_string(),
+                timeout: None,
+                callback: None,
+            });
+            let info = mock_info("local-sender", &coins(1000, denom));
+            execute(deps, mock_env(), info, msg)
+        };
+        let freeze = |deps: DepsMut, sender: &str, msg: ExecuteMsg| {
+            execute(deps, mock_env(), mock_info(sender, &[]), msg)
+        };
+        let status = |deps: Deps| {
+            CHANNEL_INFO
+                .load(deps.storage, send_channel)
+                .unwrap()
+                .status
+        };
+        transfer(deps.as_mut()).unwrap();
+
+        // only the admin freezes a channel
+        let msg = ExecuteMsg::FreezeChannel {
+            channel: send_channel.to_string(),
+        };
+        freeze(deps.as_mut(), "anyone", msg.clone()).unwrap_err();
+        freeze(deps.as_mut(), GOV_CONTRACT, msg).unwrap();
+        assert_eq!(status(deps.as_ref()), ChannelStatus::Frozen);
+
+        // no packets are sent or received on a frozen channel
+        let err = transfer(deps.as_mut()).unwrap_err();
+        let frozen = ContractError::ChannelFrozen {
+            id: send_channel.to_string(),
+        };
+        assert_eq!(err, frozen);
+        let recv_packet = mock_receive_packet(send_channel, 500, denom, "local-rcpt");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(ack, error_ack("transfer", frozen));
+        assert_eq!(res.messages.len(), 0);
+
+        let msg = ExecuteMsg::UnfreezeChannel {
+            channel: send_channel.to_string(),
+        };
+        freeze(deps.as_mut(), GOV_CONTRACT, msg).unwrap();
+        assert_eq!(status(deps.as_ref()), ChannelStatus::Open);
+        transfer(deps.as_mut()).unwrap();
+
+        // the counterparty can close the channel, but the contract never does
+        let channel = mock_ibc_channel(send_channel, ICS20_ORDERING, ICS20_VERSION);
+        let close_msg = IbcChannelCloseMsg::new_init(channel.clone());
+        let err = ibc_channel_close(deps.as_mut(), mock_env(), close_msg).unwrap_err();
+        assert_eq!(err, ContractError::CannotClose {});
+        let close_msg = IbcChannelCloseMsg::new_confirm(channel);
+        ibc_channel_close(deps.as_mut(), mock_env(), close_msg).unwrap();
+        assert_eq!(status(deps.as_ref()), ChannelStatus::Closed);
+
+        // the balance is kept to refund the packets in flight
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(2000, denom)]);
+        let closed = ContractError::ChannelClosed {
+            id: send_channel.to_string(),
+        };
+        assert_eq!(transfer(deps.as_mut()).unwrap_err(), closed);
+        let msg = ExecuteMsg::UnfreezeChannel {
+            channel: send_channel.to_string(),
+        };
+        assert_eq!(
+            freeze(deps.as_mut(), GOV_CONTRACT, msg).unwrap_err(),
+            closed
+        );
+    }
+
+    #[test]
+    fn rate_limits() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uosmo";
+
+        let transfer = |deps: DepsMut, env: Env, amount: u128| {
+            let msg = ExecuteMsg::Transfer(TransferMsg {
+                channel: send_channel.to_string(),
+                remote_address: "my-remote-address".to_string(),
+                timeout: None,
+                callback: None,
+            });
+            let info = mock_info("local-sender", &coins(amount, denom));
+            execute(deps, env, info, msg)
+        };
+        let quota = |deps: Deps, env: Env| -> (u128, u128) {
+            let msg = QueryMsg::RateLimit {
+                channel: send_channel.to_string(),
+                denom: denom.to_string(),
+            };
+            let res: RateLimitResponse = from_binary(&query(deps, env, msg).unwrap()).unwrap();
+            (res.remaining_inflow.u128(), res.remaining_outflow.u128())
+        };
+
+        // only the admin sets rate limits
+        let msg = ExecuteMsg::SetRateLimit(RateLimitMsg {
+            channel: send_channel.to_string(),
+            denom: denom.to_string(),
+            max_inflow: Uint128::new(1000),
+            max_outflow: Uint128::new(5000),
+            period: 86400,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(GOV_CONTRACT, &[]), msg).unwrap();
+        assert_eq!(quota(deps.as_ref(), mock_env()), (1000, 5000));
+
+        // transfers out are limited
+        let res = transfer(deps.as_mut(), mock_env(), 4000).unwrap();
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 1);
+        let err = transfer(deps.as_mut(), mock_env(), 1001).unwrap_err();
+        let expected = ContractError::RateLimitExceeded {
+            channel: send_channel.to_string(),
+            denom: denom.to_string(),
+            remaining: Uint128::new(1000),
+        };
+        assert_eq!(err, expected);
+        assert_eq!(quota(deps.as_ref(), mock_env()), (1000, 1000));
+
+        // transfers in are limited, the channel balance is kept on error
+        let recv_packet = mock_receive_packet(send_channel, 1001, denom, "local-rcpt");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let expected = ContractError::RateLimitExceeded {
+            channel: send_channel.to_string(),
+            denom: denom.to_string(),
+            remaining: Uint128::new(1000),
+        };
+        assert_eq!(ack, error_ack("transfer", expected));
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(4000, denom)]);
+
+        let recv_packet = mock_receive_packet(send_channel, 600, denom, "local-rcpt");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(quota(deps.as_ref(), mock_env()), (400, 1000));
+
+        // a failed receive gives the quota back
+        let reply_msg = Reply {
+            id: res.messages[0].id,
+            result: SubMsgResult::Err("bank error".to_string()),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(quota(deps.as_ref(), mock_env()), (1000, 1000));
+
+        // so does a refund
+        let timeout_msg = IbcPacketTimeoutMsg::new(packet);
+        ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        assert_eq!(quota(deps.as_ref(), mock_env()), (1000, 5000));
+
+        // the flows leave the rolling period a bucket at a time, so the full
+        // quota can't be spent again right after the end of the period
+        let res = transfer(deps.as_mut(), mock_env(), 5000).unwrap();
+        let packet = sent_packet(deps.as_mut(), res, send_channel, 2);
+        assert_eq!(quota(deps.as_ref(), mock_env()), (1000, 0));
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(86400);
+        assert_eq!(quota(deps.as_ref(), env.clone()), (1000, 0));
+        transfer(deps.as_mut(), env.clone(), 1).unwrap_err();
+        env.block.time = env.block.time.plus_seconds(8640);
+        assert_eq!(quota(deps.as_ref(), env.clone()), (1000, 5000));
+
+        // the refund of a packet sent in a past period gives nothing back
+        transfer(deps.as_mut(), env.clone(), 5000).unwrap();
+        assert_eq!(quota(deps.as_ref(), env.clone()), (1000, 0));
+        let timeout_msg = IbcPacketTimeoutMsg::new(packet);
+        ibc_packet_timeout(deps.as_mut(), env.clone(), timeout_msg).unwrap();
+        assert_eq!(quota(deps.as_ref(), env), (1000, 0));
+
+        let msg = QueryMsg::ListRateLimits {
+            channel: send_channel.to_string(),
+        };
+        let res: ListRateLimitsResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.limits.len(), 1);
+        assert_eq!(res.limits[0].denom, denom);
+
+        // without limit, the transfers are not checked
+        let msg = ExecuteMsg::RemoveRateLimit {
+            channel: send_channel.to_string(),
+            denom: denom.to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(GOV_CONTRACT, &[]), msg).unwrap();
+        transfer(deps.as_mut(), mock_env(), 10000).unwrap();
+    }
+
+    #[test]
+    fn allowed_pools_and_denoms() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let denom = "uatom";
+        let shares_denom = "gamm/pool/2";
+
+        let swap = |pool_id: u8, token_out_denom: &str| {
+            OsmoPacket::Swap(SwapPacket {
+                routes: vec![SwapAmountInRoute {
+                    pool_id: pool_id.into(),
+                    token_out_denom: token_out_denom.to_string(),
+                }],
+                token_out_min_amount: Some(1u8.into()),
+                max_slippage: None,
+                reference_price: None,
+                return_to: None,
+            })
+        };
+        let exit_pool = OsmoPacket::ExitPool(ExitPoolPacket {
+            token_out_denom: denom.into(),
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: None,
+        });
+        let admin = |deps: DepsMut, msg: ExecuteMsg| {
+            execute(deps, mock_env(), mock_info(GOV_CONTRACT, &[]), msg).unwrap();
+        };
+        let allowed_pools = |deps: Deps| -> ListAllowedPoolsResponse {
+            let msg = QueryMsg::ListAllowedPools {
+                start_after: None,
+                limit: None,
+            };
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        transfer_native(deps.as_mut(), send_channel, 3000, denom);
+        transfer_native(deps.as_mut(), send_channel, 1000, shares_denom);
+
+        // any pool can be used until the config restricts them
+        let packet = mock_rcv_action_packet(swap(1, "uosmo"), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+
+        // only the admin allows pools
+        let msg = ExecuteMsg::AllowPools {
+            pool_ids: vec![1u8.into()],
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        admin(deps.as_mut(), msg);
+        let msg = ExecuteMsg::UpdateConfig {
+            default_timeout: None,
+            lockup_id: None,
+            restrict_pools: Some(true),
+        };
+        admin(deps.as_mut(), msg);
+        let expected = ListAllowedPoolsResponse {
+            restricted: true,
+            pool_ids: vec![1u8.into()],
+        };
+        assert_eq!(allowed_pools(deps.as_ref()), expected);
+
+        let packet = mock_rcv_action_packet(swap(1, "uosmo"), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+
+        // the other pools are rejected before any message is built, the exited pool included
+        let packet = mock_rcv_action_packet(swap(2, "uosmo"), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let not_allowed = ContractError::PoolNotAllowed { pool_id: 2 };
+        assert_eq!(ack, error_ack("swap", not_allowed));
+
+        let packet = mock_rcv_action_packet(exit_pool.clone(), send_channel, 1000, shares_denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let not_allowed = ContractError::PoolNotAllowed { pool_id: 2 };
+        assert_eq!(ack, error_ack("exit_pool", not_allowed));
+
+        // the pipeline ack sets the index of the step using the pool
+        let pipeline = OsmoPacket::Pipeline(vec![swap(1, "uosmo"), swap(2, "uion")]);
+        let packet = mock_rcv_action_packet(pipeline, send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let not_allowed = ContractError::PipelineStepFailed {
+            index: 1,
+            source: Box::new(ContractError::PoolNotAllowed { pool_id: 2 }),
+        };
+        assert_eq!(ack, error_ack("pipeline", not_allowed));
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(1000, shares_denom),
+                Amount::native(1000, denom)
+            ]
+        );
+
+        admin(
+            deps.as_mut(),
+            ExecuteMsg::AllowPools {
+                pool_ids: vec![2u8.into()],
+            },
+        );
+        let packet = mock_rcv_action_packet(exit_pool, send_channel, 1000, shares_denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        check_gamm_submsg(res.messages[0].clone(), EXIT_POOL_ID, "exit").unwrap();
+
+        admin(
+            deps.as_mut(),
+            ExecuteMsg::DisallowPools {
+                pool_ids: vec![1u8.into()],
+            },
+        );
+        let expected = ListAllowedPoolsResponse {
+            restricted: true,
+            pool_ids: vec![2u8.into()],
+        };
+        assert_eq!(allowed_pools(deps.as_ref()), expected);
+
+        // once a denom is allowed on the channel, the others cannot be output
+        admin(
+            deps.as_mut(),
+            ExecuteMsg::AllowDenoms {
+                channel: send_channel.to_string(),
+                denoms: vec!["uion".to_string()],
+            },
+        );
+        let msg = QueryMsg::ListAllowedDenoms {
+            channel: send_channel.to_string(),
+            start_after: None,
+            limit: None,
+        };
+        let res: ListAllowedDenomsResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.denoms, vec!["uion".to_string()]);
+
+        transfer_native(deps.as_mut(), send_channel, 1000, denom);
+        let packet = mock_rcv_action_packet(swap(2, "uosmo"), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let not_allowed = ContractError::DenomNotAllowed {
+            denom: "uosmo".to_string(),
+        };
+        assert_eq!(ack, error_ack("swap", not_allowed));
+
+        let packet = mock_rcv_action_packet(swap(2, "uion"), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+
+        // any denom can be output again once none is allowed
+        admin(
+            deps.as_mut(),
+            ExecuteMsg::DisallowDenoms {
+                channel: send_channel.to_string(),
+                denoms: vec!["uion".to_string()],
+            },
+        );
+        transfer_native(deps.as_mut(), send_channel, 1000, denom);
+        let packet = mock_rcv_action_packet(swap(2, "uosmo"), send_channel, 1000, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+    }
+
+    fn transfer_native(deps: DepsMut, channel: &str, amount: u128, denom: &str) {
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(amount, denom));
+        execute(deps, mock_env(), info, msg).unwrap();
+    }
+
+    fn held_balance<Q: Querier>(deps: &OwnedDeps<MockStorage, MockApi, Q>, denom: &str) -> u128 {
+        USER_BALANCES
+            .may_load(&deps.storage, ("channel-9", "remote-sender", denom))
+            .unwrap()
+            .unwrap_or_default()
+            .u128()
+    }
+
+    #[test]
+    fn receive_external_token() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let local_denom = "ibc/46B44899322F3CD854D2D46DEEF881958467CDD4B3B10086DA49296BBED94BED";
+        let msg = ExecuteMsg::AddExternalToken(ExternalTokenMsg {
+            channel: send_channel.to_string(),
+            denom: "ujuno".to_string(),
+            local_denom: local_denom.to_string(),
+        });
+        let info = mock_info(GOV_CONTRACT, &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // the remote native denom has no prefix
+        let data = Ics20Packet {
+            denom: "ujuno".to_string(),
+            amount: Uint128::new(600),
+            sender: "remote-sender".to_string(),
+            receiver: "local-rcpt".to_string(),
+            memo: None,
+            action: None,
+        };
+        let packet = mock_ibc_rcv_packet(send_channel, &data);
+
+        // the same denom from another chain is not the external token
+        let other = mock_ibc_rcv_packet("channel-1", &data);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), other).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(
+            ack,
+            error_ack("transfer", ContractError::NoForeignTokens {})
+        );
+
+        // no reserve to pay out yet
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet.clone()).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(
+            ack,
+            error_ack("transfer", ContractError::InsufficientFunds {})
+        );
+
+        // only external tokens can be added to the reserve
+        let reserve = ExecuteMsg::AddReserve(ReserveMsg {
+            channel: send_channel.to_string(),
+        });
+        let info = mock_info("local-sender", &coins(1000, "uatom"));
+        let err = execute(deps.as_mut(), mock_env(), info, reserve.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NoAllowedToken {});
+        let info = mock_info("local-sender", &coins(1000, local_denom));
+        execute(deps.as_mut(), mock_env(), info, reserve).unwrap();
+
+        // the local denom is paid out from the reserve
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+        let expected = BankMsg::Send {
+            to_address: "local-rcpt".to_string(),
+            amount: coins(600, local_denom),
+        };
+        assert_eq!(res.messages[0].msg, expected.into());
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(400, local_denom)]);
+
+        // sent back with our prefix, so the remote chain releases its native tokens
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(500, local_denom));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let contract_port = format!("wasm.{}", mock_env().contract.address);
+        let data = match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                let denom = format!("{}/{}/ujuno", contract_port, send_channel);
+                assert_eq!(packet.denom, denom);
+                data.clone()
+            }
+            msg => panic!("Unexpected transfer message: {:?}", msg),
+        };
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(900, local_denom)]);
+
+        // failed transfers refund the local denom
+        let timeout_msg = IbcPacketTimeoutMsg::new(IbcPacket::new(
+            data,
+            IbcEndpoint {
+                port_id: contract_port,
+                channel_id: send_channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: "channel-1234".to_string(),
+            },
+            4,
+            Timestamp::from_seconds(1665321069).into(),
+        ));
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout_msg).unwrap();
+        let expected = BankMsg::Send {
+            to_address: "local-sender".to_string(),
+            amount: coins(500, local_denom),
+        };
+        assert_eq!(res.messages[0].msg, expected.into());
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(400, local_denom)]);
+    }
+
+    #[test]
+    fn receive_multi_join_pool_action() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let shares_denom = "gamm/pool/1";
+
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uatom");
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uosmo");
+
+        // the second asset arrives first and is held for the sender
+        let deposit_packet =
+            mock_rcv_action_packet(OsmoPacket::Deposit {}, send_channel, 5000000, "uosmo");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), deposit_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+        assert_eq!(5000000, held_balance(&deps, "uosmo"));
+
+        let join = MultiJoinPoolPacket {
+            pool_id: 1u8.into(),
+            share_out_amount: 74196993097318119147u128.into(),
+            token_in_maxs: vec![Coin::new(10000000, "uatom"), Coin::new(5000000, "uosmo")],
+        };
+
+        // cannot use more than the held funds
+        let mut invalid_join = join.clone();
+        invalid_join.token_in_maxs[1].amount = 5000001u64.into();
+        let invalid_packet = mock_rcv_action_packet(
+            OsmoPacket::MultiJoinPool(invalid_join),
+            send_channel,
+            10000000,
+            "uatom",
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), invalid_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let no_funds = error_ack("multi_join_pool", ContractError::InsufficientHeldFunds {});
+        assert_eq!(ack, no_funds);
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(987654321, "uatom"),
+                Amount::native(987654321, "uosmo")
+            ]
+        );
+
+        // a failed join gives the held funds back
+        let join_packet = mock_rcv_action_packet(
+            OsmoPacket::MultiJoinPool(join.clone()),
+            send_channel,
+            10000000,
+            "uatom",
+        );
+        ibc_packet_receive(deps.as_mut(), mock_env(), join_packet.clone()).unwrap();
+        assert_eq!(0, held_balance(&deps, "uosmo"));
+        let reply_msg = Reply {
+            id: last_reply_id(&deps, MULTI_JOIN_POOL_ID),
+            result: SubMsgResult::Err("pool not found".to_string()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert!(get_ack_result::<Vec<AmountResultAck>>(&res.data.unwrap()).is_err());
+        assert_eq!(5000000, held_balance(&deps, "uosmo"));
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(987654321, "uatom"),
+                Amount::native(987654321, "uosmo")
+            ]
+        );
+
+        // Join action
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), join_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(res.messages[0].clone(), MULTI_JOIN_POOL_ID, "msgjoinpool").unwrap();
+
+        // Simulate join reply (tokens_in: 9000000uatom,4985011uosmo)
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, MULTI_JOIN_POOL_ID),
+            mock_multi_join_pool_events(),
+            None,
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let gamm_ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let gamm_ack_exp = vec![
+            AmountResultAck {
+                amount: Uint128::new(74196993097318119147u128),
+                denom: shares_denom.to_string(),
+            },
+            AmountResultAck {
+                amount: Uint128::new(14989u128),
+                denom: "uosmo".to_string(),
+            },
+            AmountResultAck {
+                amount: Uint128::new(1000000u128),
+                denom: "uatom".to_string(),
+            },
+        ];
+        assert_eq!(gamm_ack, gamm_ack_exp);
+
+        // shares and unused input are held for the sender
+        assert_eq!(74196993097318119147, held_balance(&deps, shares_denom));
+        assert_eq!(14989, held_balance(&deps, "uosmo"));
+        assert_eq!(1000000, held_balance(&deps, "uatom"));
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(74196993097318119147, shares_denom),
+                Amount::native(978654321, "uatom"),
+                Amount::native(982669310, "uosmo")
+            ]
+        );
+    }
+
+    #[test]
+    fn receive_create_balancer_pool_action() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let shares_denom = "gamm/pool/2";
+
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uatom");
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uosmo");
+
+        // the second asset and the pool creation fee are held for the sender
+        let deposit_packet =
+            mock_rcv_action_packet(OsmoPacket::Deposit {}, send_channel, 6500000, "uosmo");
+        ibc_packet_receive(deps.as_mut(), mock_env(), deposit_packet).unwrap();
+
+        let create_pool = CreateBalancerPoolPacket {
+            pool_assets: vec![
+                PoolAsset {
+                    token: Coin::new(9000000, "uatom"),
+                    weight: 1u8.into(),
+                },
+                PoolAsset {
+                    token: Coin::new(5000000, "uosmo"),
+                    weight: 1u8.into(),
+                },
+            ],
+            swap_fee: Decimal::percent(1),
+            exit_fee: Decimal::zero(),
+            future_pool_governor: "".to_string(),
+            pool_creation_fee: vec![Coin::new(1000000, "uosmo")],
+        };
+
+        // a pool has two assets at least
+        let mut invalid_pool = create_pool.clone();
+        invalid_pool.pool_assets.pop();
+        let invalid_packet = mock_rcv_action_packet(
+            OsmoPacket::CreateBalancerPool(invalid_pool),
+            send_channel,
+            9000000,
+            "uatom",
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), invalid_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let invalid = error_ack("create_balancer_pool", ContractError::InvalidPoolParams {});
+        assert_eq!(ack, invalid);
+
+        // the pool is created with the received and held funds
+        let create_packet = mock_rcv_action_packet(
+            OsmoPacket::CreateBalancerPool(create_pool.clone()),
+            send_channel,
+            9000000,
+            "uatom",
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), create_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        let create_msg = CreatePoolMsg {
+            pool: create_pool.clone(),
+            funds: vec![Coin::new(6000000, "uosmo"), Coin::new(9000000, "uatom")],
+        };
+        assert_submsg_wasm(
+            res.messages[0].clone(),
+            CREATE_POOL_ID,
+            ReplyOn::Always,
+            &MOCK_CONTRACT_ADDR.to_string(),
+            ExecuteMsg::CreatePool(create_msg.clone()),
+            vec![],
+        );
+        assert_eq!(500000, held_balance(&deps, "uosmo"));
+        let create_reply_id = last_reply_id(&deps, CREATE_POOL_ID);
+
+        // only the contract itself creates the pools
+        let msg = ExecuteMsg::CreatePool(create_msg.clone());
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        let msg = ExecuteMsg::CreatePool(create_msg);
+        let info = mock_info(MOCK_CONTRACT_ADDR, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(
+            res.messages[0].id,
+            last_reply_id(&deps, CREATE_POOL_CHECK_ID)
+        );
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Success);
+        let msg: MsgCreateBalancerPool = match &res.messages[0].msg {
+            CosmosMsg::Stargate { type_url, value } => {
+                assert!(type_url.to_lowercase().contains("msgcreatebalancerpool"));
+                proto_decode(value.as_slice()).unwrap()
+            }
+            msg => panic!("unexpected msg: {:?}", msg),
+        };
+        let params = msg.pool_params.unwrap();
+        assert_eq!(params.swap_fee, "10000000000000000");
+        assert_eq!(params.exit_fee, "0");
+        assert_eq!(msg.pool_assets.len(), 2);
+
+        // the sender funds cover the spent coins
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, CREATE_POOL_CHECK_ID),
+            mock_create_pool_events(MOCK_CONTRACT_ADDR),
+            None,
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(res.data, None);
+
+        // the pool id is taken from the events when the response is empty
+        let reply_msg = mock_reply_msg(
+            create_reply_id,
+            mock_create_pool_events(MOCK_CONTRACT_ADDR),
+            None,
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let ack: CreatePoolResultAck = get_ack_result(&res.data.unwrap()).unwrap();
+        let expected = CreatePoolResultAck {
+            pool_id: 2u8.into(),
+            shares: AmountResultAck {
+                amount: Uint128::new(100000000000000000000),
+                denom: shares_denom.to_string(),
+            },
+        };
+        assert_eq!(ack, expected);
+        assert_eq!(100000000000000000000, held_balance(&deps, shares_denom));
+        assert_eq!(500000, held_balance(&deps, "uosmo"));
+        assert_eq!(0, held_balance(&deps, "uatom"));
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(100000000000000000000, shares_denom),
+                Amount::native(978654321, "uatom"),
+                Amount::native(981654321, "uosmo")
+            ]
+        );
+
+        // the other senders funds never pay an undeclared pool creation fee
+        let deposit_packet =
+            mock_rcv_action_packet(OsmoPacket::Deposit {}, send_channel, 5000000, "uosmo");
+        ibc_packet_receive(deps.as_mut(), mock_env(), deposit_packet).unwrap();
+        let mut no_fee = create_pool.clone();
+        no_fee.pool_creation_fee = vec![];
+        let create_packet = mock_rcv_action_packet(
+            OsmoPacket::CreateBalancerPool(no_fee.clone()),
+            send_channel,
+            9000000,
+            "uatom",
+        );
+        ibc_packet_receive(deps.as_mut(), mock_env(), create_packet).unwrap();
+        let create_reply_id = last_reply_id(&deps, CREATE_POOL_ID);
+        let msg = ExecuteMsg::CreatePool(CreatePoolMsg {
+            pool: no_fee,
+            funds: vec![Coin::new(5000000, "uosmo"), Coin::new(9000000, "uatom")],
+        });
+        let info = mock_info(MOCK_CONTRACT_ADDR, &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, CREATE_POOL_CHECK_ID),
+            mock_create_pool_events(MOCK_CONTRACT_ADDR),
+            None,
+        );
+        let err = reply(deps.as_mut(), mock_env(), reply_msg).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientHeldFunds {});
+
+        // the reverted pool creation fails the action, the held funds are restored and the
+        // received ones refunded by the error ack
+        assert_eq!(500000, held_balance(&deps, "uosmo"));
+        let reply_msg = Reply {
+            id: create_reply_id,
+            result: SubMsgResult::Err("codespace: wasm, code: 5".to_string()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert!(get_ack_result::<CreatePoolResultAck>(&res.data.unwrap()).is_err());
+        assert_eq!(5500000, held_balance(&deps, "uosmo"));
+        assert_eq!(0, held_balance(&deps, "uatom"));
+
+        // new pools cannot be vetted while the pools are restricted
+        let msg = ExecuteMsg::UpdateConfig {
+            default_timeout: None,
+            lockup_id: None,
+            restrict_pools: Some(true),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(GOV_CONTRACT, &[]), msg).unwrap();
+        let create_packet = mock_rcv_action_packet(
+            OsmoPacket::CreateBalancerPool(create_pool),
+            send_channel,
+            9000000,
+            "uatom",
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), create_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let not_allowed = ContractError::PoolCreationNotAllowed {};
+        assert_eq!(ack, error_ack("create_balancer_pool", not_allowed));
+    }
+
+    #[test]
+    fn receive_multi_exit_pool_action() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", send_channel]);
+        let shares_denom = "gamm/pool/1";
+        let ibc_denom = "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2";
+
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uatom");
+        transfer_native(
+            deps.as_mut(),
+            send_channel,
+            74196993097318119147,
+            shares_denom,
+        );
+
+        let deposit_packet = mock_rcv_action_packet(
+            OsmoPacket::Deposit {},
+            send_channel,
+            74196993097318119147,
+            shares_denom,
+        );
+        ibc_packet_receive(deps.as_mut(), mock_env(), deposit_packet).unwrap();
+
+        let exit = OsmoPacket::MultiExitPool(MultiExitPoolPacket {
+            pool_id: 1u8.into(),
+            share_in_amount: 74196993097318119147u128.into(),
+            token_out_mins: vec![Coin::new(1, ibc_denom), Coin::new(1, "uosmo")],
+        });
+        let exit_packet = mock_rcv_action_packet(exit, send_channel, 1000, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), exit_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(res.messages[0].clone(), MULTI_EXIT_POOL_ID, "msgexitpool").unwrap();
+        assert_eq!(0, held_balance(&deps, shares_denom));
+
+        // Simulate exit reply (tokens_out: 100008ibc/27394...,4985011uosmo)
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, MULTI_EXIT_POOL_ID),
+            mock_exit_pool_events(),
+            None,
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let gamm_ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let gamm_ack_exp = vec![
+            AmountResultAck {
+                amount: Uint128::new(100008u128),
+                denom: ibc_denom.to_string(),
+            },
+            AmountResultAck {
+                amount: Uint128::new(4985011u128),
+                denom: "uosmo".to_string(),
+            },
+            AmountResultAck {
+                amount: Uint128::new(1000u128),
+                denom: "uatom".to_string(),
+            },
+        ];
+        assert_eq!(gamm_ack, gamm_ack_exp);
+
+        assert_eq!(100008, held_balance(&deps, ibc_denom));
+        assert_eq!(4985011, held_balance(&deps, "uosmo"));
+        assert_eq!(1000, held_balance(&deps, "uatom"));
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(0, shares_denom),
+                Amount::native(100008, ibc_denom),
+                Amount::native(987654321, "uatom"),
+                Amount::native(4985011, "uosmo")
+            ]
+        );
+    }
+
+    #[test]
+    fn receive_pipeline_action() {
+        let send_channel = "channel-9";
+        let shares_denom = "gamm/pool/1";
+        let querier = GammQuerier {
+            base: MockQuerier::new(&[]),
+            swap_estimate: 36601070,
+            total_shares: Coin::new(100000000000000000000, shares_denom),
+            liquidity: vec![
+                Coin::new(1000000000, "uatom"),
+                Coin::new(5000000000, "uosmo"),
+            ],
+            spot_prices: vec![
+                ("uatom", "uosmo", Decimal::percent(500)),
+                ("uosmo", "uatom", Decimal::percent(20)),
+            ],
+        };
+        let mut deps = setup_with_querier(&["channel-1", send_channel], querier);
+        let contract = mock_env().contract.address.to_string();
+
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uatom");
+
+        let swap = OsmoPacket::Swap(SwapPacket {
+            routes: vec![SwapAmountInRoute {
+                pool_id: 1u8.into(),
+                token_out_denom: "uosmo".to_string(),
+            }],
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: None,
+        });
+        let join = |share_out_min_amount: u128| {
+            OsmoPacket::JoinPool(JoinPoolPacket {
+                pool_id: 1u8.into(),
+                share_out_min_amount: Some(share_out_min_amount.into()),
+                max_slippage: None,
+                reference_price: None,
+            })
+        };
+        let receive_error = |deps: DepsMut, steps: Vec<OsmoPacket>| -> ErrorAck {
+            let pipeline = OsmoPacket::Pipeline(steps);
+            let packet = mock_rcv_action_packet(pipeline, send_channel, 10000000, "uatom");
+            let res = ibc_packet_receive(deps, mock_env(), packet).unwrap();
+            assert_eq!(0, res.messages.len());
+            match from_binary(&res.acknowledgement).unwrap() {
+                Ics20Ack::Error(err) => from_slice(err.as_bytes()).unwrap(),
+                ack => panic!("Unexpected ack: {:?}", ack),
+            }
+        };
+
+        // lock can only be the last step
+        let lock = OsmoPacket::Lock(LockPacket {
+            duration: 86400u64.into(),
+        });
+        let invalid = OsmoPacket::Pipeline(vec![lock.clone(), swap.clone()]);
+        let invalid_packet = mock_rcv_action_packet(invalid, send_channel, 10000000, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), invalid_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let invalid_step = error_ack("pipeline", ContractError::InvalidPipelineStep { index: 0 });
+        assert_eq!(ack, invalid_step);
+
+        // the steps failing with the pool estimates are reported before running any of them
+        let error = receive_error(deps.as_mut(), vec![swap.clone(), join(366010700000000001)]);
+        let min_not_reached = ContractError::MinAmountNotReached {
+            amount: Uint128::new(366010700000000000),
+            min: Uint128::new(366010700000000001),
+        };
+        let expected = ErrorAck {
+            version: ERROR_ACK_VERSION,
+            code: ErrorCode::SlippageExceeded as u32,
+            action: "pipeline".to_string(),
+            details: format!("Pipeline step 1 failed: {}", min_not_reached),
+            step: Some(1),
+        };
+        assert_eq!(error, expected);
+
+        let error = receive_error(deps.as_mut(), vec![swap.clone(), join(1), lock]);
+        assert_eq!(error.code, ErrorCode::LockupNotFound as u32);
+        assert_eq!(error.step, Some(2));
+
+        // the step failing once dispatched is reported as well, even when its input amount is
+        // unknown: the shares joined are exited, then the exit output isn't a pool share
+        let exit = |token_out_denom: &str| {
+            OsmoPacket::ExitPool(ExitPoolPacket {
+                token_out_denom: token_out_denom.to_string(),
+                token_out_min_amount: Some(1u8.into()),
+                max_slippage: None,
+                reference_price: None,
+                return_to: None,
+            })
+        };
+        let error = receive_error(deps.as_mut(), vec![join(1), exit("uosmo"), exit("uatom")]);
+        let invalid_lp = ContractError::InvalidLpToken {};
+        assert_eq!(error.code, invalid_lp.code() as u32);
+        assert_eq!(
+            error.details,
+            format!("Pipeline step 2 failed: {}", invalid_lp)
+        );
+        assert_eq!(error.step, Some(2));
+        let invalid_step = error_ack("pipeline", ContractError::InvalidPipelineStep { index: 0 });
+        match invalid_step {
+            Ics20Ack::Error(err) => {
+                let error: ErrorAck = from_slice(err.as_bytes()).unwrap();
+                assert_eq!(error.step, Some(0));
+            }
+            ack => panic!("Unexpected ack: {:?}", ack),
+        }
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(987654321, "uatom")]);
+
+        // the steps run in a call to the contract itself
+        let steps = vec![swap, join(1)];
+        let pipeline = OsmoPacket::Pipeline(steps.clone());
+        let pipeline_packet = mock_rcv_action_packet(pipeline, send_channel, 10000000, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), pipeline_packet.clone()).unwrap();
+        assert_eq!(1, res.messages.len());
+        let pipeline_id = res.messages[0].id;
+        let pipeline_msg = PipelineMsg {
+            channel: send_channel.to_string(),
+            sender: "remote-sender".to_string(),
+            token_in: Coin::new(10000000, "uatom"),
+            steps,
+        };
+        assert_submsg_wasm(
+            res.messages[0].clone(),
+            PIPELINE_ID,
+            ReplyOn::Always,
+            &contract,
+            ExecuteMsg::Pipeline(pipeline_msg.clone()),
+            vec![],
+        );
+
+        // only the contract can run the steps
+        let info = mock_info("anyone", &[]);
+        let msg = ExecuteMsg::Pipeline(pipeline_msg.clone());
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        let run_pipeline = |deps: DepsMut| {
+            let info = mock_info(&contract, &[]);
+            let msg = ExecuteMsg::Pipeline(pipeline_msg.clone());
+            execute(deps, mock_env(), info, msg).unwrap()
+        };
+        let res = run_pipeline(deps.as_mut());
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(
+            res.messages[0].clone(),
+            PIPELINE_STEP_ID,
+            "swapexactamountin",
+        )
+        .unwrap();
+        let swap_id = res.messages[0].id;
+        assert!(PIPELINE.has(&deps.storage, swap_id));
+
+        // the swap output is the join input, the progress moves to the join reply
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(swap_id, r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(
+            res.messages[0].clone(),
+            PIPELINE_STEP_ID,
+            "joinswapexternamountin",
+        )
+        .unwrap();
+        let join_id = res.messages[0].id;
+        assert!(!PIPELINE.has(&deps.storage, swap_id));
+        assert!(PIPELINE.has(&deps.storage, join_id));
+
+        let r = mock_join_pool_response();
+        let reply_msg = mock_reply_msg(join_id, r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert!(!PIPELINE.has(&deps.storage, join_id));
+        let results: Vec<StepResultAck> = from_binary(&res.data.unwrap()).unwrap();
+        let results_exp = vec![
+            StepResultAck::Amount(AmountResultAck {
+                amount: Uint128::new(36601070),
+                denom: "uosmo".to_string(),
+            }),
+            StepResultAck::Amount(AmountResultAck {
+                amount: Uint128::new(74196993097318119147),
+                denom: shares_denom.to_string(),
+            }),
+        ];
+        assert_eq!(results, results_exp);
+
+        // the ack reports every step and the last output is held for the sender
+        let data = json_to_reply_proto(&String::from_utf8(to_vec(&results).unwrap()).unwrap());
+        let reply_msg = mock_reply_msg(pipeline_id, vec![], Some(data.into()));
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Vec<StepResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        assert_eq!(ack, results_exp);
+        assert_eq!(74196993097318119147, held_balance(&deps, shares_denom));
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(74196993097318119147, shares_denom),
+                Amount::native(977654321, "uatom")
+            ]
+        );
 
-        match msg.msg {
-            CosmosMsg::Stargate { type_url, .. } => {
-                if !type_url.to_lowercase().contains(action) {
-                    return Err(StdError::generic_err(format!(
-                        "Invalid stargate proto url: {type_url}"
-                    )));
-                }
-            }
-            _ => return Err(StdError::generic_err("Invalid cosmMsg")),
+        // a failing step reverts the whole call
+        let res = run_pipeline(deps.as_mut());
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(res.messages[0].id, r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let reply_msg = Reply {
+            id: res.messages[0].id,
+            result: SubMsgResult::Err("codespace: gamm, code: 7".to_string()),
         };
+        let err = reply(deps.as_mut(), mock_env(), reply_msg).unwrap_err();
+        let expected = ContractError::PipelineStepFailed {
+            index: 1,
+            source: Box::new(StdError::generic_err("codespace: gamm, code: 7").into()),
+        };
+        assert_eq!(err, expected);
 
-        Ok(())
-    }
-
-    fn get_ack_result<T: DeserializeOwned>(data: &Binary) -> StdResult<T> {
-        let ack: Ics20Ack = from_binary(data).unwrap();
-        match ack {
-            Ics20Ack::Result(data) => Ok(from_binary(&data).unwrap()),
-            Ics20Ack::Error(err) => Err(StdError::generic_err(err)),
-        }
+        // the outer reply only sees the redacted error, the balance is given back
+        ibc_packet_receive(deps.as_mut(), mock_env(), pipeline_packet).unwrap();
+        let reply_msg = Reply {
+            id: last_reply_id(&deps, PIPELINE_ID),
+            result: SubMsgResult::Err("codespace: wasm, code: 5".to_string()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let ack: Ics20Ack = from_binary(&res.data.unwrap()).unwrap();
+        let error: ErrorAck = match ack {
+            Ics20Ack::Error(err) => from_slice(err.as_bytes()).unwrap(),
+            ack => panic!("Unexpected ack: {:?}", ack),
+        };
+        assert_eq!(error.action, "pipeline");
+        assert_eq!(error.step, None);
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(74196993097318119147, shares_denom),
+                Amount::native(977654321, "uatom")
+            ]
+        );
     }
 
-    fn mock_reply_msg(id: u64, events: Vec<Event>, data: Option<Binary>) -> Reply {
-        Reply {
-            id,
-            result: SubMsgResult::Ok(SubMsgResponse { events, data }),
-        }
+    fn error_ack(action: &str, err: ContractError) -> Ics20Ack {
+        from_binary(&ack_error(action, &err)).unwrap()
     }
 
-    fn mock_ics20_data(
-        amount: u128,
-        denom: &str,
-        receiver: &str,
-        action: Option<OsmoPacket>,
-    ) -> Ics20Packet {
-        Ics20Packet {
-            // this is returning a foreign (our) token, thus denom is <port>/<channel>/<denom>
-            denom: format!("{REMOTE_PORT}/channel-1234/{denom}"),
-            amount: amount.into(),
-            sender: "remote-sender".to_string(),
-            receiver: receiver.to_string(),
-            action,
+    fn stargate_msg<M: cw_osmo_proto::Message + Default>(msg: &SubMsg) -> M {
+        match &msg.msg {
+            CosmosMsg::Stargate { value, .. } => proto_decode(value.as_slice()).unwrap(),
+            _ => panic!("Unexpected message: {:?}", msg),
         }
     }
 
-    fn mock_receive_packet(
-        my_channel: &str,
-        amount: u128,
-        denom: &str,
-        receiver: &str,
-    ) -> IbcPacketReceiveMsg {
-        let data = mock_ics20_data(amount, denom, receiver, None);
-
-        mock_ibc_rcv_packet(my_channel, &data)
-    }
+    #[test]
+    fn receive_actions_max_slippage() {
+        let send_channel = "channel-9";
+        let shares_denom = "gamm/pool/1";
+        let querier = GammQuerier {
+            base: MockQuerier::new(&[]),
+            swap_estimate: 36601070,
+            total_shares: Coin::new(100000000000000000000, shares_denom),
+            liquidity: vec![
+                Coin::new(1000000000, "uatom"),
+                Coin::new(5000000000, "uosmo"),
+            ],
+            spot_prices: vec![
+                ("uosmo", "uatom", Decimal::percent(20)),
+                ("uatom", "uosmo", Decimal::percent(500)),
+            ],
+        };
+        let mut deps = setup_with_querier(&["channel-1", send_channel], querier);
 
-    fn mock_ibc_rcv_packet(my_channel: &str, data: &impl Serialize) -> IbcPacketReceiveMsg {
-        IbcPacketReceiveMsg::new(IbcPacket::new(
-            to_binary(&data).unwrap(),
-            IbcEndpoint {
-                port_id: REMOTE_PORT.to_string(),
-                channel_id: "channel-1234".to_string(),
-            },
-            IbcEndpoint {
-                port_id: CONTRACT_PORT.to_string(),
-                channel_id: my_channel.to_string(),
-            },
-            3,
-            Timestamp::from_seconds(1665321069).into(),
-        ))
-    }
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uatom");
+        transfer_native(
+            deps.as_mut(),
+            send_channel,
+            1000000000000000000,
+            shares_denom,
+        );
 
-    fn mock_rcv_action_packet(
-        action: OsmoPacket,
-        channel: &str,
-        amount: u128,
-        denom: &str,
-    ) -> IbcPacketReceiveMsg {
-        let packet_data = mock_ics20_data(amount, denom, "", Some(action));
-        let packet = mock_ibc_rcv_packet(channel, &packet_data);
+        // swap min output is derived from the spot price: 10000000uatom at 5uosmo is 50000000uosmo
+        let mut swap = SwapPacket {
+            routes: vec![SwapAmountInRoute {
+                pool_id: 1u8.into(),
+                token_out_denom: "uosmo".to_string(),
+            }],
+            token_out_min_amount: None,
+            max_slippage: Some(Decimal::percent(30)),
+            reference_price: None,
+            return_to: None,
+        };
+        let receive_swap = |deps: &mut OwnedDeps<_, _, _>, swap: &SwapPacket| {
+            let action = OsmoPacket::Swap(swap.clone());
+            let packet = mock_rcv_action_packet(action, send_channel, 10000000, "uatom");
+            ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap()
+        };
+        let res = receive_swap(&mut deps, &swap);
+        let msg: MsgSwapExactAmountIn = stargate_msg(&res.messages[0]);
+        assert_eq!(msg.token_out_min_amount, "35000000");
+
+        // a sender reference price above the spot price raises the minimum
+        swap.reference_price = Some(Decimal::percent(520));
+        let res = receive_swap(&mut deps, &swap);
+        let msg: MsgSwapExactAmountIn = stargate_msg(&res.messages[0]);
+        assert_eq!(msg.token_out_min_amount, "36400000");
+
+        // the pool estimated output (36601070), with the price impact, is already past the slippage
+        swap.max_slippage = Some(Decimal::percent(25));
+        swap.reference_price = None;
+        let res = receive_swap(&mut deps, &swap);
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let min_not_reached = ContractError::MinAmountNotReached {
+            amount: Uint128::new(36601070),
+            min: Uint128::new(37500000),
+        };
+        assert_eq!(ack, error_ack("swap", min_not_reached));
+
+        // a min amount is required without slippage
+        swap.max_slippage = None;
+        swap.reference_price = None;
+        let packet =
+            mock_rcv_action_packet(OsmoPacket::Swap(swap), send_channel, 10000000, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(ack, error_ack("swap", ContractError::MissingMinAmount {}));
 
-        packet
-    }
+        // join min shares: 10000000uatom of a pool valued 2000000000uatom
+        let join = JoinPoolPacket {
+            pool_id: 1u8.into(),
+            share_out_min_amount: None,
+            max_slippage: Some(Decimal::percent(5)),
+            reference_price: None,
+        };
+        let packet =
+            mock_rcv_action_packet(OsmoPacket::JoinPool(join), send_channel, 10000000, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        let msg: MsgJoinSwapExternAmountIn = stargate_msg(&res.messages[0]);
+        assert_eq!(msg.share_out_min_amount, "475000000000000000");
+
+        // exit min output: 1% of the shares of a pool valued 10000000000uosmo
+        let exit = ExitPoolPacket {
+            token_out_denom: "uosmo".to_string(),
+            token_out_min_amount: None,
+            max_slippage: Some(Decimal::percent(5)),
+            reference_price: None,
+            return_to: None,
+        };
+        let packet = mock_rcv_action_packet(
+            OsmoPacket::ExitPool(exit.clone()),
+            send_channel,
+            1000000000000000000,
+            shares_denom,
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        let msg: MsgExitSwapShareAmountIn = stargate_msg(&res.messages[0]);
+        assert_eq!(msg.token_out_min_amount, "95000000");
 
-    fn assert_submsg_wasm<T: DeserializeOwned + std::cmp::PartialEq>(
-        submsg: SubMsg,
-        reply_id: u64,
-        on: ReplyOn,
-        contract: &String,
-        msg_exp: T,
-        mgs_funds: Vec<Coin>,
-    ) {
-        assert!(matches!(submsg, SubMsg {
-            id,
-            ref reply_on,
-            msg: CosmosMsg::Wasm(WasmMsg::Execute {
-                ref contract_addr,
-                ref msg,
-                funds,
-            }),
-            ..
-        } if id == reply_id && reply_on.clone() == on && contract_addr.eq(contract) && funds.eq(&mgs_funds) && msg_exp.eq(&from_binary::<T>(msg).unwrap())));
+        // slippage cannot exceed 100%
+        transfer_native(
+            deps.as_mut(),
+            send_channel,
+            1000000000000000000,
+            shares_denom,
+        );
+        let mut invalid_exit = exit;
+        invalid_exit.max_slippage = Some(Decimal::percent(101));
+        let packet = mock_rcv_action_packet(
+            OsmoPacket::ExitPool(invalid_exit),
+            send_channel,
+            1000000000000000000,
+            shares_denom,
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let invalid = error_ack("exit_pool", ContractError::InvalidSlippage {});
+        assert_eq!(ack, invalid);
     }
 
     #[test]
-    fn send_receive_native() {
+    fn receive_swap_exact_out_action() {
         let send_channel = "channel-9";
         let mut deps = setup(&["channel-1", "channel-7", send_channel]);
-
         let denom = "uatom";
+        let swap_denom = "uosmo";
 
-        // prepare some mock packets
-        let recv_packet = mock_receive_packet(send_channel, 876543210, denom, "local-rcpt");
-        let recv_high_packet = mock_receive_packet(send_channel, 1876543210, denom, "local-rcpt");
-
-        // cannot receive this denom yet
-        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet.clone()).unwrap();
-        assert!(res.messages.is_empty());
-        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
-        let no_funds = Ics20Ack::Error(ContractError::InsufficientFunds {}.to_string());
-        assert_eq!(ack, no_funds);
+        let swap = SwapExactOutPacket {
+            routes: vec![SwapAmountOutRoute {
+                pool_id: 1u8.into(),
+                token_in_denom: denom.to_string(),
+            }],
+            token_in_max_amount: 10000000u64.into(),
+            token_out_denom: swap_denom.to_string(),
+            token_out_amount: 36601070u64.into(),
+        };
 
         // we transfer some tokens
         let msg = ExecuteMsg::Transfer(TransferMsg {
             channel: send_channel.to_string(),
             remote_address: "my-remote-address".to_string(),
             timeout: None,
+            callback: None,
         });
         let info = mock_info("local-sender", &coins(987654321, denom));
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
 
-        // query channel state|_|
-        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
-        assert_eq!(state.balances, vec![Amount::native(987654321, denom)]);
-        assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
-
-        // cannot receive more than we sent
-        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_high_packet).unwrap();
-        assert!(res.messages.is_empty());
+        // max amount cannot exceed the received amount
+        let mut invalid_swap = swap.clone();
+        invalid_swap.token_in_max_amount = 10000001u64.into();
+        let invalid_packet = mock_rcv_action_packet(
+            OsmoPacket::SwapExactOut(invalid_swap),
+            send_channel,
+            10000000,
+            denom,
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), invalid_packet).unwrap();
+        assert_eq!(0, res.messages.len());
         let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
-        assert_eq!(ack, no_funds);
+        let invalid_max = error_ack("swap_exact_out", ContractError::InvalidMaxAmountIn {});
+        assert_eq!(ack, invalid_max);
 
-        // we can receive less than we sent
-        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv_packet).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(
-            native_payment(876543210, denom, "local-rcpt"),
-            res.messages[0]
+        // Swap action
+        let swap_packet = mock_rcv_action_packet(
+            OsmoPacket::SwapExactOut(swap),
+            send_channel,
+            10000000,
+            denom,
         );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), swap_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        check_gamm_submsg(
+            res.messages[0].clone(),
+            SWAP_EXACT_OUT_ID,
+            "swapexactamountout",
+        )
+        .unwrap();
+
         let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
         assert!(matches!(ack, Ics20Ack::Result(_)));
 
-        // only need to call reply block on error case
+        // Simulate swap reply (token_in_amount: 9850000)
+        let r = mock_swap_exact_out_response();
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_EXACT_OUT_ID), r.events, r.data);
 
-        // query channel state
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let gamm_ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let gamm_ack_exp = vec![
+            AmountResultAck {
+                amount: Uint128::new(36601070u128),
+                denom: swap_denom.to_string(),
+            },
+            AmountResultAck {
+                amount: Uint128::new(150000u128),
+                denom: denom.to_string(),
+            },
+        ];
+        assert_eq!(gamm_ack, gamm_ack_exp);
+
+        // query channel state, unused input is returned to the channel
         let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
-        assert_eq!(state.balances, vec![Amount::native(111111111, denom)]);
-        assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(977804321, denom),
+                Amount::native(36601070, swap_denom)
+            ]
+        );
+        assert_eq!(
+            state.total_sent,
+            vec![
+                Amount::native(987654321, denom),
+                Amount::native(36601070, swap_denom)
+            ]
+        );
+
+        // the output and the unused input are held for the sender
+        assert_eq!(36601070, held_balance(&deps, swap_denom));
+        assert_eq!(150000, held_balance(&deps, denom));
     }
 
     #[test]
-    fn receive_swap_action() {
+    fn receive_swap_held_and_withdraw() {
         let send_channel = "channel-9";
-        let mut deps = setup(&["channel-1", "channel-7", send_channel]);
-        let denom = "uatom";
-        let swap_denom = "uosmo";
+        let return_channel = "channel-7";
+        let mut deps = setup(&["channel-1", return_channel, send_channel]);
 
-        let swap = OsmoPacket::Swap(SwapPacket {
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uatom");
+        transfer_native(deps.as_mut(), send_channel, 987654321, "uosmo");
+
+        let deposit_packet =
+            mock_rcv_action_packet(OsmoPacket::Deposit {}, send_channel, 10000000, "uatom");
+        ibc_packet_receive(deps.as_mut(), mock_env(), deposit_packet).unwrap();
+
+        let swap = SwapHeldPacket {
+            token_in: Coin::new(10000000, "uatom"),
             routes: vec![SwapAmountInRoute {
                 pool_id: 1u8.into(),
-                token_out_denom: swap_denom.to_string(),
+                token_out_denom: "uosmo".to_string(),
             }],
             token_out_min_amount: 1u8.into(),
-        });
-
-        let swap_packet_data = mock_ics20_data(876543210, denom, "", Some(swap));
-
-        // prepare some mock packets
-        let swap_packet = mock_ibc_rcv_packet(send_channel, &swap_packet_data);
-
-        // we transfer some tokens
-        let msg = ExecuteMsg::Transfer(TransferMsg {
-            channel: send_channel.to_string(),
-            remote_address: "my-remote-address".to_string(),
-            timeout: None,
-        });
-        let info = mock_info("local-sender", &coins(987654321, denom));
-        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+            return_to: None,
+        };
 
-        // query channel state|_|
-        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
-        assert_eq!(state.balances, vec![Amount::native(987654321, denom)]);
-        assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
+        // cannot swap more than the held funds
+        let mut invalid_swap = swap.clone();
+        invalid_swap.token_in.amount = 10000001u64.into();
+        let invalid_packet = mock_rcv_action_packet(
+            OsmoPacket::SwapHeld(invalid_swap),
+            send_channel,
+            1000,
+            "uosmo",
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), invalid_packet).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let no_funds = error_ack("swap_held", ContractError::InsufficientHeldFunds {});
+        assert_eq!(ack, no_funds);
+        assert_eq!(10000000, held_balance(&deps, "uatom"));
 
-        // Swap action
+        // the packet only carries a fee in another denom, the swap input is held
+        let swap_packet =
+            mock_rcv_action_packet(OsmoPacket::SwapHeld(swap), send_channel, 1000, "uosmo");
         let res = ibc_packet_receive(deps.as_mut(), mock_env(), swap_packet).unwrap();
         assert_eq!(1, res.messages.len());
-        check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
-
-        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
-        assert!(matches!(ack, Ics20Ack::Result(_)));
+        check_gamm_submsg(res.messages[0].clone(), SWAP_HELD_ID, "swap").unwrap();
+        assert_eq!(0, held_balance(&deps, "uatom"));
 
         // Simulate swap reply
         let r = mock_swap_response();
-        let reply_msg = mock_reply_msg(SWAP_ID, r.events, r.data);
-
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_HELD_ID), r.events, r.data);
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         assert_eq!(0, res.messages.len());
-        let gamm_ack: AmountResultAck = get_ack_result(&res.data.unwrap()).unwrap();
-        let gamm_ack_exp = AmountResultAck {
-            amount: Uint128::new(36601070u128),
-            denom: swap_denom.to_string(),
-        };
+        let gamm_ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let gamm_ack_exp = vec![
+            AmountResultAck {
+                amount: Uint128::new(36601070u128),
+                denom: "uosmo".to_string(),
+            },
+            AmountResultAck {
+                amount: Uint128::new(1000u128),
+                denom: "uosmo".to_string(),
+            },
+        ];
         assert_eq!(gamm_ack, gamm_ack_exp);
+        assert_eq!(36602070, held_balance(&deps, "uosmo"));
 
-        // query channel state
         let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
         assert_eq!(
             state.balances,
             vec![
-                Amount::native(111111111, denom),
-                Amount::native(36601070, swap_denom)
+                Amount::native(977654321, "uatom"),
+                Amount::native(1024255391, "uosmo")
+            ]
+        );
+
+        // Withdraw the swap output to another chain
+        let withdraw = OsmoPacket::Withdraw(WithdrawPacket {
+            coins: vec![Coin::new(36602070, "uosmo")],
+            return_to: ReturnTo {
+                channel: return_channel.to_string(),
+                remote_address: "remote-rcpt".to_string(),
+                timeout: None,
+                memo: None,
+            },
+        });
+        let withdraw_packet = mock_rcv_action_packet(withdraw, send_channel, 500, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), withdraw_packet).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+        assert_eq!(1, res.messages.len());
+        match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id, data, ..
+            }) => {
+                assert_eq!(channel_id, return_channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::new(36602070));
+                assert_eq!(packet.denom, "uosmo");
+                assert_eq!(packet.receiver, "remote-rcpt");
+            }
+            _ => panic!("Unexpected message: {:?}", res.messages[0]),
+        }
+
+        assert_eq!(0, held_balance(&deps, "uosmo"));
+        assert_eq!(500, held_balance(&deps, "uatom"));
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(977654321, "uatom"),
+                Amount::native(987653321, "uosmo")
             ]
         );
+        let state = query_channel(deps.as_ref(), return_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(36602070, "uosmo")]);
+
+        // the held funds are given back when the output can't be sent
+        let key = EXTERNAL_DENOMS.key((return_channel, "uatom"));
+        deps.storage.set(&key, b"{");
+        let withdraw = OsmoPacket::Withdraw(WithdrawPacket {
+            coins: vec![Coin::new(600, "uatom")],
+            return_to: ReturnTo {
+                channel: return_channel.to_string(),
+                remote_address: "remote-rcpt".to_string(),
+                timeout: None,
+                memo: None,
+            },
+        });
+        let withdraw_packet = mock_rcv_action_packet(withdraw, send_channel, 100, "uatom");
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), withdraw_packet).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Error(_)));
+        assert_eq!(0, res.messages.len());
+        assert_eq!(500, held_balance(&deps, "uatom"));
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
         assert_eq!(
-            state.total_sent,
+            state.balances,
             vec![
-                Amount::native(987654321, denom),
-                Amount::native(36601070, swap_denom)
+                Amount::native(977654321, "uatom"),
+                Amount::native(987653321, "uosmo")
             ]
         );
     }
@@ -1028,12 +5097,17 @@ mod test {
 
         let join_pool = OsmoPacket::JoinPool(JoinPoolPacket {
             pool_id: 1u8.into(),
-            share_out_min_amount: 1u8.into(),
+            share_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
         });
 
         let exit_pool = OsmoPacket::ExitPool(ExitPoolPacket {
             token_out_denom: denom.into(),
-            token_out_min_amount: 1u8.into(),
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: None,
         });
 
         let join_packet_data = mock_ics20_data(876543210, denom, "", Some(join_pool));
@@ -1049,6 +5123,7 @@ mod test {
             channel: send_channel.to_string(),
             remote_address: "my-remote-address".to_string(),
             timeout: None,
+            callback: None,
         });
         let info = mock_info("local-sender", &coins(987654321, denom));
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -1068,7 +5143,7 @@ mod test {
 
         // Simulate join_pool reply
         let r = mock_join_pool_response();
-        let reply_msg = mock_reply_msg(JOIN_POOL_ID, r.events, r.data);
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, JOIN_POOL_ID), r.events, r.data);
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         assert_eq!(0, res.messages.len());
         let gamm_ack: AmountResultAck = get_ack_result(&res.data.unwrap()).unwrap();
@@ -1088,7 +5163,7 @@ mod test {
 
         // Simulate exit_pool reply
         let r = mock_exit_pool_response();
-        let reply_msg = mock_reply_msg(EXIT_POOL_ID, r.events, r.data);
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, EXIT_POOL_ID), r.events, r.data);
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         assert_eq!(0, res.messages.len());
         let gamm_ack: AmountResultAck = get_ack_result(&res.data.unwrap()).unwrap();
@@ -1139,6 +5214,7 @@ mod test {
             channel: send_channel.to_string(),
             remote_address: "my-remote-address".to_string(),
             timeout: None,
+            callback: None,
         });
         let info = mock_info("local-sender", &coins(987654321, denom));
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -1148,7 +5224,7 @@ mod test {
         assert_eq!(0, res.messages.len());
 
         let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
-        let no_lockup_account = Ics20Ack::Error(ContractError::LockupNotFound {}.to_string());
+        let no_lockup_account = error_ack("unlock", ContractError::LockupNotFound {});
         assert_eq!(ack, no_lockup_account);
 
         // Create Lockup account action
@@ -1160,7 +5236,11 @@ mod test {
 
         // Simulate reply lockup created (MsgInstantiateContractResponse {address: "lockup-addr"})
         let init_ctr_response = Binary::from_base64("Cgtsb2NrdXAtYWRkcg==").unwrap();
-        let reply_msg = mock_reply_msg(LOCKUP_ID, vec![], Some(init_ctr_response));
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, LOCKUP_ID),
+            vec![],
+            Some(init_ctr_response),
+        );
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         assert_eq!(0, res.messages.len());
 
@@ -1210,6 +5290,83 @@ mod test {
         let ack: Ics20Ack = from_binary(&res.data.unwrap()).unwrap();
         assert!(matches!(ack, Ics20Ack::Error(_)));
 
+        // Extend lock action, the ack is the lockup result
+        let extend = OsmoPacket::ExtendLock(ExtendLockPacket {
+            id: 1u64.into(),
+            duration: 1209600u64.into(),
+        });
+        let extend_packet = mock_rcv_action_packet(extend, send_channel, 0, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), extend_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        let lockup_msg = LockupExecuteMsg::ExtendLock {
+            id: 1u64.into(),
+            duration: 1209600u64.into(),
+        };
+        assert_submsg_wasm(
+            res.messages[0].clone(),
+            EXTEND_LOCK_ID,
+            ReplyOn::Always,
+            &lockup_contract,
+            lockup_msg,
+            vec![],
+        );
+        let result = ExtendLockResultAck {
+            lock_id: 1u64.into(),
+            duration: 1209600u64.into(),
+        };
+        let data = json_to_reply_proto(&String::from_utf8(to_vec(&result).unwrap()).unwrap());
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, EXTEND_LOCK_ID),
+            vec![],
+            Some(data.into()),
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let ack: ExtendLockResultAck = get_ack_result(&res.data.unwrap()).unwrap();
+        assert_eq!(ack, result);
+
+        // Unlock all action
+        let unlock_all_packet =
+            mock_rcv_action_packet(OsmoPacket::UnlockAll {}, send_channel, 0, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), unlock_all_packet).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_submsg_wasm(
+            res.messages[0].clone(),
+            UNLOCK_ALL_ID,
+            ReplyOn::Always,
+            &lockup_contract,
+            LockupExecuteMsg::UnlockAll {},
+            vec![],
+        );
+        let result = UnlockAllResultAck {
+            unlocks: vec![UnlockingLockAck {
+                lock_id: 1u64.into(),
+                coins: coins(54321u128, denom),
+                end_time: Timestamp::from_seconds(1660000000),
+            }],
+        };
+        let data = json_to_reply_proto(&String::from_utf8(to_vec(&result).unwrap()).unwrap());
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, UNLOCK_ALL_ID),
+            vec![],
+            Some(data.into()),
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let ack: UnlockAllResultAck = get_ack_result(&res.data.unwrap()).unwrap();
+        assert_eq!(ack, result);
+
+        // a failed unlock all is reported in the ack
+        let unlock_all_packet =
+            mock_rcv_action_packet(OsmoPacket::UnlockAll {}, send_channel, 0, denom);
+        ibc_packet_receive(deps.as_mut(), mock_env(), unlock_all_packet).unwrap();
+        let reply_msg = Reply {
+            id: last_reply_id(&deps, UNLOCK_ALL_ID),
+            result: SubMsgResult::Err("no locks to unlock".to_string()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let ack: Ics20Ack = from_binary(&res.data.unwrap()).unwrap();
+        let expected = ack_fail("unlock_all", "no locks to unlock".to_string());
+        assert_eq!(ack, from_binary(&expected).unwrap());
+
         // query channel state
         let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
         assert_eq!(state.balances, vec![Amount::native(987600000, denom)]);
@@ -1220,24 +5377,33 @@ mod test {
     fn receive_lock_claim_rewards() {
         let send_channel = "channel-9";
         let mut deps = setup(&["channel-1", "channel-7", send_channel]);
+        mock_lockup_version(&mut deps.querier, "0.2.0");
         let denom = "uosmo";
         let rewards = 45679u128;
         let lockup_contract = "lockup-addr".to_string();
 
         let lockup = OsmoPacket::LockupAccount {};
         let claim = OsmoPacket::Claim(ClaimPacket {
-            denom: denom.to_string(),
+            denoms: vec![denom.to_string()],
+            return_to: None,
         });
+        let claim_all = OsmoPacket::ClaimAll(ClaimAllPacket { return_to: None });
+
+        // counterparts before 0.2.0 send a single denom
+        let legacy: OsmoPacket = from_slice(br#"{"claim":{"denom":"uosmo"}}"#).unwrap();
+        assert_eq!(legacy, claim);
 
         // prepare some mock packets
         let lockup_packet = mock_rcv_action_packet(lockup, send_channel, 0, denom);
         let claim_packet = mock_rcv_action_packet(claim, send_channel, 0, denom);
+        let claim_all_packet = mock_rcv_action_packet(claim_all, send_channel, 0, denom);
 
         // we transfer some tokens to register denom
         let msg = ExecuteMsg::Transfer(TransferMsg {
             channel: send_channel.to_string(),
             remote_address: "my-remote-address".to_string(),
             timeout: None,
+            callback: None,
         });
         let info = mock_info("local-sender", &coins(987654321, denom));
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -1250,13 +5416,24 @@ mod test {
         // Create Lockup account action
         let res = ibc_packet_receive(deps.as_mut(), mock_env(), lockup_packet).unwrap();
         assert_eq!(1, res.messages.len());
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { admin, code_id, .. }) => {
+                assert_eq!(admin.as_deref(), Some(MOCK_CONTRACT_ADDR));
+                assert_eq!(*code_id, 1);
+            }
+            msg => panic!("Unexpected lockup message: {:?}", msg),
+        }
 
         let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
         assert!(matches!(ack, Ics20Ack::Result(_)));
 
         // Simulate reply lockup created (MsgInstantiateContractResponse {address: "lockup-addr"})
         let init_ctr_response = Binary::from_base64("Cgtsb2NrdXAtYWRkcg==").unwrap();
-        let reply_msg = mock_reply_msg(LOCKUP_ID, vec![], Some(init_ctr_response));
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, LOCKUP_ID),
+            vec![],
+            Some(init_ctr_response),
+        );
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         assert_eq!(0, res.messages.len());
 
@@ -1269,7 +5446,7 @@ mod test {
         let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
         assert!(matches!(ack, Ics20Ack::Result(_)));
         let lockup_msg = LockupExecuteMsg::Claim {
-            denom: denom.to_string(),
+            denoms: vec![denom.to_string()],
         };
         assert_submsg_wasm(
             res.messages[0].clone(),
@@ -1282,44 +5459,512 @@ mod test {
 
         // Simulate reply rewards.
         let rewards_data = json_to_reply_proto(&format!(
-            "{{\"amount\":\"{}\",\"denom\":\"{}\"}}",
+            "[{{\"amount\":\"{}\",\"denom\":\"{}\"}}]",
             rewards, denom
         ));
-        let reply_msg = mock_reply_msg(CLAIM_TOKEN_ID, vec![], Some(rewards_data.into()));
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, CLAIM_TOKEN_ID),
+            vec![],
+            Some(rewards_data.into()),
+        );
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         assert_eq!(0, res.messages.len());
 
-        let ack: AmountResultAck = get_ack_result(&res.data.unwrap()).unwrap();
-        assert_eq!(ack.amount, Uint128::new(rewards));
-        assert_eq!(ack.denom, denom);
+        let ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let expected = vec![AmountResultAck {
+            denom: denom.to_string(),
+            amount: Uint128::new(rewards),
+        }];
+        assert_eq!(ack, expected);
 
         // query channel state
         let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
         assert_eq!(state.balances, vec![Amount::native(987700000, denom)]);
         assert_eq!(state.total_sent, vec![Amount::native(987700000, denom)]);
+
+        // Claim the whole lockup balance, every denom is credited
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), claim_all_packet).unwrap();
+        assert_submsg_wasm(
+            res.messages[0].clone(),
+            CLAIM_ALL_ID,
+            ReplyOn::Always,
+            &lockup_contract,
+            LockupExecuteMsg::ClaimAll {},
+            vec![],
+        );
+
+        let rewards_data = json_to_reply_proto(
+            "[{\"amount\":\"300\",\"denom\":\"uion\"},{\"amount\":\"100000\",\"denom\":\"uosmo\"}]",
+        );
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, CLAIM_ALL_ID),
+            vec![],
+            Some(rewards_data.into()),
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        let ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let expected = vec![
+            AmountResultAck {
+                denom: "uion".to_string(),
+                amount: Uint128::new(300),
+            },
+            AmountResultAck {
+                denom: denom.to_string(),
+                amount: Uint128::new(100000),
+            },
+        ];
+        assert_eq!(ack, expected);
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(300, "uion"),
+                Amount::native(987800000, denom)
+            ]
+        );
+
+        // the lockup accounts before 0.2.0 claim a single denom and return a single coin
+        mock_lockup_version(&mut deps.querier, "0.1.0");
+        let claim = OsmoPacket::Claim(ClaimPacket {
+            denoms: vec![denom.to_string()],
+            return_to: None,
+        });
+        let claim_packet = mock_rcv_action_packet(claim, send_channel, 0, denom);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), claim_packet).unwrap();
+        assert_submsg_wasm(
+            res.messages[0].clone(),
+            CLAIM_TOKEN_ID,
+            ReplyOn::Always,
+            &lockup_contract,
+            LegacyLockupExecuteMsg::Claim {
+                denom: denom.to_string(),
+            },
+            vec![],
+        );
+        let rewards_data = json_to_reply_proto("{\"amount\":\"200\",\"denom\":\"uosmo\"}");
+        let reply_msg = mock_reply_msg(
+            last_reply_id(&deps, CLAIM_TOKEN_ID),
+            vec![],
+            Some(rewards_data.into()),
+        );
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let ack: Vec<AmountResultAck> = get_ack_result(&res.data.unwrap()).unwrap();
+        let expected = vec![AmountResultAck {
+            denom: denom.to_string(),
+            amount: Uint128::new(200),
+        }];
+        assert_eq!(ack, expected);
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(300, "uion"),
+                Amount::native(987800200, denom)
+            ]
+        );
+
+        // an unexpected claim result is an error ack, not a failed receive
+        let bad_data = json_to_reply_proto("{\"amount\":\"oops\"}");
+        for data in [Some(bad_data.into()), None] {
+            let claim = OsmoPacket::Claim(ClaimPacket {
+                denoms: vec![denom.to_string()],
+                return_to: None,
+            });
+            let claim_packet = mock_rcv_action_packet(claim, send_channel, 0, denom);
+            ibc_packet_receive(deps.as_mut(), mock_env(), claim_packet).unwrap();
+            let reply_msg = mock_reply_msg(last_reply_id(&deps, CLAIM_TOKEN_ID), vec![], data);
+            let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+            assert_eq!(0, res.messages.len());
+            let err = match from_binary(&res.data.unwrap()).unwrap() {
+                Ics20Ack::Error(err) => from_slice::<ErrorAck>(err.as_bytes()).unwrap(),
+                ack => panic!("Unexpected ack: {:?}", ack),
+            };
+            assert_eq!(err.action, "claim");
+            assert_eq!(err.code, ErrorCode::Internal as u32);
+        }
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(300, "uion"),
+                Amount::native(987800200, denom)
+            ]
+        );
+
+        // they can't claim several denoms at once
+        let claim = OsmoPacket::Claim(ClaimPacket {
+            denoms: vec!["uion".to_string(), denom.to_string()],
+            return_to: None,
+        });
+        for claim in [
+            claim,
+            OsmoPacket::ClaimAll(ClaimAllPacket { return_to: None }),
+        ] {
+            let action = claim.name();
+            let packet = mock_rcv_action_packet(claim, send_channel, 0, denom);
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+            assert_eq!(0, res.messages.len());
+            let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+            let unsupported = ContractError::LockupClaimUnsupported {
+                version: "0.1.0".to_string(),
+            };
+            assert_eq!(ack, error_ack(action, unsupported));
+        }
+    }
+    #[test]
+    fn receive_memo_actions() {
+        let send_channel = "channel-9";
+        let forward_channel = "channel-7";
+        let mut deps = setup(&["channel-1", forward_channel, send_channel]);
+        let denom = "uatom";
+        let swap_denom = "uosmo";
+        let contract = mock_env().contract.address.to_string();
+
+        // we transfer some tokens
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(987654321, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let swap = OsmoPacket::Swap(SwapPacket {
+            routes: vec![SwapAmountInRoute {
+                pool_id: 1u8.into(),
+                token_out_denom: swap_denom.to_string(),
+            }],
+            token_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+            return_to: None,
+        });
+        let memo_packet = |action: Option<OsmoPacket>, memo: String| {
+            let mut data = mock_ics20_data(1000, denom, "local-rcpt", action);
+            data.memo = Some(memo);
+            mock_ibc_rcv_packet(send_channel, &data)
+        };
+        let json = |memo: &PacketMemo| String::from_utf8(to_vec(memo).unwrap()).unwrap();
+
+        // plain memos are notes, the tokens are received as usual
+        let packet = memo_packet(None, "thanks".to_string());
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert_eq!(
+            native_payment(last_reply_id(&deps, RECEIVE_ID), 1000, denom, "local-rcpt"),
+            res.messages[0]
+        );
+
+        // the action can be set in the memo, in both formats
+        let osmo_memo = json(&PacketMemo {
+            osmo_action: Some(swap.clone()),
+            ..PacketMemo::default()
+        });
+        let wasm_memo = format!(
+            r#"{{"wasm":{{"contract":"{}","msg":{{"swap":{{"routes":[{{"pool_id":"1","token_out_denom":"uosmo"}}],"token_out_min_amount":"1"}}}}}}}}"#,
+            contract
+        );
+        for memo in [osmo_memo.clone(), wasm_memo] {
+            let res =
+                ibc_packet_receive(deps.as_mut(), mock_env(), memo_packet(None, memo)).unwrap();
+            assert_eq!(1, res.messages.len());
+            check_gamm_submsg(res.messages[0].clone(), SWAP_ID, "swap").unwrap();
+            let reply_args = REPLY_ARGS.load(&deps.storage, res.messages[0].id).unwrap();
+            assert_eq!(reply_args.action, Some(swap.clone()));
+        }
+
+        // wasm memo for another contract
+        let memo = r#"{"wasm":{"contract":"other","msg":{"deposit":{}}}}"#.to_string();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), memo_packet(None, memo)).unwrap();
+        assert!(res.messages.is_empty());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let invalid_memo = ContractError::InvalidMemo {
+            error: "unknown wasm contract other".to_string(),
+        };
+        assert_eq!(ack, error_ack("transfer", invalid_memo));
+
+        // only one action by packet
+        let packet = memo_packet(Some(swap.clone()), osmo_memo);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap();
+        assert!(res.messages.is_empty());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        let invalid_memo = ContractError::InvalidMemo {
+            error: "more than one action".to_string(),
+        };
+        assert_eq!(ack, error_ack("transfer", invalid_memo));
+
+        // the swap output is forwarded through another hop
+        let next = PacketMemo {
+            forward: Some(ForwardMemo {
+                receiver: "final-rcpt".to_string(),
+                port: None,
+                channel: "channel-0".to_string(),
+                timeout: None,
+                next: None,
+            }),
+            ..PacketMemo::default()
+        };
+        let forward = ForwardMemo {
+            receiver: "remote-rcpt".to_string(),
+            port: Some("transfer".to_string()),
+            channel: forward_channel.to_string(),
+            timeout: Some(600_000_000_000u64.into()),
+            next: Some(Box::new(next)),
+        };
+        let memo = json(&PacketMemo {
+            osmo_action: Some(swap),
+            forward: Some(forward.clone()),
+            ..PacketMemo::default()
+        });
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), memo_packet(None, memo)).unwrap();
+        assert_eq!(1, res.messages.len());
+
+        let r = mock_swap_response();
+        let reply_msg = mock_reply_msg(last_reply_id(&deps, SWAP_ID), r.events, r.data);
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            }) => {
+                let expected_timeout = mock_env().block.time.plus_seconds(600);
+                assert_eq!(timeout, &expected_timeout.into());
+                assert_eq!(channel_id.as_str(), forward_channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::new(36601070));
+                assert_eq!(packet.denom.as_str(), swap_denom);
+                assert_eq!(packet.receiver.as_str(), "remote-rcpt");
+                assert_eq!(
+                    packet.memo.unwrap(),
+                    r#"{"forward":{"receiver":"final-rcpt","channel":"channel-0"}}"#
+                );
+            }
+            msg => panic!("Unexpected forward message: {:?}", msg),
+        }
+
+        // without action, the received tokens are forwarded
+        let memo = json(&PacketMemo {
+            forward: Some(forward),
+            ..PacketMemo::default()
+        });
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), memo_packet(None, memo)).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+        assert_eq!(1, res.messages.len());
+        match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id, data, ..
+            }) => {
+                assert_eq!(channel_id.as_str(), forward_channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::new(1000));
+                assert_eq!(packet.denom.as_str(), denom);
+                assert_eq!(packet.receiver.as_str(), "remote-rcpt");
+            }
+            msg => panic!("Unexpected forward message: {:?}", msg),
+        }
+        let state = query_channel(deps.as_ref(), forward_channel.to_string()).unwrap();
+        assert_eq!(
+            state.balances,
+            vec![
+                Amount::native(1000, denom),
+                Amount::native(36601070, swap_denom)
+            ]
+        );
     }
 
     #[test]
-    fn reply_on_errors() {
+    fn receive_pfm_forward_memos() {
+        let send_channel = "channel-9";
+        let forward_channel = "channel-7";
+        let mut deps = setup(&["channel-1", forward_channel, send_channel]);
+        let denom = "uatom";
+
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(987654321, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let mut receive_memo = |memo: &str| {
+            let mut data = mock_ics20_data(1000, denom, "local-rcpt", None);
+            data.memo = Some(memo.to_string());
+            let packet = mock_ibc_rcv_packet(send_channel, &data);
+            ibc_packet_receive(deps.as_mut(), mock_env(), packet).unwrap()
+        };
+        let sent_packet = |res: &IbcReceiveResponse| match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            }) => {
+                assert_eq!(channel_id.as_str(), forward_channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::new(1000));
+                assert_eq!(packet.receiver.as_str(), "osmo1rcpt");
+                (timeout.timestamp().unwrap(), packet.memo)
+            }
+            msg => panic!("Unexpected forward message: {:?}", msg),
+        };
+
+        // memo as sent by the packet-forward-middleware tooling, a Go duration timeout and the
+        // next hop memo as a JSON string
+        let memo = r#"{"forward":{"receiver":"osmo1rcpt","port":"transfer","channel":"channel-7","timeout":"10m","retries":2,"next":"{\"forward\":{\"receiver\":\"juno1rcpt\",\"port\":\"transfer\",\"channel\":\"channel-42\",\"timeout\":\"1h30m\"}}"}}"#;
+        let res = receive_memo(memo);
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+        let (timeout, next) = sent_packet(&res);
+        assert_eq!(timeout, mock_env().block.time.plus_seconds(600));
+        assert_eq!(
+            next.unwrap(),
+            r#"{"forward":{"receiver":"juno1rcpt","port":"transfer","channel":"channel-42","timeout":5400000000000}}"#
+        );
+
+        // nested next object with a timeout in nanoseconds
+        let memo = r#"{"forward":{"receiver":"osmo1rcpt","port":"transfer","channel":"channel-7","timeout":90000000000,"retries":2,"next":{"forward":{"receiver":"juno1rcpt","port":"transfer","channel":"channel-42","timeout":"1.5s"}}}}"#;
+        let (timeout, next) = sent_packet(&receive_memo(memo));
+        assert_eq!(timeout, mock_env().block.time.plus_seconds(90));
+        assert_eq!(
+            next.unwrap(),
+            r#"{"forward":{"receiver":"juno1rcpt","port":"transfer","channel":"channel-42","timeout":1500000000}}"#
+        );
+
+        // a numeric string is nanoseconds, without timeout the default one is used
+        let memo = r#"{"forward":{"receiver":"osmo1rcpt","channel":"channel-7","timeout":"120000000000"}}"#;
+        let (timeout, next) = sent_packet(&receive_memo(memo));
+        assert_eq!(timeout, mock_env().block.time.plus_seconds(120));
+        assert_eq!(next, None);
+        let memo = r#"{"forward":{"receiver":"osmo1rcpt","channel":"channel-7"}}"#;
+        let (timeout, _) = sent_packet(&receive_memo(memo));
+        assert_eq!(timeout, mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT));
+
+        // invalid durations and next memos are rejected
+        for memo in [
+            r#"{"forward":{"receiver":"osmo1rcpt","channel":"channel-7","timeout":"10x"}}"#,
+            r#"{"forward":{"receiver":"osmo1rcpt","channel":"channel-7","timeout":"-10m"}}"#,
+            r#"{"forward":{"receiver":"osmo1rcpt","channel":"channel-7","timeout":"10 days"}}"#,
+            r#"{"forward":{"receiver":"osmo1rcpt","channel":"channel-7","next":"{\"forward\""}}"#,
+        ] {
+            let res = receive_memo(memo);
+            assert!(res.messages.is_empty());
+            let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+            assert!(
+                matches!(&ack, Ics20Ack::Error(err) if err.contains("Invalid packet memo")),
+                "{}: {:?}",
+                memo,
+                ack
+            );
+        }
+
+        assert_eq!(GoDuration::parse("2h45m"), Some(9_900_000_000_000.into()));
+        assert_eq!(GoDuration::parse("300ms"), Some(300_000_000.into()));
+        assert_eq!(GoDuration::parse("1.5us"), Some(1_500.into()));
+        assert_eq!(GoDuration::parse("3\u{b5}s"), Some(3_000.into()));
+        assert_eq!(GoDuration::parse(".5m"), Some(30_000_000_000.into()));
+        assert_eq!(GoDuration::parse("0"), Some(0.into()));
+        assert_eq!(GoDuration::parse(""), None);
+        assert_eq!(GoDuration::parse("1"), None);
+        assert_eq!(GoDuration::parse("1d"), None);
+        assert_eq!(GoDuration::parse("."), None);
+    }
+
+    #[test]
+    fn interleaved_replies() {
         let send_channel = "channel-9";
         let mut deps = setup(&["channel-1", "channel-7", send_channel]);
         let denom = "uosmo";
-        let error_msg = "Invalid operation".to_string();
+
+        // Transfer initial tokens
+        let msg = ExecuteMsg::Transfer(TransferMsg {
+            channel: send_channel.to_string(),
+            remote_address: "my-remote-address".to_string(),
+            timeout: None,
+            callback: None,
+        });
+        let info = mock_info("local-sender", &coins(1000, denom));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
 
         let join_pool = OsmoPacket::JoinPool(JoinPoolPacket {
             pool_id: 1u8.into(),
-            share_out_min_amount: 1u8.into(),
+            share_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
         });
+
+        // Two packets in flight, each with its own reply context
+        let first = mock_ibc_rcv_packet(
+            send_channel,
+            &mock_ics20_data(600, denom, "", Some(join_pool.clone())),
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), first).unwrap();
+        let first_id = res.messages[0].id;
+        let second = mock_ibc_rcv_packet(
+            send_channel,
+            &mock_ics20_data(400, denom, "", Some(join_pool)),
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), second).unwrap();
+        let second_id = res.messages[0].id;
+        assert_ne!(first_id, second_id);
+        assert_eq!(reply_kind(first_id), JOIN_POOL_ID);
+        assert_eq!(reply_kind(second_id), JOIN_POOL_ID);
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(0, denom)]);
+
+        // The first reply fails and only restores its own funds
         let reply_msg = Reply {
-            id: JOIN_POOL_ID,
-            result: SubMsgResult::Err(error_msg.clone()),
+            id: first_id,
+            result: SubMsgResult::Err("Invalid operation".to_string()),
         };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(600, denom)]);
+        assert!(!REPLY_ARGS.has(&deps.storage, first_id));
+        let pending = REPLY_ARGS.load(&deps.storage, second_id).unwrap();
+        assert_eq!(pending.amount, Uint128::new(400));
+
+        // The second one is still handled after the first context is gone
+        let reply_msg = Reply {
+            id: second_id,
+            result: SubMsgResult::Err("Invalid operation".to_string()),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg.clone()).unwrap();
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.balances, vec![Amount::native(1000, denom)]);
+        assert!(!REPLY_ARGS.has(&deps.storage, second_id));
+
+        // A context is only used once
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap_err();
+    }
+
+    #[test]
+    fn reply_on_errors() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&["channel-1", "channel-7", send_channel]);
+        let denom = "uosmo";
+        // the error of the submessage is redacted by the chain
+        let error_msg = "codespace: gamm, code: 7".to_string();
 
+        let join_pool = OsmoPacket::JoinPool(JoinPoolPacket {
+            pool_id: 1u8.into(),
+            share_out_min_amount: Some(1u8.into()),
+            max_slippage: None,
+            reference_price: None,
+        });
         // Transfer initial tokens
         let msg = ExecuteMsg::Transfer(TransferMsg {
             channel: send_channel.to_string(),
             remote_address: "my-remote-address".to_string(),
             timeout: None,
+            callback: None,
         });
         let info = mock_info("local-sender", &coins(1000, denom));
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -1333,8 +5978,51 @@ mod test {
         assert_eq!(1, res.messages.len());
 
         // Reply with error result
+        let reply_msg = Reply {
+            id: last_reply_id(&deps, JOIN_POOL_ID),
+            result: SubMsgResult::Err(error_msg.clone()),
+        };
         let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
         let ack: Ics20Ack = from_binary(&res.data.unwrap()).unwrap();
-        assert!(matches!(ack, Ics20Ack::Error(err) if err == error_msg));
+        let error: ErrorAck = match ack {
+            Ics20Ack::Error(err) => from_slice(err.as_bytes()).unwrap(),
+            _ => panic!("Unexpected ack: {:?}", ack),
+        };
+        let expected = ErrorAck {
+            version: ERROR_ACK_VERSION,
+            code: ErrorCode::SlippageExceeded as u32,
+            action: "join_pool".to_string(),
+            details: error_msg,
+            step: None,
+        };
+        assert_eq!(error, expected);
+    }
+
+    #[test]
+    fn submsg_error_codes() {
+        let code = ErrorCode::from_submsg_error;
+        assert_eq!(
+            code("codespace: gamm, code: 7"),
+            ErrorCode::SlippageExceeded
+        );
+        assert_eq!(
+            code("codespace: gamm, code: 6"),
+            ErrorCode::SlippageExceeded
+        );
+        assert_eq!(code("codespace: gamm, code: 1"), ErrorCode::PoolUnavailable);
+        assert_eq!(
+            code("codespace: sdk, code: 5"),
+            ErrorCode::InsufficientFunds
+        );
+
+        // the errors of the contracts called can't be told apart
+        assert_eq!(code("codespace: wasm, code: 5"), ErrorCode::ActionFailed);
+        assert_eq!(code("codespace: gamm, code: 21"), ErrorCode::ActionFailed);
+
+        // only the redacted format is parsed
+        let slippage =
+            "token amount calculated (10) is lesser than min amount (11): invalid request";
+        assert_eq!(code(slippage), ErrorCode::ActionFailed);
+        assert_eq!(code("codespace: gamm, code: x"), ErrorCode::ActionFailed);
     }
 }
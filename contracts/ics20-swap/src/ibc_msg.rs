@@ -1,6 +1,14 @@
-use cosmwasm_std::{Binary, Uint128, Uint64};
+use std::convert::TryFrom;
+use std::fmt;
+
+use cosmwasm_std::{
+    from_slice, to_vec, Binary, Coin, Decimal, StdResult, Timestamp, Uint128, Uint64,
+};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, value::MapAccessDeserializer, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::parse::pool_share_denom;
 
 /// The format for sending an ics20 packet.
 /// Proto defined here: https://github.com/cosmos/cosmos-sdk/blob/v0.42.0/proto/ibc/applications/transfer/v1/transfer.proto#L11-L20
@@ -18,6 +26,9 @@ pub struct Ics20Packet {
     /// Action packet
     #[serde(skip_serializing_if = "Option::is_none")]
     pub action: Option<OsmoPacket>,
+    /// ICS-20 memo, can hold the action or forward metadata as JSON, see `PacketMemo`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
 }
 
 impl Ics20Packet {
@@ -28,8 +39,222 @@ impl Ics20Packet {
             sender: sender.to_string(),
             receiver: receiver.to_string(),
             action: None,
+            memo: None,
+        }
+    }
+
+    pub fn with_memo(mut self, memo: Option<String>) -> Self {
+        self.memo = memo;
+        self
+    }
+}
+
+/// JSON memo understood in the ics20 packets, so that the standard transfer tooling can send
+/// actions. Memos that are not a JSON object are plain notes and are ignored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct PacketMemo {
+    /// Action run with the received tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osmo_action: Option<OsmoPacket>,
+    /// Action run with the received tokens, in the ibc-hooks format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<WasmMemo>,
+    /// packet-forward-middleware metadata, the action output (or the received tokens without
+    /// action) is sent on through another hop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward: Option<ForwardMemo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WasmMemo {
+    /// Must be this contract
+    pub contract: String,
+    pub msg: OsmoPacket,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ForwardMemo {
+    /// The remote address to send to
+    pub receiver: String,
+    /// Ignored, the packet is always sent from this contract port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    /// The local channel to send the packet on
+    pub channel: String,
+    /// How long the packet lives, as a Go duration string ("10m") or nanoseconds. If not
+    /// specified, use default_timeout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<GoDuration>,
+    /// Memo of the forwarded packet, for the next hop, as an object or a JSON string
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_next_memo"
+    )]
+    pub next: Option<Box<PacketMemo>>,
+}
+
+impl ForwardMemo {
+    pub fn return_to(&self) -> StdResult<ReturnTo> {
+        let memo = match &self.next {
+            Some(next) => Some(String::from_utf8(to_vec(next)?)?),
+            None => None,
+        };
+
+        Ok(ReturnTo {
+            channel: self.channel.clone(),
+            remote_address: self.receiver.clone(),
+            // at least one second, as the timeout is in seconds
+            timeout: self.timeout.map(|t| (t.nanos() / 1_000_000_000).max(1)),
+            memo,
+        })
+    }
+}
+
+/// The `next` memo of the packet-forward-middleware is either an object or the memo string
+fn deserialize_next_memo<'de, D>(deserializer: D) -> Result<Option<Box<PacketMemo>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct NextMemoVisitor;
+
+    impl<'de> Visitor<'de> for NextMemoVisitor {
+        type Value = Option<Box<PacketMemo>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a memo object or a JSON string")
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            from_slice(value.as_bytes())
+                .map(Some)
+                .map_err(|err| E::custom(format!("invalid next memo: {}", err)))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            PacketMemo::deserialize(MapAccessDeserializer::new(map)).map(|memo| Some(memo.into()))
         }
     }
+
+    deserializer.deserialize_any(NextMemoVisitor)
+}
+
+/// Duration of the packet-forward-middleware metadata, in nanoseconds. It is parsed from a Go
+/// duration string ("1h30m", "10m", "1.5s") or from a number (or numeric string) of nanoseconds,
+/// and serialized as a number, which every version of the middleware reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+pub struct GoDuration(u64);
+
+impl GoDuration {
+    pub const fn nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Parses a duration as formatted by Go `time.Duration.String()`, a sequence of decimal
+    /// numbers with unit, e.g. "300ms" or "2h45m". Negative durations are rejected.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut rest = value.strip_prefix('+').unwrap_or(value);
+        if rest == "0" {
+            return Some(GoDuration(0));
+        }
+        if rest.is_empty() {
+            return None;
+        }
+
+        let not_digit = |c: char| !c.is_ascii_digit();
+        let mut total: u64 = 0;
+        while !rest.is_empty() {
+            let (int, tail) = rest.split_at(rest.find(not_digit).unwrap_or(rest.len()));
+            let (frac, tail) = match tail.strip_prefix('.') {
+                Some(tail) => tail.split_at(tail.find(not_digit).unwrap_or(tail.len())),
+                None => ("", tail),
+            };
+            if int.is_empty() && frac.is_empty() {
+                return None;
+            }
+            let unit_len = tail
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit_len);
+            let unit: u64 = match unit {
+                "ns" => 1,
+                "us" | "\u{b5}s" | "\u{3bc}s" => 1_000,
+                "ms" => 1_000_000,
+                "s" => 1_000_000_000,
+                "m" => 60 * 1_000_000_000,
+                "h" => 60 * 60 * 1_000_000_000,
+                _ => return None,
+            };
+
+            let mut nanos = match int {
+                "" => 0,
+                int => int.parse::<u64>().ok()?.checked_mul(unit)?,
+            };
+            let mut scale = unit;
+            for digit in frac.bytes() {
+                scale /= 10;
+                nanos = nanos.checked_add(u64::from(digit - b'0') * scale)?;
+            }
+            total = total.checked_add(nanos)?;
+            rest = tail;
+        }
+        Some(GoDuration(total))
+    }
+}
+
+impl From<u64> for GoDuration {
+    fn from(nanos: u64) -> Self {
+        GoDuration(nanos)
+    }
+}
+
+impl Serialize for GoDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for GoDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(GoDurationVisitor)
+    }
+}
+
+struct GoDurationVisitor;
+
+impl<'de> Visitor<'de> for GoDurationVisitor {
+    type Value = GoDuration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a Go duration string or a number of nanoseconds")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(GoDuration(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        u64::try_from(value)
+            .map(GoDuration)
+            .map_err(|_| E::custom(format!("negative duration {}", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        // a numeric string is nanoseconds, as the Uint64 serialization
+        let parsed = match value.parse::<u64>() {
+            Ok(nanos) => Some(GoDuration(nanos)),
+            Err(_) => GoDuration::parse(value),
+        };
+        parsed.ok_or_else(|| E::custom(format!("invalid duration {}", value)))
+    }
 }
 
 /// This is a generic ICS acknowledgement format.
@@ -42,6 +267,24 @@ pub enum Ics20Ack {
     Error(String),
 }
 
+/// Version of the `ErrorAck` format
+pub const ERROR_ACK_VERSION: u32 = 1;
+
+/// Machine-readable failure, sent JSON encoded in `Ics20Ack::Error`
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ErrorAck {
+    pub version: u32,
+    /// Stable error code, see `ErrorCode`
+    pub code: u32,
+    /// Name of the failing action
+    pub action: String,
+    /// Human-readable error
+    pub details: String,
+    /// Index of the failing pipeline step, when it is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<u32>,
+}
+
 pub struct Voucher {
     pub denom: String,
 }
@@ -51,21 +294,168 @@ pub struct Voucher {
 pub enum OsmoPacket {
     /// Swap assets.
     Swap(SwapPacket),
+    /// Swap assets for an exact output amount.
+    SwapExactOut(SwapExactOutPacket),
     /// Join a specific pool.
     JoinPool(JoinPoolPacket),
     /// Exit a specific pool.
     ExitPool(ExitPoolPacket),
+    /// Join a specific pool with multiple assets, using the sender held funds.
+    MultiJoinPool(MultiJoinPoolPacket),
+    /// Exit a specific pool for all of its assets, using the sender held funds.
+    MultiExitPool(MultiExitPoolPacket),
+    /// Create a balancer pool, using the sender held funds.
+    CreateBalancerPool(CreateBalancerPoolPacket),
+    /// Hold the received tokens in the contract for the sender.
+    Deposit {},
+    /// Swap assets held for the sender.
+    SwapHeld(SwapHeldPacket),
+    /// Send assets held for the sender over ics20.
+    Withdraw(WithdrawPacket),
+    /// Run several actions in order, each step uses the output of the previous one.
+    /// Supports Swap, JoinPool and ExitPool steps, and Lock as the last step.
+    Pipeline(Vec<OsmoPacket>),
     LockupAccount {},
     Lock(LockPacket),
+    /// Claim the balance of some denoms of the sender lockup account.
     Claim(ClaimPacket),
+    /// Claim the whole balance of the sender lockup account.
+    ClaimAll(ClaimAllPacket),
     Unlock(UnlockPacket),
+    /// Extend the duration of a lock without unlocking it.
+    ExtendLock(ExtendLockPacket),
+    /// Begin unlocking all the locks of the sender lockup account.
+    UnlockAll {},
+}
+
+impl OsmoPacket {
+    /// Destination of the action output, if it must be sent back over IBC
+    pub fn return_to(&self) -> Option<ReturnTo> {
+        match self {
+            OsmoPacket::Swap(swap) => swap.return_to.clone(),
+            OsmoPacket::ExitPool(exit_pool) => exit_pool.return_to.clone(),
+            OsmoPacket::Claim(claim) => claim.return_to.clone(),
+            OsmoPacket::ClaimAll(claim) => claim.return_to.clone(),
+            OsmoPacket::SwapHeld(swap) => swap.return_to.clone(),
+            OsmoPacket::Withdraw(withdraw) => Some(withdraw.return_to.clone()),
+            OsmoPacket::Pipeline(steps) => steps.last().and_then(|step| step.return_to()),
+            _ => None,
+        }
+    }
+
+    /// Denom of the action output, as requested for the x/gamm actions
+    pub fn token_out_denom(&self) -> Option<String> {
+        match self {
+            OsmoPacket::Swap(swap) => swap.routes.last().map(|r| r.token_out_denom.clone()),
+            OsmoPacket::SwapExactOut(swap) => Some(swap.token_out_denom.clone()),
+            OsmoPacket::JoinPool(join_pool) => Some(pool_share_denom(join_pool.pool_id.u64())),
+            OsmoPacket::ExitPool(exit_pool) => Some(exit_pool.token_out_denom.clone()),
+            OsmoPacket::SwapHeld(swap) => swap.routes.last().map(|r| r.token_out_denom.clone()),
+            _ => None,
+        }
+    }
+
+    /// GAMM pools used by the action, the pipeline steps included. The pool exited by
+    /// `ExitPool` is only known from its input denom.
+    pub fn pool_ids(&self) -> Vec<u64> {
+        match self {
+            OsmoPacket::Swap(swap) => swap.routes.iter().map(|r| r.pool_id.u64()).collect(),
+            OsmoPacket::SwapExactOut(swap) => swap.routes.iter().map(|r| r.pool_id.u64()).collect(),
+            OsmoPacket::JoinPool(join_pool) => vec![join_pool.pool_id.u64()],
+            OsmoPacket::MultiJoinPool(join_pool) => vec![join_pool.pool_id.u64()],
+            OsmoPacket::MultiExitPool(exit_pool) => vec![exit_pool.pool_id.u64()],
+            OsmoPacket::SwapHeld(swap) => swap.routes.iter().map(|r| r.pool_id.u64()).collect(),
+            OsmoPacket::Pipeline(steps) => steps.iter().flat_map(|s| s.pool_ids()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Denoms output by the swaps and pool exits of the action, the pipeline steps included
+    pub fn denoms_out(&self) -> Vec<String> {
+        match self {
+            OsmoPacket::Swap(swap) => swap
+                .routes
+                .iter()
+                .map(|r| r.token_out_denom.clone())
+                .collect(),
+            OsmoPacket::SwapExactOut(swap) => swap
+                .routes
+                .iter()
+                .skip(1)
+                .map(|r| r.token_in_denom.clone())
+                .chain(std::iter::once(swap.token_out_denom.clone()))
+                .collect(),
+            OsmoPacket::ExitPool(exit_pool) => vec![exit_pool.token_out_denom.clone()],
+            OsmoPacket::MultiExitPool(exit_pool) => exit_pool
+                .token_out_mins
+                .iter()
+                .map(|c| c.denom.clone())
+                .collect(),
+            OsmoPacket::SwapHeld(swap) => swap
+                .routes
+                .iter()
+                .map(|r| r.token_out_denom.clone())
+                .collect(),
+            OsmoPacket::Pipeline(steps) => steps.iter().flat_map(|s| s.denoms_out()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Action name, as serialized in the packet
+    pub fn name(&self) -> &'static str {
+        match self {
+            OsmoPacket::Swap(_) => "swap",
+            OsmoPacket::SwapExactOut(_) => "swap_exact_out",
+            OsmoPacket::JoinPool(_) => "join_pool",
+            OsmoPacket::ExitPool(_) => "exit_pool",
+            OsmoPacket::MultiJoinPool(_) => "multi_join_pool",
+            OsmoPacket::MultiExitPool(_) => "multi_exit_pool",
+            OsmoPacket::CreateBalancerPool(_) => "create_balancer_pool",
+            OsmoPacket::Deposit {} => "deposit",
+            OsmoPacket::SwapHeld(_) => "swap_held",
+            OsmoPacket::Withdraw(_) => "withdraw",
+            OsmoPacket::Pipeline(_) => "pipeline",
+            OsmoPacket::LockupAccount {} => "lockup_account",
+            OsmoPacket::Lock(_) => "lock",
+            OsmoPacket::Claim(_) => "claim",
+            OsmoPacket::ClaimAll(_) => "claim_all",
+            OsmoPacket::Unlock(_) => "unlock",
+            OsmoPacket::ExtendLock(_) => "extend_lock",
+            OsmoPacket::UnlockAll {} => "unlock_all",
+        }
+    }
+}
+
+/// Send the action output back over ics20 in the same transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReturnTo {
+    /// The local channel to send the packets on
+    pub channel: String,
+    /// The remote address to send to
+    pub remote_address: String,
+    /// How long the packet lives in seconds. If not specified, use default_timeout
+    pub timeout: Option<u64>,
+    /// Memo of the packet sent, e.g. forward metadata for the next hop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
 }
 
 /// Swap Packet
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct SwapPacket {
     pub routes: Vec<SwapAmountInRoute>,
-    pub token_out_min_amount: Uint128,
+    /// Required unless `max_slippage` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_out_min_amount: Option<Uint128>,
+    /// Derive the min output from the pool spot prices, queried on receive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_slippage: Option<Decimal>,
+    /// Expected output by input token, optional with `max_slippage`. Raises the min output when
+    /// above the spot price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<ReturnTo>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -74,18 +464,106 @@ pub struct SwapAmountInRoute {
     pub token_out_denom: String,
 }
 
+/// SwapExactOut Packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapExactOutPacket {
+    pub routes: Vec<SwapAmountOutRoute>,
+    pub token_in_max_amount: Uint128,
+    pub token_out_denom: String,
+    pub token_out_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapAmountOutRoute {
+    pub pool_id: Uint64,
+    pub token_in_denom: String,
+}
+
 /// JoinPool Packet
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct JoinPoolPacket {
     pub pool_id: Uint64,
-    pub share_out_min_amount: Uint128,
+    /// Required unless `max_slippage` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_out_min_amount: Option<Uint128>,
+    /// Derive the min share output from the pool spot prices, queried on receive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_slippage: Option<Decimal>,
+    /// Expected shares by input token, optional with `max_slippage`. Raises the min shares when
+    /// above the spot price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_price: Option<Decimal>,
 }
 
-/// JoinPool Packet
+/// ExitPool Packet
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ExitPoolPacket {
     pub token_out_denom: String,
+    /// Required unless `max_slippage` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_out_min_amount: Option<Uint128>,
+    /// Derive the min output from the pool spot prices, queried on receive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_slippage: Option<Decimal>,
+    /// Expected output by share, optional with `max_slippage`. Raises the min output when above
+    /// the spot price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<ReturnTo>,
+}
+
+/// MultiJoinPool Packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MultiJoinPoolPacket {
+    pub pool_id: Uint64,
+    pub share_out_amount: Uint128,
+    pub token_in_maxs: Vec<Coin>,
+}
+
+/// MultiExitPool Packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MultiExitPoolPacket {
+    pub pool_id: Uint64,
+    pub share_in_amount: Uint128,
+    pub token_out_mins: Vec<Coin>,
+}
+
+/// CreateBalancerPool Packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreateBalancerPoolPacket {
+    pub pool_assets: Vec<PoolAsset>,
+    pub swap_fee: Decimal,
+    pub exit_fee: Decimal,
+    /// Who can govern the pool, none if empty
+    #[serde(default)]
+    pub future_pool_governor: String,
+    /// Pool creation fee charged by x/gamm, paid with the sender held funds
+    #[serde(default)]
+    pub pool_creation_fee: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PoolAsset {
+    pub token: Coin,
+    pub weight: Uint128,
+}
+
+/// SwapHeld Packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapHeldPacket {
+    pub token_in: Coin,
+    pub routes: Vec<SwapAmountInRoute>,
     pub token_out_min_amount: Uint128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<ReturnTo>,
+}
+
+/// Withdraw Packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawPacket {
+    pub coins: Vec<Coin>,
+    pub return_to: ReturnTo,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -94,8 +572,37 @@ pub struct LockPacket {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(from = "ClaimPacketCompat")]
 pub struct ClaimPacket {
-    pub denom: String,
+    pub denoms: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<ReturnTo>,
+}
+
+// Also accepts the single `denom` sent by the counterparts before 0.2.0
+#[derive(Deserialize)]
+struct ClaimPacketCompat {
+    #[serde(default)]
+    denoms: Vec<String>,
+    denom: Option<String>,
+    return_to: Option<ReturnTo>,
+}
+
+impl From<ClaimPacketCompat> for ClaimPacket {
+    fn from(packet: ClaimPacketCompat) -> Self {
+        let mut denoms = packet.denoms;
+        denoms.extend(packet.denom);
+        ClaimPacket {
+            denoms,
+            return_to: packet.return_to,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClaimAllPacket {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<ReturnTo>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -103,17 +610,58 @@ pub struct UnlockPacket {
     pub id: Uint64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExtendLockPacket {
+    pub id: Uint64,
+    /// New lock duration in seconds, cannot be shorter than the current one
+    pub duration: Uint64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct AmountResultAck {
     pub amount: Uint128,
     pub denom: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreatePoolResultAck {
+    pub pool_id: Uint64,
+    /// Pool shares minted for the sender
+    pub shares: AmountResultAck,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LockResultAck {
     pub lock_id: Uint64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExtendLockResultAck {
+    pub lock_id: Uint64,
+    pub duration: Uint64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnlockAllResultAck {
+    pub unlocks: Vec<UnlockingLockAck>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnlockingLockAck {
+    pub lock_id: Uint64,
+    pub coins: Vec<Coin>,
+    /// When the coins can be claimed
+    pub end_time: Timestamp,
+}
+
+/// Result of one pipeline step
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StepResultAck {
+    Amount(AmountResultAck),
+    Lock(LockResultAck),
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LockupAck {
     pub contract: String,
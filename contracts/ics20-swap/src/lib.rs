@@ -2,9 +2,10 @@ pub mod amount;
 pub mod contract;
 mod error;
 pub mod ibc;
-mod ibc_msg;
+pub mod ibc_msg;
 pub mod msg;
-mod parse;
+pub mod parse;
+mod slippage;
 pub mod state;
 mod test_helpers;
 
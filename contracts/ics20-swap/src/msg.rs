@@ -1,9 +1,10 @@
-use cosmwasm_std::Uint64;
+use cosmwasm_std::{Binary, Coin, Uint128, Uint64};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::amount::Amount;
-use crate::state::ChannelInfo;
+use crate::ibc_msg::{CreateBalancerPoolPacket, OsmoPacket};
+use crate::state::{ChannelInfo, TransferInfo};
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct InitMsg {
@@ -11,6 +12,28 @@ pub struct InitMsg {
     pub default_timeout: u64,
     /// Lockup code ID
     pub lockup_id: u64,
+    /// who can update the config, the instantiate sender if not set
+    #[serde(default)]
+    pub gov_contract: Option<String>,
+    /// Tokens native to the remote chains that can be received
+    #[serde(default)]
+    pub external_tokens: Vec<ExternalTokenMsg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    /// who can update the config, required when migrating from a version without admin
+    pub gov_contract: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExternalTokenMsg {
+    /// The local channel the token is received on
+    pub channel: String,
+    /// Denom on the remote chain
+    pub denom: String,
+    /// Local denom of the token received through the transfer module (ibc/<hash>)
+    pub local_denom: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -18,6 +41,95 @@ pub struct InitMsg {
 pub enum ExecuteMsg {
     /// This allows us to transfer *exactly one* native token
     Transfer(TransferMsg),
+    /// Transfer *exactly one* native token to a counterpart contract, which runs the action with
+    /// it. The ack of the action is sent to the callback contract as `CallbackMsg::ActionAck`.
+    TransferWithAction(TransferWithActionMsg),
+    /// Runs the steps of a pipeline action packet. Only the contract itself can call it, so
+    /// that a failing step reverts all the previous ones.
+    Pipeline(PipelineMsg),
+    /// Creates the pool of a create pool action packet. Only the contract itself can call it, so
+    /// that the pool creation is reverted when x/gamm charges more than the sender funds.
+    CreatePool(CreatePoolMsg),
+    /// Adds the sent external tokens to the channel balance, so that they can be paid out when
+    /// the remote chain sends its native tokens
+    AddReserve(ReserveMsg),
+    /// Withdraws external tokens the sender added to the channel reserve, as long as they have
+    /// not been paid out
+    WithdrawReserve {
+        channel: String,
+        /// The local denom of the external token
+        denom: String,
+        amount: Uint128,
+    },
+    /// Accept the token native to the remote chain of the channel (must be called by current
+    /// admin)
+    AddExternalToken(ExternalTokenMsg),
+    /// Stop accepting the token native to the remote chain, the reserve can still be withdrawn
+    /// (must be called by current admin)
+    RemoveExternalToken {
+        channel: String,
+        /// Denom on the remote chain
+        denom: String,
+    },
+    /// Update the default timeout, the lockup code ID or whether only the allowed pools can be
+    /// used (must be called by current admin)
+    UpdateConfig {
+        default_timeout: Option<u64>,
+        lockup_id: Option<u64>,
+        restrict_pools: Option<bool>,
+    },
+    /// Change the admin (must be called by current admin)
+    UpdateAdmin { admin: String },
+    /// Migrate the lockup account of the remote owner to the lockup code ID of the config. Only
+    /// the accounts created since 0.2.0 have the contract as wasm admin and can be migrated (must
+    /// be called by current admin)
+    MigrateLockup { channel: String, owner: String },
+    /// Stop sending and receiving packets on the channel (must be called by current admin)
+    FreezeChannel { channel: String },
+    /// Resume a frozen channel (must be called by current admin)
+    UnfreezeChannel { channel: String },
+    /// Limit the amounts of a denom sent and received on the channel over a rolling period, or
+    /// update the limit keeping the flows already recorded (must be called by current admin)
+    SetRateLimit(RateLimitMsg),
+    /// Stop limiting the denom on the channel (must be called by current admin)
+    RemoveRateLimit { channel: String, denom: String },
+    /// Allow the actions to use the GAMM pools, when the config restricts them (must be called
+    /// by current admin)
+    AllowPools { pool_ids: Vec<Uint64> },
+    /// Stop allowing the GAMM pools (must be called by current admin)
+    DisallowPools { pool_ids: Vec<Uint64> },
+    /// Allow the actions of the channel senders to output the denoms. Once a denom is allowed,
+    /// the others are rejected on the channel (must be called by current admin)
+    AllowDenoms {
+        channel: String,
+        denoms: Vec<String>,
+    },
+    /// Stop allowing the denoms on the channel, any denom is allowed again once none is left
+    /// (must be called by current admin)
+    DisallowDenoms {
+        channel: String,
+        denoms: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RateLimitMsg {
+    /// The local channel
+    pub channel: String,
+    /// The local denom
+    pub denom: String,
+    /// Max amount received over a period
+    pub max_inflow: Uint128,
+    /// Max amount sent over a period
+    pub max_outflow: Uint128,
+    /// Length of the rolling period in seconds, e.g. 86400 for a day
+    pub period: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReserveMsg {
+    /// The local channel receiving the external tokens
+    pub channel: String,
 }
 
 /// This is the message we accept via Receive
@@ -31,6 +143,86 @@ pub struct TransferMsg {
     pub remote_address: String,
     /// How long the packet lives in seconds. If not specified, use default_timeout
     pub timeout: Option<u64>,
+    /// Contract called with the packet ack or timeout
+    #[serde(default)]
+    pub callback: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TransferWithActionMsg {
+    /// The local channel to send the packets on
+    pub channel: String,
+    /// The counterpart contract on the remote chain
+    pub remote_address: String,
+    /// How long the packet lives in seconds. If not specified, use default_timeout
+    pub timeout: Option<u64>,
+    /// The action run by the counterpart contract
+    pub action: OsmoPacket,
+    /// Contract called with the result of the action
+    pub callback: Option<String>,
+}
+
+/// Message sent to the callback contracts, they must handle it in their `ExecuteMsg`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallbackMsg {
+    /// Result of an action sent with `TransferWithAction`
+    ActionAck(ActionAckMsg),
+    /// Result of a plain `Transfer`
+    IbcTransferCallback(IbcTransferCallback),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ActionAckMsg {
+    /// The local channel the packet was sent on
+    pub channel: String,
+    pub packet_sequence: u64,
+    /// Name of the action, as serialized in the packet
+    pub action: String,
+    pub result: ActionAckResult,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IbcTransferCallback {
+    /// The local channel the packet was sent on
+    pub channel: String,
+    pub packet_sequence: u64,
+    /// Whether the tokens were received, otherwise they were refunded
+    pub success: bool,
+    /// The ack data of the remote chain, set on success
+    pub ack_data: Option<Binary>,
+    /// The error ack or `timeout`, set on failure
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionAckResult {
+    /// The action result acked by the counterpart contract, e.g. an `AmountResultAck`
+    Success { data: Binary },
+    /// The action failed and the tokens were refunded. `code` is set when the counterpart
+    /// contract sent an `ErrorAck`.
+    Error { code: Option<u32>, details: String },
+    /// The packet timed out and the tokens were refunded
+    Timeout {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreatePoolMsg {
+    pub pool: CreateBalancerPoolPacket,
+    /// The received and held funds of the sender, the most the pool creation can spend
+    pub funds: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PipelineMsg {
+    /// The channel the packet was received on
+    pub channel: String,
+    /// The remote sender of the packet
+    pub sender: String,
+    /// The input of the first step
+    pub token_in: Coin,
+    pub steps: Vec<OsmoPacket>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -41,14 +233,64 @@ pub enum QueryMsg {
     /// Returns the details of the name channel, error if not created.
     /// Return type: ChannelResponse.
     Channel { id: String },
-    /// Show the Config. Returns ConfigResponse
+    /// Show the Config and the admin allowed to update it.
+    /// Return type: ConfigResponse.
     Config {},
+    /// Return AdminResponse
+    Admin {},
     /// Returns the lockup address of the channel and owner, empty if not created.
     /// Return type: LockupResponse.
     Lockup { channel: String, owner: String },
     /// Show all lockups created by channel.
     /// Return type: ListLockupResponse.
     AllLockups { channel: String },
+    /// Returns the funds held for the remote sender on the channel, empty if none.
+    /// Return type: UserBalanceResponse.
+    UserBalance { channel: String, sender: String },
+    /// Show the funds held for every remote sender on the channel.
+    /// Return type: ListUserBalancesResponse.
+    ListUserBalances { channel: String },
+    /// Show all the tokens native to the remote chains that can be received.
+    /// Return type: ListExternalTokensResponse.
+    ListExternalTokens {},
+    /// Returns the external tokens the provider can withdraw from the channel reserve, zero if
+    /// none. Return type: ReserveResponse.
+    Reserve {
+        channel: String,
+        /// The local denom of the external token
+        denom: String,
+        provider: String,
+    },
+    /// Show the packets sent by the local sender that are not acked or timed out yet, ordered by
+    /// channel and sequence. Return type: PendingTransfersResponse.
+    PendingTransfers {
+        sender: String,
+        /// (channel, sequence) of the last transfer of the previous page
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    /// Returns the packet sent on the channel with the sequence and its status, error if not
+    /// found. Return type: TransferResponse.
+    Transfer { channel: String, sequence: u64 },
+    /// Returns the rate limit of the denom on the channel with the quota remaining in the
+    /// current period, error if not limited. Return type: RateLimitResponse.
+    RateLimit { channel: String, denom: String },
+    /// Show the rate limits of every denom on the channel.
+    /// Return type: ListRateLimitsResponse.
+    ListRateLimits { channel: String },
+    /// Show the GAMM pools the actions can use, ordered by id.
+    /// Return type: ListAllowedPoolsResponse.
+    ListAllowedPools {
+        start_after: Option<Uint64>,
+        limit: Option<u32>,
+    },
+    /// Show the denoms the actions of the channel senders can output, any if empty.
+    /// Return type: ListAllowedDenomsResponse.
+    ListAllowedDenoms {
+        channel: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -70,6 +312,11 @@ pub struct ChannelResponse {
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 pub struct ConfigResponse {
     pub default_timeout: u64,
+    pub lockup_id: u64,
+    /// the current admin, empty if none
+    pub gov_contract: String,
+    /// only the allowed pools can be used by the actions
+    pub restrict_pools: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -85,17 +332,102 @@ pub struct ListLockupResponse {
     pub lockups: Vec<LockupResponse>,
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct UserBalanceResponse {
+    /// Remote sender
+    pub sender: String,
+    /// Funds held for the sender
+    pub balances: Vec<Amount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListUserBalancesResponse {
+    pub users: Vec<UserBalanceResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListExternalTokensResponse {
+    pub tokens: Vec<ExternalTokenMsg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ReserveResponse {
+    pub provider: String,
+    /// External tokens the provider can withdraw
+    pub amount: Amount,
+    /// Shares of the provider in the channel reserve
+    pub shares: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TransferResponse {
+    /// The local channel the packet was sent on
+    pub channel: String,
+    pub sequence: u64,
+    pub info: TransferInfo,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct PendingTransfersResponse {
+    pub transfers: Vec<TransferResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RateLimitResponse {
+    pub channel: String,
+    pub denom: String,
+    pub max_inflow: Uint128,
+    pub max_outflow: Uint128,
+    /// Length of the rolling period in seconds
+    pub period: u64,
+    /// Amount that can still be received, until the flows of the last period leave it
+    pub remaining_inflow: Uint128,
+    /// Amount that can still be sent, until the flows of the last period leave it
+    pub remaining_outflow: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListRateLimitsResponse {
+    pub limits: Vec<RateLimitResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListAllowedPoolsResponse {
+    /// Whether the config restricts the actions to the allowed pools
+    pub restricted: bool,
+    pub pool_ids: Vec<Uint64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ListAllowedDenomsResponse {
+    pub denoms: Vec<String>,
+}
+
 // Lockup contract InstantiateMsg
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct LockupInitMsg {
     pub admin: String,
 }
 
+// Lockup contract MigrateMsg
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct LockupMigrateMsg {}
+
 // Lockup contract ExecuteMsg
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum LockupExecuteMsg {
     Lock { duration: Uint64 },
     Unlock { id: Uint64 },
+    ExtendLock { id: Uint64, duration: Uint64 },
+    UnlockAll {},
+    Claim { denoms: Vec<String> },
+    ClaimAll {},
+}
+
+// ExecuteMsg of the lockup contracts before 0.2.0, they claim a single denom
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LegacyLockupExecuteMsg {
     Claim { denom: String },
 }
@@ -1,11 +1,13 @@
 use crate::ibc_msg::AmountResultAck;
 use crate::ContractError;
-use cosmwasm_std::{Attribute, Coin, Event, SubMsgResponse, Uint128};
+use cosmwasm_std::{Attribute, Coin, Decimal, Event, SubMsgResponse, Uint128};
+use cw_osmo_proto::osmosis::gamm::poolmodels::balancer::v1beta1::MsgCreateBalancerPoolResponse;
 use cw_osmo_proto::osmosis::gamm::v1beta1::{
     MsgExitSwapShareAmountInResponse, MsgJoinSwapExternAmountInResponse,
-    MsgSwapExactAmountInResponse,
+    MsgSwapExactAmountInResponse, MsgSwapExactAmountOutResponse,
 };
 use cw_osmo_proto::proto_ext::proto_decode;
+use std::str::FromStr;
 
 pub const SWAP_EVENT: &str = "token_swapped";
 pub const SWAP_ATTR: &str = "tokens_out";
@@ -13,6 +15,19 @@ pub const JOIN_POOL_EVENT: &str = "coinbase";
 pub const JOIN_POOL_ATTR: &str = "amount";
 pub const EXIT_POOL_EVENT: &str = "token_swapped";
 pub const EXIT_POOL_ATTR: &str = "tokens_out";
+pub const POOL_JOINED_EVENT: &str = "pool_joined";
+pub const POOL_JOINED_ATTR: &str = "tokens_in";
+pub const POOL_EXITED_EVENT: &str = "pool_exited";
+pub const POOL_EXITED_ATTR: &str = "tokens_out";
+pub const BURN_EVENT: &str = "burn";
+pub const BURN_ATTR: &str = "amount";
+pub const POOL_CREATED_EVENT: &str = "pool_created";
+pub const POOL_ID_ATTR: &str = "pool_id";
+pub const COIN_SPENT_EVENT: &str = "coin_spent";
+pub const SPENDER_ATTR: &str = "spender";
+pub const COIN_SPENT_ATTR: &str = "amount";
+pub const SEND_PACKET_EVENT: &str = "send_packet";
+pub const PACKET_SEQUENCE_ATTR: &str = "packet_sequence";
 
 pub trait GammResult {
     fn amount(&self) -> &String;
@@ -40,6 +55,10 @@ pub fn find_event_type(events: Vec<Event>, key: &str) -> Option<Event> {
     events.into_iter().find(|ev| ev.ty.eq(&key))
 }
 
+pub fn find_last_event_type(events: Vec<Event>, key: &str) -> Option<Event> {
+    events.into_iter().rev().find(|ev| ev.ty.eq(&key))
+}
+
 pub fn find_attributes(attributes: Vec<Attribute>, key: &str) -> Vec<String> {
     let mut values = vec![];
     for attr in attributes {
@@ -51,21 +70,19 @@ pub fn find_attributes(attributes: Vec<Attribute>, key: &str) -> Vec<String> {
     values
 }
 
-pub fn parse_coin(value: &str) -> Result<Coin, ContractError> {
-    let mut num_str = vec![];
-    for c in value.chars() {
-        if !c.is_numeric() {
-            break;
-        }
-
-        num_str.push(c)
-    }
+/// Coin with a decimal amount, as the SDK `DecCoin`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecCoin {
+    pub denom: String,
+    pub amount: Decimal,
+}
 
-    let amount_str: String = num_str.into_iter().collect();
-    let amount = amount_str
+// value: 100uatom, the denom can contain digits: 100ibc/100ABC, 5factory/osmo1.../5token
+pub fn parse_coin(value: &str) -> Result<Coin, ContractError> {
+    let (amount, denom) = split_coin(value, |c| c.is_ascii_digit())?;
+    let amount = amount
         .parse::<u128>()
         .map_err(|_| ContractError::InvalidAmountValue {})?;
-    let denom = value.replace(amount_str.as_str(), "");
 
     Ok(Coin {
         amount: amount.into(),
@@ -73,6 +90,79 @@ pub fn parse_coin(value: &str) -> Result<Coin, ContractError> {
     })
 }
 
+// value: 1.5uatom, 100uatom or .5uatom
+pub fn parse_dec_coin(value: &str) -> Result<DecCoin, ContractError> {
+    let (amount, denom) = split_coin(value, |c| c.is_ascii_digit() || c == '.')?;
+    if amount.ends_with('.') || amount.matches('.').count() > 1 {
+        return Err(ContractError::InvalidAmountValue {});
+    }
+    // the SDK accepts a missing integer part
+    let amount = if amount.starts_with('.') {
+        Decimal::from_str(&format!("0{}", amount))
+    } else {
+        Decimal::from_str(amount)
+    }
+    .map_err(|_| ContractError::InvalidAmountValue {})?;
+
+    Ok(DecCoin { amount, denom })
+}
+
+// value: 100uatom,200uosmo
+pub fn parse_coins(value: &str) -> Result<Vec<Coin>, ContractError> {
+    split_coins(value).map(parse_coin).collect()
+}
+
+// value: 1.5uatom,200uosmo
+pub fn parse_dec_coins(value: &str) -> Result<Vec<DecCoin>, ContractError> {
+    split_coins(value).map(parse_dec_coin).collect()
+}
+
+// an empty list has no coins, as in the SDK
+fn split_coins(value: &str) -> impl Iterator<Item = &str> {
+    let value = value.trim();
+    value.split(',').filter(move |_| !value.is_empty())
+}
+
+// Splits <amount><denom>, the denom starts with a letter so the amount ends at the first
+// character that can't be part of it. Whitespace is allowed around the coin and between both.
+fn split_coin(value: &str, is_amount: fn(char) -> bool) -> Result<(&str, String), ContractError> {
+    let value = value.trim();
+    let split = value.find(|c: char| !is_amount(c)).unwrap_or(value.len());
+    let (amount, denom) = value.split_at(split);
+    if amount.is_empty() {
+        return Err(ContractError::InvalidAmountValue {});
+    }
+
+    let denom = denom.trim_start();
+    validate_denom(denom)?;
+
+    Ok((amount, denom.to_string()))
+}
+
+// Same rule as the SDK denom regex: [a-zA-Z][a-zA-Z0-9/:._-]{2,127}
+pub fn validate_denom(denom: &str) -> Result<(), ContractError> {
+    let invalid = || ContractError::InvalidDenom {
+        denom: denom.to_string(),
+    };
+    if denom.len() < 3 || denom.len() > 128 {
+        return Err(invalid());
+    }
+
+    let mut chars = denom.chars();
+    if !chars.next().is_some_and(|c| c.is_ascii_alphabetic()) {
+        return Err(invalid());
+    }
+    if !chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | ':' | '.' | '_' | '-')) {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+pub fn pool_share_denom(pool_id: u64) -> String {
+    format!("gamm/pool/{}", pool_id)
+}
+
 pub fn parse_pool_id(denom: &str) -> Result<u64, ContractError> {
     // denom: gamm/pool/1
     let pool_str = denom.trim_start_matches("gamm/pool/");
@@ -83,23 +173,51 @@ pub fn parse_pool_id(denom: &str) -> Result<u64, ContractError> {
     Ok(pool_id)
 }
 
-pub fn parse_gamm_result<M: GammResult + cw_osmo_proto::Message + std::default::Default>(
-    msg: SubMsgResponse,
+fn find_last_attribute(
+    events: Vec<Event>,
     event: &str,
     attribute: &str,
-) -> Result<AmountResultAck, ContractError> {
-    let event = find_event_type(msg.events, event);
+) -> Result<String, ContractError> {
+    let event = find_event_type(events, event);
     if event.is_none() {
         return Err(ContractError::GammResultNotFound {});
     }
 
     let values = find_attributes(event.unwrap().attributes, attribute);
-    if values.is_empty() {
-        return Err(ContractError::GammResultNotFound {});
-    }
+    values
+        .last()
+        .cloned()
+        .ok_or(ContractError::GammResultNotFound {})
+}
+
+/// Returns the sequence of the packet sent, from the ibc events.
+pub fn parse_packet_sequence(events: Vec<Event>) -> Result<u64, ContractError> {
+    find_event_type(events, SEND_PACKET_EVENT)
+        .and_then(|event| find_attributes(event.attributes, PACKET_SEQUENCE_ATTR).pop())
+        .and_then(|value| value.parse::<u64>().ok())
+        .ok_or(ContractError::PacketSequenceNotFound {})
+}
 
-    let token_out_str = values.last().unwrap();
-    let token_out = parse_coin(token_out_str.as_str())?;
+/// Returns all the coins listed in the last matching event attribute.
+pub fn parse_event_coins(
+    events: Vec<Event>,
+    event: &str,
+    attribute: &str,
+) -> Result<Vec<Coin>, ContractError> {
+    let coins_str = find_last_attribute(events, event, attribute)?;
+    parse_coins(coins_str.as_str())
+}
+
+/// Returns the x/gamm action output. The denom comes from the request and the amount from the
+/// decoded response, `events` optionally names the attribute reporting the output to cross-check.
+pub fn parse_gamm_result<M: GammResult + cw_osmo_proto::Message + std::default::Default>(
+    msg: SubMsgResponse,
+    denom_out: &str,
+    events: Option<(&str, &str)>,
+) -> Result<AmountResultAck, ContractError> {
+    if let Some((event, attribute)) = events {
+        check_event_denom(msg.events, event, attribute, denom_out)?;
+    }
 
     let data = msg.data.ok_or(ContractError::NoReplyData {})?;
     let response: M = proto_decode(data.as_slice())?;
@@ -110,19 +228,103 @@ pub fn parse_gamm_result<M: GammResult + cw_osmo_proto::Message + std::default::
 
     let ack = AmountResultAck {
         amount: Uint128::from(amount),
-        denom: token_out.denom,
+        denom: denom_out.to_string(),
     };
 
     Ok(ack)
 }
 
+/// Returns the id of the pool created, from the response or the x/gamm events.
+pub fn parse_create_pool_id(msg: &SubMsgResponse) -> Result<u64, ContractError> {
+    let pool_id = msg
+        .data
+        .as_ref()
+        .and_then(|data| proto_decode::<MsgCreateBalancerPoolResponse>(data.as_slice()).ok())
+        .map(|response| response.pool_id)
+        .unwrap_or_default();
+    if pool_id > 0 {
+        return Ok(pool_id);
+    }
+
+    // the response is empty before osmosis v10
+    find_last_attribute(msg.events.clone(), POOL_CREATED_EVENT, POOL_ID_ATTR)?
+        .parse::<u64>()
+        .map_err(|_| ContractError::GammResultNotFound {})
+}
+
+/// Returns all the coins spent by the account, as reported by the bank events.
+pub fn parse_spent_coins(events: &[Event], spender: &str) -> Result<Vec<Coin>, ContractError> {
+    let mut coins = vec![];
+    for event in events.iter().filter(|ev| ev.ty == COIN_SPENT_EVENT) {
+        let spenders = find_attributes(event.attributes.clone(), SPENDER_ATTR);
+        if spenders.iter().any(|s| s == spender) {
+            for value in find_attributes(event.attributes.clone(), COIN_SPENT_ATTR) {
+                coins.extend(parse_coins(&value)?);
+            }
+        }
+    }
+
+    Ok(coins)
+}
+
+/// Returns the swap output requested and the input amount spent by the swap.
+pub fn parse_swap_exact_out_result(
+    msg: SubMsgResponse,
+    token_out: Coin,
+) -> Result<(AmountResultAck, Uint128), ContractError> {
+    check_event_denom(msg.events, SWAP_EVENT, SWAP_ATTR, &token_out.denom)?;
+
+    let data = msg.data.ok_or(ContractError::NoReplyData {})?;
+    let response: MsgSwapExactAmountOutResponse = proto_decode(data.as_slice())?;
+    let token_in_amount = response
+        .token_in_amount
+        .parse::<u128>()
+        .map_err(|_| ContractError::InvalidAmountValue {})?;
+
+    let ack = AmountResultAck {
+        amount: token_out.amount,
+        denom: token_out.denom,
+    };
+
+    Ok((ack, Uint128::from(token_in_amount)))
+}
+
+// The events are only a cross-check of the response: a missing or unreadable output is
+// accepted, but the output reported by the last event, the last hop of a multi-hop swap, must
+// list the requested denom among its coins.
+fn check_event_denom(
+    events: Vec<Event>,
+    event: &str,
+    attribute: &str,
+    denom: &str,
+) -> Result<(), ContractError> {
+    let value = match find_last_event_type(events, event)
+        .and_then(|event| find_attributes(event.attributes, attribute).pop())
+    {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    let coins = match parse_coins(&value) {
+        Ok(coins) if !coins.is_empty() => coins,
+        _ => return Ok(()),
+    };
+    if !coins.iter().any(|coin| coin.denom == denom) {
+        return Err(ContractError::GammResultMismatch {
+            denom: denom.to_string(),
+            value,
+        });
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::test_helpers::*;
 
     use crate::ContractError;
-    use cosmwasm_std::{Binary, SubMsgResponse, Uint128};
+    use cosmwasm_std::{coin, Binary, SubMsgResponse, Uint128};
 
     #[test]
     fn parse_token_str() {
@@ -136,8 +338,20 @@ mod test {
                 true,
             ),
             ("6543gamm/pool/1", 6543u64, "gamm/pool/1", true),
+            ("100ibc/100ABC", 100u64, "ibc/100ABC", true),
+            (
+                "5factory/osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj/5token",
+                5u64,
+                "factory/osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj/5token",
+                true,
+            ),
+            (" 12 uosmo ", 12u64, "uosmo", true),
             ("aafffbbcc", 0u64, "", false),
             ("x6557", 0u64, "", false),
+            ("100", 0u64, "", false),
+            ("100ab", 0u64, "", false),
+            ("100uosmo!", 0u64, "", false),
+            ("1.5uosmo", 0u64, "", false),
         ];
 
         for case in cases {
@@ -154,12 +368,96 @@ mod test {
         }
     }
 
+    #[test]
+    fn parse_coins_str() {
+        let coins = parse_coins(
+            "100008ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2,4985011uosmo",
+        )
+        .unwrap();
+        assert_eq!(
+            coins,
+            vec![
+                coin(
+                    100008,
+                    "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"
+                ),
+                coin(4985011, "uosmo"),
+            ]
+        );
+
+        assert_eq!(parse_coins("").unwrap(), vec![]);
+        assert_eq!(
+            parse_coins(" 1uosmo, 2uion ").unwrap(),
+            vec![coin(1, "uosmo"), coin(2, "uion")]
+        );
+        parse_coins("1uosmo,").unwrap_err();
+        parse_coins("1uosmo,,2uion").unwrap_err();
+    }
+
+    #[test]
+    fn parse_dec_coins_str() {
+        let coins = parse_dec_coins("1.5uosmo,.25gamm/pool/1,3ibc/3A").unwrap();
+        assert_eq!(
+            coins,
+            vec![
+                DecCoin {
+                    denom: "uosmo".to_string(),
+                    amount: Decimal::from_str("1.5").unwrap(),
+                },
+                DecCoin {
+                    denom: "gamm/pool/1".to_string(),
+                    amount: Decimal::from_str("0.25").unwrap(),
+                },
+                DecCoin {
+                    denom: "ibc/3A".to_string(),
+                    amount: Decimal::from_str("3").unwrap(),
+                },
+            ]
+        );
+
+        for invalid in ["1.uosmo", "1.2.3uosmo", ".uosmo", "1.5", "1.5u"] {
+            parse_dec_coin(invalid).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn validate_denoms() {
+        for denom in [
+            "uosmo",
+            "gamm/pool/1",
+            "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2",
+            "factory/osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj/token",
+            "cw20:osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj",
+            "u-token_v1.0",
+        ] {
+            validate_denom(denom).unwrap();
+        }
+
+        for denom in [
+            "",
+            "ab",
+            "1osmo",
+            "/osmo",
+            "u osmo",
+            "uosmo!",
+            &"a".repeat(129),
+        ] {
+            let err = validate_denom(denom).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::InvalidDenom {
+                    denom: denom.to_string()
+                }
+            );
+        }
+    }
+
     #[test]
     fn find_events_attributes() {
         let events = mock_swap_events();
 
         let event = find_event_type(events, SWAP_EVENT);
-        assert_eq!(true, event.is_some());
+        assert!(event.is_some());
 
         let attrs = find_attributes(event.unwrap().attributes, SWAP_ATTR);
         assert_eq!(2, attrs.len());
@@ -167,29 +465,143 @@ mod test {
 
     #[test]
     fn parse_swap_result() {
+        let swap_events = Some((SWAP_EVENT, SWAP_ATTR));
         let msg = SubMsgResponse {
-            events: mock_join_pool_events(),
+            events: mock_swap_events(),
             data: None,
         };
         let err_result =
-            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, SWAP_EVENT, SWAP_ATTR)
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events)
+                .unwrap_err();
+        assert_eq!(ContractError::NoReplyData {}, err_result);
+
+        // the response is enough when the events don't report the output
+        let data = Binary::from_base64("CggzNjYwMTA3MA==").unwrap();
+        for events in [
+            vec![],
+            mock_join_pool_events(),
+            vec![Event::new(SWAP_EVENT).add_attribute("tokens_in", "100uatom")],
+            vec![Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, "")],
+        ] {
+            let msg = SubMsgResponse {
+                events,
+                data: Some(data.clone()),
+            };
+            let token =
+                parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events)
+                    .unwrap();
+            assert_eq!(Uint128::new(36601070), token.amount);
+            assert_eq!("uosmo", token.denom);
+        }
+
+        // a multi-hop swap is checked against the output of its last hop
+        let hops = |first: &str, last: &str| SubMsgResponse {
+            events: vec![
+                Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, first),
+                Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, last),
+            ],
+            data: Some(data.clone()),
+        };
+        let msg = hops("500uion", "36601070uosmo");
+        let token =
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events).unwrap();
+        assert_eq!(Uint128::new(36601070), token.amount);
+        let msg = hops("500uosmo", "36601070uion");
+        let err_result =
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events)
+                .unwrap_err();
+        assert_eq!(
+            ContractError::GammResultMismatch {
+                denom: "uosmo".to_string(),
+                value: "36601070uion".to_string(),
+            },
+            err_result
+        );
+
+        // the events must report the requested denom
+        let msg = mock_swap_response();
+        let err_result =
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "smo", swap_events).unwrap_err();
+        assert_eq!(
+            ContractError::GammResultMismatch {
+                denom: "smo".to_string(),
+                value: "36601070uosmo".to_string(),
+            },
+            err_result
+        );
+
+        // the events are only checked without them
+        let msg = SubMsgResponse {
+            events: vec![],
+            data: Some(data.clone()),
+        };
+        let token = parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", None).unwrap();
+        assert_eq!(Uint128::new(36601070), token.amount);
+        assert_eq!("uosmo", token.denom);
+
+        // the denom is the requested one when the events report several coins
+        let msg = SubMsgResponse {
+            events: vec![Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, "5uatom,36601070uosmo")],
+            data: Some(data.clone()),
+        };
+        let token =
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events).unwrap();
+        assert_eq!(Uint128::new(36601070), token.amount);
+        assert_eq!("uosmo", token.denom);
+        let msg = SubMsgResponse {
+            events: vec![Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, "5uatom,36601070uion")],
+            data: Some(data),
+        };
+        let err_result =
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events)
                 .unwrap_err();
-        assert_eq!(ContractError::GammResultNotFound {}, err_result);
+        assert_eq!(
+            ContractError::GammResultMismatch {
+                denom: "uosmo".to_string(),
+                value: "5uatom,36601070uion".to_string(),
+            },
+            err_result
+        );
+
+        // the denom is not guessed from the event value
+        let ibc_denom = "ibc/36601070";
+        let msg = SubMsgResponse {
+            events: vec![Event::new(SWAP_EVENT).add_attribute(SWAP_ATTR, "36601070ibc/36601070")],
+            data: Some(Binary::from_base64("CggzNjYwMTA3MA==").unwrap()),
+        };
+        let token =
+            parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, ibc_denom, swap_events).unwrap();
+        assert_eq!(Uint128::new(36601070), token.amount);
+        assert_eq!(ibc_denom, token.denom);
 
         let events = mock_swap_events();
         let msg = SubMsgResponse {
             events,
             data: Some(Binary::from_base64("CggzNjYwMTA3MA==").unwrap()),
         };
-        let result = parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, SWAP_EVENT, SWAP_ATTR);
+        let result = parse_gamm_result::<MsgSwapExactAmountInResponse>(msg, "uosmo", swap_events);
 
-        assert_eq!(true, result.is_ok());
+        assert!(result.is_ok());
         let token = result.unwrap();
 
         assert_eq!(Uint128::new(36601070), token.amount);
         assert_eq!("uosmo", token.denom);
     }
 
+    #[test]
+    fn parse_swap_exact_out() {
+        let msg = SubMsgResponse {
+            events: mock_swap_events(),
+            data: Some(Binary::from_base64("Cgc5ODUwMDAw").unwrap()),
+        };
+        let (token_out, token_in_amount) =
+            parse_swap_exact_out_result(msg, coin(36601070, "uosmo")).unwrap();
+
+        assert_eq!(Uint128::new(36601070), token_out.amount);
+        assert_eq!("uosmo", token_out.denom);
+        assert_eq!(Uint128::new(9850000), token_in_amount);
+    }
+
     #[test]
     fn parse_join_pool_result() {
         let events = mock_join_pool_events();
@@ -199,11 +611,11 @@ mod test {
         };
         let result = parse_gamm_result::<MsgJoinSwapExternAmountInResponse>(
             msg,
-            JOIN_POOL_EVENT,
-            JOIN_POOL_ATTR,
+            &pool_share_denom(1),
+            Some((JOIN_POOL_EVENT, JOIN_POOL_ATTR)),
         );
 
-        assert_eq!(true, result.is_ok());
+        assert!(result.is_ok());
         let token = result.unwrap();
 
         assert_eq!(Uint128::new(74196993097318119147), token.amount);
@@ -219,14 +631,48 @@ mod test {
         };
         let result = parse_gamm_result::<MsgExitSwapShareAmountInResponse>(
             msg,
-            EXIT_POOL_EVENT,
-            EXIT_POOL_ATTR,
+            "uosmo",
+            Some((EXIT_POOL_EVENT, EXIT_POOL_ATTR)),
         );
 
-        assert_eq!(true, result.is_ok());
+        assert!(result.is_ok());
         let token = result.unwrap();
 
         assert_eq!(Uint128::new(9970022), token.amount);
         assert_eq!("uosmo", token.denom);
     }
+
+    #[test]
+    fn parse_exit_pool_coins() {
+        let events = mock_exit_pool_events();
+
+        let tokens_out =
+            parse_event_coins(events.clone(), POOL_EXITED_EVENT, POOL_EXITED_ATTR).unwrap();
+        assert_eq!(
+            tokens_out,
+            vec![
+                Coin {
+                    amount: Uint128::new(100008),
+                    denom: "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"
+                        .to_string(),
+                },
+                Coin {
+                    amount: Uint128::new(4985011),
+                    denom: "uosmo".to_string(),
+                },
+            ]
+        );
+
+        let shares_in = parse_event_coins(events.clone(), BURN_EVENT, BURN_ATTR).unwrap();
+        assert_eq!(
+            shares_in,
+            vec![Coin {
+                amount: Uint128::new(74196993097318119147),
+                denom: "gamm/pool/1".to_string(),
+            }]
+        );
+
+        let res = parse_event_coins(events, POOL_JOINED_EVENT, POOL_JOINED_ATTR);
+        assert_eq!(res.unwrap_err(), ContractError::GammResultNotFound {});
+    }
 }
@@ -0,0 +1,266 @@
+use std::str::FromStr;
+
+use cosmwasm_std::{Coin, Decimal, Deps, Uint128};
+use cw_osmo_proto::osmosis::gamm::v1beta1::{
+    QuerySpotPriceRequest, QuerySpotPriceResponse, QuerySwapExactAmountInRequest,
+    QuerySwapExactAmountInResponse, QueryTotalPoolLiquidityRequest,
+    QueryTotalPoolLiquidityResponse, QueryTotalSharesRequest, QueryTotalSharesResponse,
+    SwapAmountInRoute,
+};
+use cw_osmo_proto::query::query_proto;
+
+use crate::ibc_msg::SwapPacket;
+use crate::ContractError;
+
+/// Returns the packet minimum, raised to the output at the pool spot prices minus `max_slippage`
+/// when it is set. `spot_output` queries the pools before dispatch, it is only called with a
+/// slippage. The spot output has no swap fee nor price impact, so the slippage bounds both, while
+/// the optional sender `reference_price` also bounds the pool price itself.
+pub fn min_amount(
+    min_amount: Option<Uint128>,
+    max_slippage: Option<Decimal>,
+    reference_price: Option<Decimal>,
+    amount_in: Uint128,
+    spot_output: impl FnOnce() -> Result<Uint128, ContractError>,
+) -> Result<Uint128, ContractError> {
+    let max_slippage = match (max_slippage, reference_price) {
+        (None, None) => return min_amount.ok_or(ContractError::MissingMinAmount {}),
+        (Some(max_slippage), _) if max_slippage <= Decimal::one() => max_slippage,
+        _ => return Err(ContractError::InvalidSlippage {}),
+    };
+
+    let mut expected = spot_output()?;
+    if let Some(price) = reference_price {
+        let reference = amount_in
+            .checked_multiply_ratio(price.atomics(), Decimal::one().atomics())
+            .map_err(|_| ContractError::AmountOverflow {})?;
+        expected = std::cmp::max(expected, reference);
+    }
+    let derived = expected * (Decimal::one() - max_slippage);
+
+    Ok(std::cmp::max(min_amount.unwrap_or_default(), derived))
+}
+
+/// Fails when the output estimated by the pool is already lower than the min amount, the action
+/// would fail on dispatch with a redacted error.
+pub fn check_min_amount(estimate: Uint128, min: Uint128) -> Result<(), ContractError> {
+    if estimate < min {
+        return Err(ContractError::MinAmountNotReached {
+            amount: estimate,
+            min,
+        });
+    }
+    Ok(())
+}
+
+/// Swap output estimated by the pools for the given input.
+pub fn estimate_swap(
+    deps: Deps,
+    contract: &str,
+    swap: &SwapPacket,
+    token_in: &Coin,
+) -> Result<Uint128, ContractError> {
+    let first = swap
+        .routes
+        .first()
+        .ok_or(ContractError::MinOutputUnavailable {})?;
+    let request = QuerySwapExactAmountInRequest {
+        sender: contract.to_string(),
+        pool_id: first.pool_id.u64(),
+        token_in: token_in.to_string(),
+        routes: swap
+            .routes
+            .iter()
+            .map(|r| SwapAmountInRoute {
+                token_out_denom: r.token_out_denom.to_owned(),
+                pool_id: r.pool_id.u64(),
+            })
+            .collect(),
+    };
+    let res: QuerySwapExactAmountInResponse = query_proto(deps, request)?;
+
+    res.token_out_amount
+        .parse::<u128>()
+        .map(Uint128::from)
+        .map_err(|_| ContractError::InvalidAmountValue {})
+}
+
+/// Swap output at the spot prices of the route pools, with no swap fee nor price impact.
+pub fn estimate_swap_at_spot(
+    deps: Deps,
+    swap: &SwapPacket,
+    token_in: &Coin,
+) -> Result<Uint128, ContractError> {
+    if swap.routes.is_empty() {
+        return Err(ContractError::MinOutputUnavailable {});
+    }
+
+    let mut amount = token_in.amount;
+    let mut denom = token_in.denom.as_str();
+    for route in &swap.routes {
+        let price = spot_price(deps, route.pool_id.u64(), denom, &route.token_out_denom)?;
+        amount = amount
+            .checked_multiply_ratio(price.atomics(), Decimal::one().atomics())
+            .map_err(|_| ContractError::AmountOverflow {})?;
+        denom = &route.token_out_denom;
+    }
+
+    Ok(amount)
+}
+
+/// Shares received for a single asset join at spot price, with no swap fee nor price impact.
+pub fn estimate_join_shares(
+    deps: Deps,
+    pool_id: u64,
+    token_in: &Coin,
+) -> Result<Uint128, ContractError> {
+    let total_shares = total_shares(deps, pool_id)?;
+    let pool_value = pool_value(deps, pool_id, &token_in.denom)?;
+    if pool_value.is_zero() {
+        return Err(ContractError::MinOutputUnavailable {});
+    }
+
+    Ok(total_shares.multiply_ratio(token_in.amount, pool_value))
+}
+
+/// Tokens received for a single asset exit at spot price, with no swap fee nor price impact.
+pub fn estimate_exit_amount(
+    deps: Deps,
+    pool_id: u64,
+    share_in: Uint128,
+    token_out_denom: &str,
+) -> Result<Uint128, ContractError> {
+    let total_shares = total_shares(deps, pool_id)?;
+    if total_shares.is_zero() {
+        return Err(ContractError::MinOutputUnavailable {});
+    }
+    let pool_value = pool_value(deps, pool_id, token_out_denom)?;
+
+    Ok(pool_value.multiply_ratio(share_in, total_shares))
+}
+
+fn total_shares(deps: Deps, pool_id: u64) -> Result<Uint128, ContractError> {
+    let res: QueryTotalSharesResponse = query_proto(deps, QueryTotalSharesRequest { pool_id })?;
+    let total_shares = res
+        .total_shares
+        .ok_or(ContractError::MinOutputUnavailable {})?;
+
+    parse_uint(&total_shares.amount)
+}
+
+// value of all the pool assets in `denom`
+fn pool_value(deps: Deps, pool_id: u64, denom: &str) -> Result<Uint128, ContractError> {
+    let res: QueryTotalPoolLiquidityResponse =
+        query_proto(deps, QueryTotalPoolLiquidityRequest { pool_id })?;
+
+    let mut value = Uint128::zero();
+    for asset in res.liquidity {
+        let amount = parse_uint(&asset.amount)?;
+        if asset.denom == denom {
+            value += amount;
+            continue;
+        }
+
+        value += amount * spot_price(deps, pool_id, &asset.denom, denom)?;
+    }
+
+    Ok(value)
+}
+
+// price of `base` in `quote`
+fn spot_price(deps: Deps, pool_id: u64, base: &str, quote: &str) -> Result<Decimal, ContractError> {
+    let request = QuerySpotPriceRequest {
+        pool_id,
+        base_asset_denom: base.to_string(),
+        quote_asset_denom: quote.to_string(),
+    };
+    let res: QuerySpotPriceResponse = query_proto(deps, request)?;
+
+    parse_price(&res.spot_price)
+}
+
+fn parse_uint(value: &str) -> Result<Uint128, ContractError> {
+    value
+        .parse::<u128>()
+        .map(Uint128::from)
+        .map_err(|_| ContractError::InvalidAmountValue {})
+}
+
+// price: 10.203 or 10.203uatom
+fn parse_price(value: &str) -> Result<Decimal, ContractError> {
+    let price: String = value
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+
+    Decimal::from_str(&price).map_err(|_| ContractError::InvalidAmountValue {})
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derive_min_amount() {
+        let amount_in = Uint128::new(500);
+        let price = Some(Decimal::percent(200));
+        let slippage = Some(Decimal::percent(5));
+        // 500 in at a spot price of 2 is 1000 expected
+        let spot = || Ok(Uint128::new(1000));
+        let no_query = || -> Result<Uint128, ContractError> { panic!("unexpected spot query") };
+
+        // absolute minimum only, the pools are not queried
+        let min = min_amount(Some(Uint128::new(10)), None, None, amount_in, no_query).unwrap();
+        assert_eq!(min, Uint128::new(10));
+
+        // the slippage applies to the spot output, without reference price
+        let min = min_amount(None, slippage, None, amount_in, spot).unwrap();
+        assert_eq!(min, Uint128::new(950));
+        let min = min_amount(Some(Uint128::new(10)), slippage, None, amount_in, spot).unwrap();
+        assert_eq!(min, Uint128::new(950));
+
+        // the highest minimum wins
+        let min = min_amount(Some(Uint128::new(990)), slippage, None, amount_in, spot).unwrap();
+        assert_eq!(min, Uint128::new(990));
+
+        // a reference price above the spot price raises the minimum, not below
+        let above = Some(Decimal::percent(300));
+        let min = min_amount(None, slippage, above, amount_in, spot).unwrap();
+        assert_eq!(min, Uint128::new(1425));
+        let below = Some(Decimal::percent(100));
+        let min = min_amount(None, slippage, below, amount_in, spot).unwrap();
+        assert_eq!(min, Uint128::new(950));
+
+        // the spot query errors are returned
+        let unavailable = || Err(ContractError::MinOutputUnavailable {});
+        let err = min_amount(None, slippage, price, amount_in, unavailable).unwrap_err();
+        assert_eq!(err, ContractError::MinOutputUnavailable {});
+
+        // a minimum is required
+        let err = min_amount(None, None, None, amount_in, no_query).unwrap_err();
+        assert_eq!(err, ContractError::MissingMinAmount {});
+
+        // the reference price needs a slippage, which cannot exceed 100%
+        let err = min_amount(Some(Uint128::new(10)), None, price, amount_in, no_query).unwrap_err();
+        assert_eq!(err, ContractError::InvalidSlippage {});
+        let max_slippage = Some(Decimal::percent(101));
+        let err = min_amount(None, max_slippage, None, amount_in, no_query).unwrap_err();
+        assert_eq!(err, ContractError::InvalidSlippage {});
+
+        let err = min_amount(None, slippage, Some(Decimal::MAX), Uint128::MAX, spot).unwrap_err();
+        assert_eq!(err, ContractError::AmountOverflow {});
+    }
+
+    #[test]
+    fn parse_spot_price() {
+        assert_eq!(
+            parse_price("10.203").unwrap(),
+            Decimal::from_str("10.203").unwrap()
+        );
+        assert_eq!(
+            parse_price("10.203uatom").unwrap(),
+            Decimal::from_str("10.203").unwrap()
+        );
+        assert!(parse_price("uatom").is_err());
+    }
+}
@@ -1,15 +1,40 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{IbcEndpoint, StdResult, Storage, Uint128};
+use cosmwasm_std::{Addr, Coin, Empty, IbcEndpoint, StdResult, Storage, Timestamp, Uint128};
+use cw_controllers::Admin;
 use cw_storage_plus::{Item, Map};
 
+use crate::ibc_msg::{OsmoPacket, ReturnTo, StepResultAck};
 use crate::ContractError;
 
+pub const ADMIN: Admin = Admin::new("admin");
+
 pub const CONFIG: Item<Config> = Item::new("ics20_config");
 
-// Used to pass info from the ibc_packet_receive to the reply handler
-pub const REPLY_ARGS: Item<ReplyArgs> = Item::new("reply_args");
+// Used to pass info from the ibc_packet_receive to the reply handler, indexed by reply id
+pub const REPLY_ARGS: Map<u64, ReplyArgs> = Map::new("reply_args");
+
+// Sequence giving every submessage its own reply id
+pub const REPLY_SEQ: Item<u64> = Item::new("reply_seq");
+
+// Used to pass a sent packet to the reply reading its sequence, indexed by reply id
+pub const PENDING_PACKETS: Map<u64, PendingPacket> = Map::new("pending_packets");
+
+/// indexed by (channel_id, packet sequence) saving the packets we sent and their status
+pub const TRANSFERS: Map<(&str, u64), TransferInfo> = Map::new("transfers");
+
+/// indexed by (sender, channel_id, packet sequence) listing the packets not acked or timed out yet
+pub const PENDING_TRANSFERS: Map<(&str, &str, u64), Empty> = Map::new("pending_transfers");
+
+/// indexed by (channel_id, packet sequence) saving the contract called with the packet ack
+pub const ACK_CALLBACKS: Map<(&str, u64), String> = Map::new("ack_callbacks");
+
+// Used to pass the pipeline progress between its step replies, indexed by the step reply id
+pub const PIPELINE: Map<u64, PipelineState> = Map::new("pipeline");
+
+// The funds a pool creation can spend, indexed by its reply id
+pub const CREATE_POOL_FUNDS: Map<u64, Vec<Coin>> = Map::new("create_pool_funds");
 
 /// static info on one channel that doesn't change
 pub const CHANNEL_INFO: Map<&str, ChannelInfo> = Map::new("channel_info");
@@ -17,19 +42,161 @@ pub const CHANNEL_INFO: Map<&str, ChannelInfo> = Map::new("channel_info");
 /// indexed by (channel_id, denom) maintaining the balance of the channel in that currency
 pub const CHANNEL_STATE: Map<(&str, &str), ChannelState> = Map::new("channel_state");
 
+/// indexed by (channel_id, denom) limiting the amounts sent and received over a period
+pub const RATE_LIMITS: Map<(&str, &str), RateLimit> = Map::new("rate_limits");
+
+/// GAMM pools the actions can use, when the config restricts them
+pub const ALLOWED_POOLS: Map<u64, Empty> = Map::new("allowed_pools");
+
+/// indexed by (channel_id, denom), the denoms the actions of the channel senders can output.
+/// Any denom can be output on the channels without any.
+pub const ALLOWED_DENOMS: Map<(&str, &str), Empty> = Map::new("allowed_denoms");
+
 /// indexed by (channel_id, owner) saving lockup account
 pub const LOCKUP: Map<(&str, &str), String> = Map::new("lockup");
 
+/// indexed by (channel_id, remote_sender, denom) maintaining the funds held by the contract for each remote sender
+pub const USER_BALANCES: Map<(&str, &str, &str), Uint128> = Map::new("user_balances");
+
+/// indexed by (channel_id, remote_denom), the tokens native to the remote chain we accept
+pub const EXTERNAL_TOKENS: Map<(&str, &str), ExternalTokenInfo> = Map::new("external_tokens");
+
+/// indexed by (channel_id, local_denom), the remote denom of the external tokens
+pub const EXTERNAL_DENOMS: Map<(&str, &str), String> = Map::new("external_denoms");
+
+/// indexed by (channel_id, local_denom, provider), the shares of the provider in the channel
+/// reserve of the external token
+pub const RESERVES: Map<(&str, &str, &Addr), Uint128> = Map::new("reserves");
+
+/// indexed by (channel_id, local_denom), the shares of all the providers in the channel reserve
+pub const RESERVE_SHARES: Map<(&str, &str), Uint128> = Map::new("reserve_shares");
+
+/// indexed by (channel_id, denom), the sum of the balances held for the remote senders
+pub const HELD_TOTALS: Map<(&str, &str), Uint128> = Map::new("held_totals");
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default)]
 pub struct ChannelState {
     pub outstanding: Uint128,
     pub total_sent: Uint128,
 }
 
+/// The rate limit periods are split in buckets, the flows leave the rolling period a bucket at
+/// a time
+pub const RATE_LIMIT_BUCKETS: u64 = 10;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RateLimit {
+    /// max amount received over a period
+    pub max_inflow: Uint128,
+    /// max amount sent over a period
+    pub max_outflow: Uint128,
+    /// length of the period in seconds
+    pub period: u64,
+    /// amounts received and sent over the last period, oldest first
+    pub buckets: Vec<FlowBucket>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct FlowBucket {
+    /// start of the bucket, a multiple of the bucket length
+    pub start: Timestamp,
+    pub inflow: Uint128,
+    pub outflow: Uint128,
+}
+
+impl RateLimit {
+    pub fn new(max_inflow: Uint128, max_outflow: Uint128, period: u64) -> Self {
+        RateLimit {
+            max_inflow,
+            max_outflow,
+            period,
+            buckets: vec![],
+        }
+    }
+
+    fn bucket_length(&self) -> u64 {
+        self.period.div_ceil(RATE_LIMIT_BUCKETS).max(1)
+    }
+
+    /// Drops the buckets that ended before the rolling period. The flows are counted until their
+    /// whole bucket leaves it, so no more than the max amounts go through over any period.
+    pub fn update_period(&mut self, now: Timestamp) {
+        let length = self.bucket_length();
+        let period = self.period;
+        self.buckets
+            .retain(|bucket| bucket.start.plus_seconds(length + period) > now);
+    }
+
+    pub fn inflow(&self) -> Uint128 {
+        self.buckets.iter().map(|bucket| bucket.inflow).sum()
+    }
+
+    pub fn outflow(&self) -> Uint128 {
+        self.buckets.iter().map(|bucket| bucket.outflow).sum()
+    }
+
+    pub fn remaining_inflow(&self) -> Uint128 {
+        self.max_inflow.saturating_sub(self.inflow())
+    }
+
+    pub fn remaining_outflow(&self) -> Uint128 {
+        self.max_outflow.saturating_sub(self.outflow())
+    }
+
+    // adds the amount to the bucket of the current time
+    fn record(&mut self, now: Timestamp, amount: Uint128, flow: Flow) {
+        let length = self.bucket_length();
+        let start = Timestamp::from_seconds(now.seconds() - now.seconds() % length);
+        let bucket = match self.buckets.last_mut() {
+            Some(bucket) if bucket.start == start => bucket,
+            _ => {
+                self.buckets.push(FlowBucket {
+                    start,
+                    inflow: Uint128::zero(),
+                    outflow: Uint128::zero(),
+                });
+                self.buckets.last_mut().unwrap()
+            }
+        };
+        match flow {
+            Flow::In => bucket.inflow += amount,
+            Flow::Out => bucket.outflow += amount,
+        }
+    }
+
+    // subtracts the amount from the bucket it was recorded in, the last one if not set. Nothing
+    // is given back once the bucket left the period.
+    fn undo(&mut self, at: Option<Timestamp>, amount: Uint128, flow: Flow) {
+        let length = self.bucket_length();
+        let bucket = match at {
+            Some(at) => self
+                .buckets
+                .iter_mut()
+                .find(|bucket| bucket.start <= at && at < bucket.start.plus_seconds(length)),
+            None => self.buckets.last_mut(),
+        };
+        if let Some(bucket) = bucket {
+            match flow {
+                Flow::In => bucket.inflow = bucket.inflow.saturating_sub(amount),
+                Flow::Out => bucket.outflow = bucket.outflow.saturating_sub(amount),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Flow {
+    In,
+    Out,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 pub struct Config {
     pub default_timeout: u64,
     pub lockup_id: u64,
+    /// only the allowed pools can be used by the actions
+    #[serde(default)]
+    pub restrict_pools: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -40,6 +207,27 @@ pub struct ChannelInfo {
     pub counterparty_endpoint: IbcEndpoint,
     /// the connection this exists on (you can use to query client/consensus info)
     pub connection_id: String,
+    /// whether packets can be sent and received on the channel
+    #[serde(default)]
+    pub status: ChannelStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelStatus {
+    #[default]
+    Open,
+    /// Halted by the admin, no packets are sent or received until it is unfrozen. Acks and
+    /// timeouts of the packets already sent are still handled.
+    Frozen,
+    /// Closed by the counterparty, the packets already sent time out
+    Closed,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ExternalTokenInfo {
+    /// local denom paid out on receive, the `ibc/<hash>` of the transfer module
+    pub denom: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -48,17 +236,165 @@ pub struct ReplyArgs {
     pub denom: String,
     pub amount: Uint128,
     pub sender: String,
+    pub return_to: Option<ReturnTo>,
+    /// the requested action, the reply reads the expected output from it
+    pub action: Option<OsmoPacket>,
+    /// funds taken from the sender held balance for this action
+    pub held: Vec<Coin>,
+    /// funds fully spent by the action, the unused received and held funds are kept for the sender
+    pub spent: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct PendingPacket {
+    pub channel: String,
+    pub transfer: TransferInfo,
+    /// contract called with the packet ack or timeout
+    pub callback: Option<String>,
 }
 
-pub fn restore_balance_reply(storage: &mut dyn Storage) -> Result<(), ContractError> {
-    let reply_args = REPLY_ARGS.load(storage)?;
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TransferInfo {
+    /// local sender, the contract itself for action outputs sent back
+    pub sender: String,
+    pub receiver: String,
+    /// local denom of the tokens sent
+    pub denom: String,
+    pub amount: Uint128,
+    pub timeout: Timestamp,
+    pub status: TransferStatus,
+    /// remote sender credited with the tokens if the action output sent back fails
+    #[serde(default)]
+    pub refund_to: Option<HeldBy>,
+    /// block time the packet was sent, to give back its rate limit quota on refund
+    #[serde(default)]
+    pub sent_at: Option<Timestamp>,
+}
 
+/// Remote sender holding funds on a channel
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct HeldBy {
+    pub channel: String,
+    pub sender: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    /// Sent, not acked or timed out yet
+    Pending,
+    /// Acked by the remote chain
+    Delivered,
+    /// Failed or timed out, the tokens were returned to the sender, or credited to the remote
+    /// sender held funds for action outputs sent back
+    Refunded,
+    /// Sent by the contract without a remote sender to credit, failed or timed out.
+    /// The tokens stay in the channel balance.
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct PipelineState {
+    pub channel: String,
+    pub sender: String,
+    pub steps: Vec<OsmoPacket>,
+    /// results of the steps already run
+    pub results: Vec<StepResultAck>,
+}
+
+// Loads the context of the reply and cleans it up
+pub fn take_reply_args(storage: &mut dyn Storage, id: u64) -> StdResult<ReplyArgs> {
+    let reply_args = REPLY_ARGS.load(storage, id)?;
+    REPLY_ARGS.remove(storage, id);
+    Ok(reply_args)
+}
+
+/// Loads the channel, it must be open to send or receive packets
+pub fn load_open_channel(storage: &dyn Storage, id: &str) -> Result<ChannelInfo, ContractError> {
+    let info = CHANNEL_INFO
+        .may_load(storage, id)?
+        .ok_or_else(|| ContractError::NoSuchChannel { id: id.to_string() })?;
+    match info.status {
+        ChannelStatus::Open => Ok(info),
+        ChannelStatus::Frozen => Err(ContractError::ChannelFrozen { id: id.to_string() }),
+        ChannelStatus::Closed => Err(ContractError::ChannelClosed { id: id.to_string() }),
+    }
+}
+
+pub fn save_transfer(
+    storage: &mut dyn Storage,
+    channel: &str,
+    sequence: u64,
+    transfer: &TransferInfo,
+) -> StdResult<()> {
+    TRANSFERS.save(storage, (channel, sequence), transfer)?;
+    if transfer.status == TransferStatus::Pending {
+        PENDING_TRANSFERS.save(storage, (&transfer.sender, channel, sequence), &Empty {})?;
+    }
+    Ok(())
+}
+
+// Loads the transfer of the packet, the packets sent before they were tracked are built from the
+// packet instead
+pub fn load_transfer(
+    storage: &dyn Storage,
+    channel: &str,
+    sequence: u64,
+    sent: TransferInfo,
+) -> StdResult<TransferInfo> {
+    Ok(TRANSFERS
+        .may_load(storage, (channel, sequence))?
+        .unwrap_or(sent))
+}
+
+// Sets the final status of the transfer and saves it
+pub fn finish_transfer(
+    storage: &mut dyn Storage,
+    channel: &str,
+    sequence: u64,
+    mut transfer: TransferInfo,
+    status: TransferStatus,
+) -> StdResult<()> {
+    PENDING_TRANSFERS.remove(storage, (&transfer.sender, channel, sequence));
+    transfer.status = status;
+    save_transfer(storage, channel, sequence, &transfer)
+}
+
+pub fn restore_balance_reply(
+    storage: &mut dyn Storage,
+    reply_args: &ReplyArgs,
+) -> Result<(), ContractError> {
     undo_reduce_channel_balance(
         storage,
         &reply_args.channel,
         &reply_args.denom,
         reply_args.amount,
     )?;
+    undo_flow(
+        storage,
+        &reply_args.channel,
+        &reply_args.denom,
+        reply_args.amount,
+        Flow::In,
+    )?;
+    restore_held_funds(storage, reply_args)
+}
+
+// Gives the held funds taken by the action back to the sender
+pub fn restore_held_funds(
+    storage: &mut dyn Storage,
+    reply_args: &ReplyArgs,
+) -> Result<(), ContractError> {
+    for coin in &reply_args.held {
+        increase_user_balance(
+            storage,
+            &reply_args.channel,
+            &reply_args.sender,
+            &coin.denom,
+            coin.amount,
+        )?;
+        undo_reduce_channel_balance(storage, &reply_args.channel, &coin.denom, coin.amount)?;
+    }
 
     Ok(())
 }
@@ -115,3 +451,154 @@ pub fn undo_reduce_channel_balance(
     })?;
     Ok(())
 }
+
+/// Adds the amount to the flows of the rolling period, if the channel and denom are rate limited
+pub fn record_flow(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+    flow: Flow,
+) -> Result<(), ContractError> {
+    let mut limit = match RATE_LIMITS.may_load(storage, (channel, denom))? {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    limit.update_period(now);
+
+    let remaining = match flow {
+        Flow::In => limit.remaining_inflow(),
+        Flow::Out => limit.remaining_outflow(),
+    };
+    if amount > remaining {
+        return Err(ContractError::RateLimitExceeded {
+            channel: channel.to_string(),
+            denom: denom.to_string(),
+            remaining,
+        });
+    }
+    limit.record(now, amount, flow);
+
+    RATE_LIMITS.save(storage, (channel, denom), &limit)?;
+    Ok(())
+}
+
+// this gives back the quota of a flow recorded in this transaction that didn't happen, e.g.
+// failed receives
+pub fn undo_flow(
+    storage: &mut dyn Storage,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+    flow: Flow,
+) -> StdResult<()> {
+    undo_flow_at(storage, None, channel, denom, amount, flow)
+}
+
+// this gives back the quota of a flow recorded at the time that didn't happen, e.g. refunds of
+// the packets sent
+pub fn undo_flow_at(
+    storage: &mut dyn Storage,
+    at: Option<Timestamp>,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+    flow: Flow,
+) -> StdResult<()> {
+    let mut limit = match RATE_LIMITS.may_load(storage, (channel, denom))? {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    limit.undo(at, amount, flow);
+    RATE_LIMITS.save(storage, (channel, denom), &limit)
+}
+
+pub fn increase_user_balance(
+    storage: &mut dyn Storage,
+    channel: &str,
+    sender: &str,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    USER_BALANCES.update(storage, (channel, sender, denom), |orig| -> StdResult<_> {
+        Ok(orig.unwrap_or_default() + amount)
+    })?;
+    HELD_TOTALS.update(storage, (channel, denom), |orig| -> StdResult<_> {
+        Ok(orig.unwrap_or_default() + amount)
+    })?;
+    Ok(())
+}
+
+pub fn reduce_user_balance(
+    storage: &mut dyn Storage,
+    channel: &str,
+    sender: &str,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let key = (channel, sender, denom);
+    let balance = USER_BALANCES
+        .may_load(storage, key)?
+        .unwrap_or_default()
+        .checked_sub(amount)
+        .or(Err(ContractError::InsufficientHeldFunds {}))?;
+
+    if balance.is_zero() {
+        USER_BALANCES.remove(storage, key);
+    } else {
+        USER_BALANCES.save(storage, key, &balance)?;
+    }
+    let total = HELD_TOTALS
+        .may_load(storage, (channel, denom))?
+        .unwrap_or_default()
+        .saturating_sub(amount);
+    if total.is_zero() {
+        HELD_TOTALS.remove(storage, (channel, denom));
+    } else {
+        HELD_TOTALS.save(storage, (channel, denom), &total)?;
+    }
+    Ok(())
+}
+
+/// The channel reserve of an external token: the channel balance of its local denom, less what is
+/// held for the remote senders. The payouts reduce it and the tokens sent back restore it, for
+/// all the providers in proportion of their shares.
+pub fn reserve_amount(storage: &dyn Storage, channel: &str, denom: &str) -> StdResult<Uint128> {
+    let balance = CHANNEL_STATE
+        .may_load(storage, (channel, denom))?
+        .unwrap_or_default()
+        .outstanding;
+    let held = HELD_TOTALS
+        .may_load(storage, (channel, denom))?
+        .unwrap_or_default();
+    Ok(balance.saturating_sub(held))
+}
+
+/// Registers the token native to the remote chain of the channel
+pub fn save_external_token(
+    storage: &mut dyn Storage,
+    channel: &str,
+    remote_denom: &str,
+    local_denom: &str,
+) -> StdResult<()> {
+    let info = ExternalTokenInfo {
+        denom: local_denom.to_string(),
+    };
+    EXTERNAL_TOKENS.save(storage, (channel, remote_denom), &info)?;
+    EXTERNAL_DENOMS.save(storage, (channel, local_denom), &remote_denom.to_string())
+}
+
+/// Stops accepting the token native to the remote chain of the channel, returns its local denom
+pub fn remove_external_token(
+    storage: &mut dyn Storage,
+    channel: &str,
+    remote_denom: &str,
+) -> Result<String, ContractError> {
+    let info = EXTERNAL_TOKENS
+        .may_load(storage, (channel, remote_denom))?
+        .ok_or(ContractError::NoAllowedToken {})?;
+    EXTERNAL_TOKENS.remove(storage, (channel, remote_denom));
+    EXTERNAL_DENOMS.remove(storage, (channel, &info.denom));
+    Ok(info.denom)
+}
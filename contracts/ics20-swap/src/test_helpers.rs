@@ -2,17 +2,30 @@
 
 use crate::contract::instantiate;
 use crate::ibc::{ibc_channel_connect, ibc_channel_open, ICS20_ORDERING, ICS20_VERSION};
-use crate::state::ChannelInfo;
+use crate::state::{ChannelInfo, ChannelStatus};
 
 use cosmwasm_std::testing::{
-    mock_dependencies, mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init,
-    mock_info, MockApi, MockQuerier, MockStorage,
+    mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init, mock_info, MockApi,
+    MockQuerier, MockStorage,
 };
-use cosmwasm_std::{attr, Binary, DepsMut, Event, IbcEndpoint, OwnedDeps, SubMsgResponse};
+use cosmwasm_std::{
+    attr, from_slice, to_binary, Binary, Coin, ContractResult, Decimal, DepsMut, Empty, Event,
+    IbcEndpoint, OwnedDeps, Querier, QuerierResult, QueryRequest, SubMsgResponse, SystemError,
+    SystemResult, WasmQuery,
+};
+use cw2::ContractVersion;
+use cw_osmo_proto::osmosis::gamm::v1beta1::{
+    QuerySpotPriceRequest, QuerySpotPriceResponse, QuerySwapExactAmountInResponse,
+    QueryTotalPoolLiquidityResponse, QueryTotalSharesResponse,
+};
+use cw_osmo_proto::proto_ext::proto_decode;
+use cw_osmo_proto::Message;
+use std::marker::PhantomData;
 
 use crate::msg::InitMsg;
 
 pub const DEFAULT_TIMEOUT: u64 = 3600; // 1 hour,
+pub const GOV_CONTRACT: &str = "gov";
 pub const CONTRACT_PORT: &str = "ibc:wasm1234567890abcdef";
 pub const REMOTE_PORT: &str = "transfer";
 pub const CONNECTION_ID: &str = "connection-2";
@@ -25,6 +38,7 @@ pub fn mock_channel_info(channel_id: &str) -> ChannelInfo {
             channel_id: "channel-7".to_string(),
         },
         connection_id: CONNECTION_ID.into(),
+        status: ChannelStatus::Open,
     }
 }
 
@@ -37,12 +51,26 @@ pub fn add_channel(mut deps: DepsMut, channel_id: &str) {
 }
 
 pub fn setup(channels: &[&str]) -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
-    let mut deps = mock_dependencies();
+    setup_with_querier(channels, MockQuerier::new(&[]))
+}
+
+pub fn setup_with_querier<Q: Querier>(
+    channels: &[&str],
+    querier: Q,
+) -> OwnedDeps<MockStorage, MockApi, Q> {
+    let mut deps = OwnedDeps {
+        storage: MockStorage::default(),
+        api: MockApi::default(),
+        querier,
+        custom_query_type: PhantomData,
+    };
 
     // instantiate an empty contract
     let instantiate_msg = InitMsg {
         default_timeout: DEFAULT_TIMEOUT,
         lockup_id: 1,
+        gov_contract: Some(GOV_CONTRACT.to_string()),
+        external_tokens: vec![],
     };
     let info = mock_info(&String::from("anyone"), &[]);
     let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
@@ -54,6 +82,82 @@ pub fn setup(channels: &[&str]) -> OwnedDeps<MockStorage, MockApi, MockQuerier>
     deps
 }
 
+/// Answers the cw2 version of the lockup accounts
+pub fn mock_lockup_version(querier: &mut MockQuerier, version: &str) {
+    let info = to_binary(&ContractVersion {
+        contract: "crates.io:cw-osmo-lockup".to_string(),
+        version: version.to_string(),
+    })
+    .unwrap();
+    querier.update_wasm(move |query| match query {
+        WasmQuery::Raw { key, .. } if key.as_slice() == b"contract_info" => {
+            SystemResult::Ok(ContractResult::Ok(info.clone()))
+        }
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "wasm".to_string(),
+        }),
+    });
+}
+
+/// Answers the osmosis gamm queries used to derive the slippage minimums
+pub struct GammQuerier {
+    pub base: MockQuerier,
+    pub swap_estimate: u128,
+    pub total_shares: Coin,
+    pub liquidity: Vec<Coin>,
+    /// (base denom, quote denom, price)
+    pub spot_prices: Vec<(&'static str, &'static str, Decimal)>,
+}
+
+impl Querier for GammQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+        let (path, data) = match request {
+            QueryRequest::Stargate { path, data } => (path, data),
+            _ => return self.base.raw_query(bin_request),
+        };
+
+        let res = match path.as_str() {
+            "/osmosis.gamm.v1beta1.Query/EstimateSwapExactAmountIn" => {
+                QuerySwapExactAmountInResponse {
+                    token_out_amount: self.swap_estimate.to_string(),
+                }
+                .encode_to_vec()
+            }
+            "/osmosis.gamm.v1beta1.Query/TotalShares" => QueryTotalSharesResponse {
+                total_shares: Some(proto_coin(&self.total_shares)),
+            }
+            .encode_to_vec(),
+            "/osmosis.gamm.v1beta1.Query/TotalPoolLiquidity" => QueryTotalPoolLiquidityResponse {
+                liquidity: self.liquidity.iter().map(proto_coin).collect(),
+            }
+            .encode_to_vec(),
+            "/osmosis.gamm.v1beta1.Query/SpotPrice" => {
+                let req: QuerySpotPriceRequest = proto_decode(data.as_slice()).unwrap();
+                let price = self
+                    .spot_prices
+                    .iter()
+                    .find(|p| p.0 == req.base_asset_denom && p.1 == req.quote_asset_denom)
+                    .unwrap();
+                QuerySpotPriceResponse {
+                    spot_price: price.2.to_string(),
+                }
+                .encode_to_vec()
+            }
+            _ => return SystemResult::Err(SystemError::UnsupportedRequest { kind: path }),
+        };
+
+        SystemResult::Ok(ContractResult::Ok(res.into()))
+    }
+}
+
+fn proto_coin(coin: &Coin) -> cw_osmo_proto::cosmos::base::v1beta1::Coin {
+    cw_osmo_proto::cosmos::base::v1beta1::Coin {
+        denom: coin.denom.clone(),
+        amount: coin.amount.to_string(),
+    }
+}
+
 pub fn json_to_reply_proto(json: &str) -> Vec<u8> {
     let mut proto_data = vec![10u8, json.len() as u8];
     proto_data.extend_from_slice(json.as_bytes());
@@ -69,7 +173,7 @@ pub fn mock_swap_response() -> SubMsgResponse {
 }
 
 pub fn mock_swap_events() -> Vec<Event> {
-    return vec![
+    vec![
         Event::new("token_swapped").add_attributes(vec![
             attr("module", "gamm"),
             attr("sender", "osmo1q4aw0vtcyyredprm4ncmr4jdj70kpgyr3"),
@@ -108,7 +212,14 @@ pub fn mock_swap_events() -> Vec<Event> {
                 "338527564ibc/BE1BB42D4BE3C30D50B68D7C41DB4DFCE9678E8EF8C539F6E6A9345048894FCC",
             ),
         ]),
-    ];
+    ]
+}
+
+pub fn mock_swap_exact_out_response() -> SubMsgResponse {
+    SubMsgResponse {
+        events: mock_swap_events(),
+        data: Some(Binary::from_base64("Cgc5ODUwMDAw").unwrap()),
+    }
 }
 
 pub fn mock_join_pool_response() -> SubMsgResponse {
@@ -119,7 +230,7 @@ pub fn mock_join_pool_response() -> SubMsgResponse {
 }
 
 pub fn mock_join_pool_events() -> Vec<Event> {
-    return vec![
+    vec![
         Event::new("pool_joined").add_attributes(vec![
             attr("module", "gamm"),
             attr("sender", "osmo1q4aw0vtcyyredprm4ncmr4jdj70kpgyr3"),
@@ -130,7 +241,7 @@ pub fn mock_join_pool_events() -> Vec<Event> {
             attr("minter", "osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj"),
             attr("amount", "74196993097318119147gamm/pool/1"),
         ]),
-    ];
+    ]
 }
 
 pub fn mock_exit_pool_response() -> SubMsgResponse {
@@ -141,7 +252,7 @@ pub fn mock_exit_pool_response() -> SubMsgResponse {
 }
 
 pub fn mock_exit_pool_events() -> Vec<Event> {
-    return vec![
+    vec![
         Event::new("pool_exited").add_attributes(vec![
             attr("module", "gamm"),
             attr("sender", "osmo1q4aw0vtcyyredprm4ncmr4jdj70kpgyr3"),
@@ -159,5 +270,39 @@ pub fn mock_exit_pool_events() -> Vec<Event> {
             attr("burner", "osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj"),
             attr("amount", "74196993097318119147gamm/pool/1"),
         ]),
-    ];
+    ]
+}
+
+pub fn mock_multi_join_pool_events() -> Vec<Event> {
+    vec![
+        Event::new("pool_joined").add_attributes(vec![
+            attr("module", "gamm"),
+            attr("sender", "osmo1q4aw0vtcyyredprm4ncmr4jdj70kpgyr3"),
+            attr("pool_id", "1"),
+            attr("tokens_in", "9000000uatom,4985011uosmo"),
+        ]),
+        Event::new("coinbase").add_attributes(vec![
+            attr("minter", "osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj"),
+            attr("amount", "74196993097318119147gamm/pool/1"),
+        ]),
+    ]
+}
+
+pub fn mock_create_pool_events(spender: &str) -> Vec<Event> {
+    vec![
+        Event::new("coin_spent").add_attributes(vec![
+            attr("spender", spender),
+            attr("amount", "1000000uosmo"),
+        ]),
+        Event::new("coin_spent").add_attributes(vec![
+            attr("spender", spender),
+            attr("amount", "9000000uatom,5000000uosmo"),
+        ]),
+        Event::new("coinbase").add_attributes(vec![
+            attr("minter", "osmo1c9y7crgg6y9pfkq0y8mqzknqz84c3etr0kpcvj"),
+            attr("amount", "100000000000000000000gamm/pool/2"),
+        ]),
+        Event::new("pool_created")
+            .add_attributes(vec![attr("module", "gamm"), attr("pool_id", "2")]),
+    ]
 }
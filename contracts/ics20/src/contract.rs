@@ -7,6 +7,7 @@ use cosmwasm_std::{
 
 use cw2::set_contract_version;
 use cw20::{Cw20Coin, Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_migrate::check_migrate_version;
 use cw_storage_plus::Bound;
 
 use crate::amount::Amount;
@@ -15,11 +16,13 @@ use crate::ibc::Ics20Packet;
 use crate::msg::{
     AllowMsg, AllowedInfo, AllowedResponse, AllowedTokenInfo, AllowedTokenResponse,
     ChannelResponse, ConfigResponse, ExecuteMsg, ExternalTokenMsg, InitMsg, ListAllowedResponse,
-    ListChannelsResponse, ListExternalTokensResponse, PortResponse, QueryMsg, TransferMsg,
+    ListChannelsResponse, ListExternalTokensResponse, MigrateMsg, PortResponse, QueryMsg,
+    TransferMsg,
 };
 use crate::state::{
-    find_external_token, increase_channel_balance, join_ibc_paths, AllowInfo, Config,
-    ExternalTokenInfo, ADMIN, ALLOW_LIST, CHANNEL_INFO, CHANNEL_STATE, CONFIG, EXTERNAL_TOKENS,
+    find_external_token, increase_channel_balance, join_ibc_paths, load_open_channel, AllowInfo,
+    ChannelStatus, Config, ExternalTokenInfo, ADMIN, ALLOW_LIST, CHANNEL_INFO, CHANNEL_STATE,
+    CONFIG, EXTERNAL_TOKENS,
 };
 use cw_utils::{maybe_addr, nonpayable, one_coin};
 
@@ -75,6 +78,10 @@ pub fn execute(
             let admin = deps.api.addr_validate(&admin)?;
             Ok(ADMIN.execute_update_admin(deps, info, Some(admin))?)
         }
+        ExecuteMsg::FreezeChannel { channel } => execute_freeze_channel(deps, info, channel, true),
+        ExecuteMsg::UnfreezeChannel { channel } => {
+            execute_freeze_channel(deps, info, channel, false)
+        }
     }
 }
 
@@ -105,10 +112,8 @@ pub fn execute_transfer(
     if amount.is_empty() {
         return Err(ContractError::NoFunds {});
     }
-    // ensure the requested channel is registered
-    if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
-        return Err(ContractError::NoSuchChannel { id: msg.channel });
-    }
+    // ensure the requested channel is registered and open
+    load_open_channel(deps.storage, &msg.channel)?;
 
     // if cw20 token, ensure it is whitelisted
     let mut denom = amount.denom();
@@ -274,6 +279,52 @@ pub fn allow_external_token(
     Ok(res)
 }
 
+pub fn execute_freeze_channel(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    freeze: bool,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let mut channel_info = CHANNEL_INFO
+        .may_load(deps.storage, &channel)?
+        .ok_or_else(|| ContractError::NoSuchChannel {
+            id: channel.clone(),
+        })?;
+    if channel_info.status == ChannelStatus::Closed {
+        return Err(ContractError::ChannelClosed { id: channel });
+    }
+    channel_info.status = if freeze {
+        ChannelStatus::Frozen
+    } else {
+        ChannelStatus::Open
+    };
+    CHANNEL_INFO.save(deps.storage, &channel, &channel_info)?;
+
+    let action = if freeze {
+        "freeze_channel"
+    } else {
+        "unfreeze_channel"
+    };
+    let res = Response::new()
+        .add_attribute("action", action)
+        .add_attribute("channel", channel);
+    Ok(res)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let previous = check_migrate_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let res = Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", previous.to_string())
+        .add_attribute("to_version", CONTRACT_VERSION);
+    Ok(res)
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
@@ -424,6 +475,7 @@ fn list_external_tokens(
 mod test {
     use super::*;
     use crate::test_helpers::*;
+    use cw2::get_contract_version;
 
     use cosmwasm_std::testing::{mock_env, mock_info};
     use cosmwasm_std::{coin, coins, CosmosMsg, IbcMsg, StdError, Uint128};
@@ -592,4 +644,33 @@ mod test {
         let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
         assert_eq!(err, ContractError::NotOnAllowList);
     }
+
+    #[test]
+    fn migrate_contract() {
+        let mut deps = setup(&["channel-3"], &[]);
+
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.0.1").unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.version, CONTRACT_VERSION);
+
+        // cannot downgrade
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrateVersion {
+                previous_version: "99.0.0".to_string()
+            }
+        );
+
+        set_contract_version(deps.as_mut().storage, "crates.io:other", "0.0.1").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate {
+                previous_contract: "crates.io:other".to_string()
+            }
+        );
+    }
 }
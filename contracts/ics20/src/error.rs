@@ -4,6 +4,7 @@ use thiserror::Error;
 
 use cosmwasm_std::StdError;
 use cw_controllers::AdminError;
+use cw_migrate::MigrateError;
 use cw_utils::PaymentError;
 
 /// Never is a placeholder to ensure we don't return any errors
@@ -24,6 +25,12 @@ pub enum ContractError {
     #[error("Channel doesn't exist: {id}")]
     NoSuchChannel { id: String },
 
+    #[error("Channel is frozen: {id}")]
+    ChannelFrozen { id: String },
+
+    #[error("Channel is closed: {id}")]
+    ChannelClosed { id: String },
+
     #[error("Didn't send any funds")]
     NoFunds {},
 
@@ -90,3 +97,17 @@ impl From<TryFromIntError> for ContractError {
         ContractError::AmountOverflow {}
     }
 }
+
+impl From<MigrateError> for ContractError {
+    fn from(err: MigrateError) -> Self {
+        match err {
+            MigrateError::Std(err) => ContractError::Std(err),
+            MigrateError::CannotMigrate { previous_contract } => {
+                ContractError::CannotMigrate { previous_contract }
+            }
+            MigrateError::CannotMigrateVersion { previous_version } => {
+                ContractError::CannotMigrateVersion { previous_version }
+            }
+        }
+    }
+}
@@ -11,8 +11,9 @@ use cosmwasm_std::{
 use crate::amount::{get_cw20_denom, Amount};
 use crate::error::{ContractError, Never};
 use crate::state::{
-    join_ibc_paths, reduce_channel_balance, undo_reduce_channel_balance, ChannelInfo, ReplyArgs,
-    ALLOW_LIST, CHANNEL_INFO, CONFIG, EXTERNAL_TOKENS, REPLY_ARGS,
+    join_ibc_paths, load_open_channel, reduce_channel_balance, undo_reduce_channel_balance,
+    ChannelInfo, ChannelStatus, ReplyArgs, ALLOW_LIST, CHANNEL_INFO, CONFIG, EXTERNAL_TOKENS,
+    REPLY_ARGS,
 };
 use cw20::Cw20ExecuteMsg;
 
@@ -144,6 +145,7 @@ pub fn ibc_channel_connect(
         id: channel.endpoint.channel_id,
         counterparty_endpoint: channel.counterparty_endpoint,
         connection_id: channel.connection_id,
+        status: ChannelStatus::Open,
     };
     CHANNEL_INFO.save(deps.storage, &info.id, &info)?;
     CONFIG.update(deps.storage, |mut cfg| -> Result<_, ContractError> {
@@ -178,14 +180,26 @@ fn enforce_order_and_version(
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_channel_close(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     channel: IbcChannelCloseMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     match channel {
-        IbcChannelCloseMsg::CloseConfirm { .. } => Ok(IbcBasicResponse::new()),
-        IbcChannelCloseMsg::CloseInit { .. } => Err(ContractError::CannotClose {}),
-        _ => panic!(),
+        IbcChannelCloseMsg::CloseConfirm { channel } => {
+            // the balances are kept, the packets already sent time out and are refunded
+            let id = channel.endpoint.channel_id;
+            let mut info = CHANNEL_INFO
+                .may_load(deps.storage, &id)?
+                .ok_or_else(|| ContractError::NoSuchChannel { id: id.clone() })?;
+            info.status = ChannelStatus::Closed;
+            CHANNEL_INFO.save(deps.storage, &id, &info)?;
+
+            Ok(IbcBasicResponse::new()
+                .add_attribute("action", "close_channel")
+                .add_attribute("channel", id))
+        }
+        // the contract never closes its channels
+        _ => Err(ContractError::CannotClose {}),
     }
 }
 
@@ -287,6 +301,7 @@ fn do_ibc_packet_receive(
 ) -> Result<IbcReceiveResponse, ContractError> {
     let msg: Ics20Packet = from_binary(&packet.data)?;
     let channel = packet.dest.channel_id.clone();
+    load_open_channel(deps.storage, &channel)?;
 
     // If the token originated on the remote chain, it looks like "ucosm".
     // If it originated on our chain, it looks like "port/channel/ucosm".
@@ -458,7 +473,7 @@ mod test {
 
     use crate::contract::{execute, query_channel};
     use crate::msg::{ExecuteMsg, TransferMsg};
-    use cosmwasm_std::testing::{mock_env, mock_info};
+    use cosmwasm_std::testing::{mock_env, mock_ibc_channel, mock_info};
     use cosmwasm_std::{coins, to_vec, IbcEndpoint, IbcMsg, IbcTimeout, Timestamp};
     use cw20::Cw20ReceiveMsg;
 
@@ -692,4 +707,70 @@ mod test {
         assert_eq!(state.balances, vec![Amount::native(111111111, denom)]);
         assert_eq!(state.total_sent, vec![Amount::native(987654321, denom)]);
     }
+
+    #[test]
+    fn channel_status() {
+        let send_channel = "channel-9";
+        let mut deps = setup(&[send_channel], &[]);
+        let denom = "uatom";
+
+        let transfer = |deps: DepsMut| {
+            let msg = ExecuteMsg::Transfer(TransferMsg {
+                channel: send_channel.to_string(),
+                remote_address: "my-remote-address".to_string(),
+                timeout: None,
+            });
+            let info = mock_info("local-sender", &coins(1000, denom));
+            execute(deps, mock_env(), info, msg)
+        };
+        let admin = |deps: DepsMut, sender: &str, msg: ExecuteMsg| {
+            execute(deps, mock_env(), mock_info(sender, &[]), msg)
+        };
+        transfer(deps.as_mut()).unwrap();
+
+        // only the admin freezes a channel
+        let msg = ExecuteMsg::FreezeChannel {
+            channel: send_channel.to_string(),
+        };
+        admin(deps.as_mut(), "anyone", msg.clone()).unwrap_err();
+        admin(deps.as_mut(), "gov", msg).unwrap();
+
+        // no packets are sent or received on a frozen channel
+        let frozen = ContractError::ChannelFrozen {
+            id: send_channel.to_string(),
+        };
+        assert_eq!(transfer(deps.as_mut()).unwrap_err(), frozen);
+        let recv_packet = mock_receive_packet(send_channel, 500, denom, "local-rcpt");
+        let msg = IbcPacketReceiveMsg::new(recv_packet);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res.messages.is_empty());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(ack, Ics20Ack::Error(frozen.to_string()));
+
+        let msg = ExecuteMsg::UnfreezeChannel {
+            channel: send_channel.to_string(),
+        };
+        admin(deps.as_mut(), "gov", msg).unwrap();
+        transfer(deps.as_mut()).unwrap();
+
+        // once closed by the counterparty, the channel can't be used
+        let channel = mock_ibc_channel(send_channel, ICS20_ORDERING, ICS20_VERSION);
+        let close_msg = IbcChannelCloseMsg::new_init(channel.clone());
+        let err = ibc_channel_close(deps.as_mut(), mock_env(), close_msg).unwrap_err();
+        assert_eq!(err, ContractError::CannotClose {});
+        let close_msg = IbcChannelCloseMsg::new_confirm(channel);
+        ibc_channel_close(deps.as_mut(), mock_env(), close_msg).unwrap();
+
+        let state = query_channel(deps.as_ref(), send_channel.to_string()).unwrap();
+        assert_eq!(state.info.status, ChannelStatus::Closed);
+        assert_eq!(state.balances, vec![Amount::native(2000, denom)]);
+        let closed = ContractError::ChannelClosed {
+            id: send_channel.to_string(),
+        };
+        assert_eq!(transfer(deps.as_mut()).unwrap_err(), closed);
+        let msg = ExecuteMsg::UnfreezeChannel {
+            channel: send_channel.to_string(),
+        };
+        assert_eq!(admin(deps.as_mut(), "gov", msg).unwrap_err(), closed);
+    }
 }
@@ -16,6 +16,9 @@ pub struct InitMsg {
     pub allowlist: Vec<AllowMsg>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct AllowMsg {
     pub contract: String,
@@ -43,6 +46,10 @@ pub enum ExecuteMsg {
     AllowExternalToken(ExternalTokenMsg),
     /// Change the admin (must be called by current admin)
     UpdateAdmin { admin: String },
+    /// Stop sending and receiving packets on the channel (must be called by current admin)
+    FreezeChannel { channel: String },
+    /// Resume a frozen channel (must be called by current admin)
+    UnfreezeChannel { channel: String },
 }
 
 /// This is the message we accept via Receive
@@ -46,6 +46,21 @@ pub struct ChannelInfo {
     pub counterparty_endpoint: IbcEndpoint,
     /// the connection this exists on (you can use to query client/consensus info)
     pub connection_id: String,
+    /// whether packets can be sent and received on the channel
+    #[serde(default)]
+    pub status: ChannelStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelStatus {
+    #[default]
+    Open,
+    /// Halted by the admin, no packets are sent or received until it is unfrozen. Acks and
+    /// timeouts of the packets already sent are still handled.
+    Frozen,
+    /// Closed by the counterparty, the packets already sent time out
+    Closed,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -85,6 +100,18 @@ pub fn increase_channel_balance(
     Ok(())
 }
 
+/// Loads the channel, it must be open to send or receive packets
+pub fn load_open_channel(storage: &dyn Storage, id: &str) -> Result<ChannelInfo, ContractError> {
+    let info = CHANNEL_INFO
+        .may_load(storage, id)?
+        .ok_or_else(|| ContractError::NoSuchChannel { id: id.to_string() })?;
+    match info.status {
+        ChannelStatus::Open => Ok(info),
+        ChannelStatus::Frozen => Err(ContractError::ChannelFrozen { id: id.to_string() }),
+        ChannelStatus::Closed => Err(ContractError::ChannelClosed { id: id.to_string() }),
+    }
+}
+
 pub fn reduce_channel_balance(
     storage: &mut dyn Storage,
     channel: &str,
@@ -127,7 +154,7 @@ pub fn find_external_token(
         return Ok(None);
     }
 
-    return Ok(Some(allow.get(0).unwrap().to_string()));
+    Ok(Some(allow.first().unwrap().to_string()))
 }
 
 // this is like increase, but it only "un-subtracts" (= adds) outstanding, not total_sent
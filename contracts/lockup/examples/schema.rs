@@ -3,7 +3,10 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use cw_osmo_lockup::msg::{ExecuteMsg, InstantiateMsg, LockResult, QueryMsg};
+use cw_osmo_lockup::msg::{
+    BalancesResponse, ExecuteMsg, ExtendLockResult, InstantiateMsg, ListLocksResponse, LockInfo,
+    LockResult, LocksResponse, MigrateMsg, QueryMsg, UnlockAllResult,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -12,7 +15,14 @@ fn main() {
     remove_schemas(&out_dir).unwrap();
 
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(LockResult), &out_dir);
+    export_schema(&schema_for!(ExtendLockResult), &out_dir);
+    export_schema(&schema_for!(UnlockAllResult), &out_dir);
+    export_schema(&schema_for!(LocksResponse), &out_dir);
+    export_schema(&schema_for!(LockInfo), &out_dir);
+    export_schema(&schema_for!(BalancesResponse), &out_dir);
+    export_schema(&schema_for!(ListLocksResponse), &out_dir);
 }
@@ -1,38 +1,66 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Reply, Response,
-    StdError, StdResult, SubMsg, SubMsgResult, Uint64,
+    to_binary, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order, Reply,
+    Response, StdError, StdResult, Storage, SubMsg, SubMsgResult, Timestamp, Uint64,
 };
 use cw2::set_contract_version;
 use cw_osmo_proto::osmosis::lockup;
 use cw_osmo_proto::proto_ext::{proto_decode, MessageExt};
+use cw_osmo_proto::query::query_proto;
+use cw_storage_plus::Bound;
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, LockResult, QueryMsg};
-use crate::state::ADMIN;
+use crate::msg::{
+    BalancesResponse, ExecuteMsg, ExtendLockResult, InstantiateMsg, ListLocksResponse, LockInfo,
+    LockResult, LocksResponse, MigrateMsg, QueryMsg, UnlockAllResult, UnlockingLock,
+};
+use crate::state::{LockRecord, LockStatus, ADMIN, LOCKS, PENDING_LOCKS, REPLY_SEQ};
 
+use cw_migrate::check_migrate_version;
 use cw_utils::{nonpayable, one_coin};
 
 const CONTRACT_NAME: &str = "crates.io:cw-osmo-lockup";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 const LOCK_TOKEN_ID: u64 = 0x43ab;
+const UNLOCK_ALL_ID: u64 = 0x43ac;
+
+// Reply ids keep the handler in the low bits, the high bits are a sequence so that every
+// submessage has its own reply context
+const REPLY_KIND_BITS: u32 = 16;
+
+fn reply_kind(id: u64) -> u64 {
+    id & ((1 << REPLY_KIND_BITS) - 1)
+}
+
+// Reply id of the given kind, unique so that it can index the reply context
+fn next_reply_id(storage: &mut dyn Storage, kind: u64) -> StdResult<u64> {
+    let seq = REPLY_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    REPLY_SEQ.save(storage, &seq)?;
+    Ok(kind | seq << REPLY_KIND_BITS)
+}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(_deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, ContractError> {
-    match reply.id {
-        LOCK_TOKEN_ID => reply_lock(reply),
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    match reply_kind(reply.id) {
+        LOCK_TOKEN_ID => reply_lock(deps, reply),
+        UNLOCK_ALL_ID => reply_unlock_all(deps, env, reply),
         _ => Err(ContractError::UnknownReplyId { id: reply.id }),
     }
 }
 
-pub fn reply_lock(reply: Reply) -> Result<Response, ContractError> {
+pub fn reply_lock(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
     match reply.result {
         SubMsgResult::Ok(tx) => {
             let data = tx.data.ok_or(ContractError::NoReplyData {})?;
 
             let response: lockup::MsgLockTokensResponse = proto_decode(data.as_slice())?;
+            let mut record = PENDING_LOCKS.load(deps.storage, reply.id)?;
+            PENDING_LOCKS.remove(deps.storage, reply.id);
+            record.lock_id = response.id.into();
+            LOCKS.save(deps.storage, response.id, &record)?;
+
             let result = LockResult {
                 lock_id: response.id.into(),
             };
@@ -43,6 +71,40 @@ pub fn reply_lock(reply: Reply) -> Result<Response, ContractError> {
     }
 }
 
+pub fn reply_unlock_all(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    match reply.result {
+        SubMsgResult::Ok(tx) => {
+            let data = tx.data.ok_or(ContractError::NoReplyData {})?;
+
+            let response: lockup::MsgBeginUnlockingAllResponse = proto_decode(data.as_slice())?;
+            let unlocks = response
+                .unlocks
+                .into_iter()
+                .map(|lock| {
+                    let mut record = match LOCKS.may_load(deps.storage, lock.id)? {
+                        Some(record) => record,
+                        None => to_lock_record(&lock, env.block.time)?,
+                    };
+                    if record.status == LockStatus::Locked {
+                        record.status = LockStatus::Unlocking;
+                        record.unlock_started_at = Some(env.block.time);
+                    }
+                    LOCKS.save(deps.storage, lock.id, &record)?;
+
+                    Ok(UnlockingLock {
+                        lock_id: lock.id.into(),
+                        coins: to_coins(lock.coins)?,
+                        end_time: to_timestamp(lock.end_time),
+                    })
+                })
+                .collect::<StdResult<_>>()?;
+
+            Ok(Response::new().set_data(to_binary(&UnlockAllResult { unlocks })?))
+        }
+        SubMsgResult::Err(err) => Err(StdError::generic_err(err).into()),
+    }
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     mut deps: DepsMut,
@@ -65,14 +127,19 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
-    let contract = env.contract.address.into();
+    let contract = env.contract.address.to_string();
     match msg {
         ExecuteMsg::Lock { duration } => {
             let coin = one_coin(&info)?;
-            execute_lock(deps, info, duration, coin, contract)
+            execute_lock(deps, env.block.time, info, duration, coin, contract)
+        }
+        ExecuteMsg::Unlock { id } => execute_unlock(deps, env.block.time, info, contract, id),
+        ExecuteMsg::ExtendLock { id, duration } => {
+            execute_extend_lock(deps, info, contract, id, duration)
         }
-        ExecuteMsg::Unlock { id } => execute_unlock(deps, info, contract, id),
-        ExecuteMsg::Claim { denom } => execute_claim(deps, info, contract, denom),
+        ExecuteMsg::UnlockAll {} => execute_unlock_all(deps, info, contract),
+        ExecuteMsg::Claim { denoms } => execute_claim(deps, info, contract, denoms),
+        ExecuteMsg::ClaimAll {} => execute_claim_all(deps, info, contract),
         ExecuteMsg::UpdateAdmin { admin } => {
             let admin = deps.api.addr_validate(&admin)?;
             Ok(ADMIN.execute_update_admin(deps, info, Some(admin))?)
@@ -82,6 +149,7 @@ pub fn execute(
 
 pub fn execute_lock(
     deps: DepsMut,
+    now: Timestamp,
     info: MessageInfo,
     duration: Uint64,
     token_in: Coin,
@@ -89,6 +157,18 @@ pub fn execute_lock(
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
 
+    let record = LockRecord {
+        lock_id: Uint64::zero(),
+        denom: token_in.denom.clone(),
+        amount: token_in.amount,
+        duration,
+        locked_at: now,
+        unlock_started_at: None,
+        status: LockStatus::Locked,
+    };
+    let reply_id = next_reply_id(deps.storage, LOCK_TOKEN_ID)?;
+    PENDING_LOCKS.save(deps.storage, reply_id, &record)?;
+
     let tx = lockup::MsgLockTokens {
         owner: contract,
         duration: Some(cw_osmo_proto::Duration {
@@ -100,7 +180,7 @@ pub fn execute_lock(
             amount: token_in.amount.to_string(),
         }],
     };
-    let submsg = SubMsg::reply_on_success(tx.to_msg()?, LOCK_TOKEN_ID);
+    let submsg = SubMsg::reply_on_success(tx.to_msg()?, reply_id);
 
     Ok(Response::new()
         .add_submessage(submsg)
@@ -110,6 +190,7 @@ pub fn execute_lock(
 
 pub fn execute_unlock(
     deps: DepsMut,
+    now: Timestamp,
     info: MessageInfo,
     contract: String,
     lock_id: Uint64,
@@ -121,6 +202,11 @@ pub fn execute_unlock(
     }
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
 
+    let mut record = load_locked(deps.storage, lock_id)?;
+    record.status = LockStatus::Unlocking;
+    record.unlock_started_at = Some(now);
+    LOCKS.save(deps.storage, lock_id.u64(), &record)?;
+
     let tx = lockup::MsgBeginUnlocking {
         owner: contract,
         id: lock_id.u64(),
@@ -133,57 +219,339 @@ pub fn execute_unlock(
         .add_attribute("lock_id", lock_id.to_string()))
 }
 
+pub fn execute_extend_lock(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+    lock_id: Uint64,
+    duration: Uint64,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+
+    if lock_id.is_zero() {
+        return Err(ContractError::InvalidLockId {});
+    }
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let mut record = load_locked(deps.storage, lock_id)?;
+    record.duration = duration;
+    LOCKS.save(deps.storage, lock_id.u64(), &record)?;
+
+    // x/lockup rejects a shorter duration, so the result is known beforehand
+    let tx = lockup::MsgExtendLockup {
+        owner: contract,
+        id: lock_id.u64(),
+        duration: Some(cw_osmo_proto::Duration {
+            seconds: duration.u64() as i64,
+            nanos: 0,
+        }),
+    };
+    let result = ExtendLockResult { lock_id, duration };
+
+    Ok(Response::new()
+        .set_data(to_binary(&result)?)
+        .add_message(tx.to_msg()?)
+        .add_attribute("action", "extend_lock")
+        .add_attribute("lock_id", lock_id.to_string())
+        .add_attribute("duration", duration.to_string()))
+}
+
+// x/lockup only unlocks or extends locks that are not unlocking yet
+fn load_locked(storage: &dyn Storage, lock_id: Uint64) -> Result<LockRecord, ContractError> {
+    let record = LOCKS
+        .may_load(storage, lock_id.u64())?
+        .ok_or(ContractError::InvalidLockId {})?;
+    if record.status != LockStatus::Locked {
+        return Err(ContractError::LockUnlocking { id: lock_id.u64() });
+    }
+    Ok(record)
+}
+
+pub fn execute_unlock_all(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let tx = lockup::MsgBeginUnlockingAll { owner: contract };
+    let submsg = SubMsg::reply_on_success(tx.to_msg()?, UNLOCK_ALL_ID);
+
+    Ok(Response::new()
+        .add_submessage(submsg)
+        .add_attribute("action", "unlock_all"))
+}
+
 pub fn execute_claim(
     deps: DepsMut,
     info: MessageInfo,
     contract: String,
-    denom: String,
+    mut denoms: Vec<String>,
 ) -> Result<Response, ContractError> {
     nonpayable(&info)?;
 
-    if denom.is_empty() {
+    if denoms.is_empty() || denoms.iter().any(|d| d.is_empty()) {
         return Err(ContractError::InvalidEmptyDenom {});
     }
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
 
-    let balance = deps.querier.query_balance(contract, denom)?;
-    if balance.amount.is_zero() {
+    denoms.sort();
+    denoms.dedup();
+    let balances = denoms
+        .into_iter()
+        .map(|denom| deps.querier.query_balance(&contract, denom))
+        .collect::<StdResult<_>>()?;
+
+    send_balances(info, balances, "claim")
+}
+
+pub fn execute_claim_all(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+
+    let balances = deps.querier.query_all_balances(contract)?;
+    send_balances(info, balances, "claim_all")
+}
+
+// Sends the non-zero balances to the admin, they are returned as data
+fn send_balances(
+    info: MessageInfo,
+    balances: Vec<Coin>,
+    action: &str,
+) -> Result<Response, ContractError> {
+    let balances: Vec<Coin> = balances
+        .into_iter()
+        .filter(|c| !c.amount.is_zero())
+        .collect();
+    if balances.is_empty() {
         return Err(ContractError::NoBalance {});
     }
 
+    let amount = balances
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
     let bank_msg: CosmosMsg = BankMsg::Send {
         to_address: info.sender.to_string(),
-        amount: vec![balance.clone()],
+        amount: balances.clone(),
     }
     .into();
 
     Ok(Response::new()
-        .set_data(to_binary(&balance)?)
+        .set_data(to_binary(&balances)?)
         .add_message(bank_msg)
-        .add_attribute("action", "claim")
-        .add_attribute("amount", balance.amount))
+        .add_attribute("action", action)
+        .add_attribute("amount", amount))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn migrate(deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let previous = check_migrate_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // track the locks created before the contract kept its own records
+    let tracked = LOCKS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if !tracked {
+        let contract = env.contract.address.to_string();
+        let request = lockup::AccountLockedLongerDurationRequest {
+            owner: contract,
+            duration: Some(cw_osmo_proto::Duration::default()),
+        };
+        let res: lockup::AccountLockedLongerDurationResponse = query_proto(deps.as_ref(), request)?;
+        for lock in res.locks {
+            let record = to_lock_record(&lock, env.block.time)?;
+            LOCKS.save(deps.storage, lock.id, &record)?;
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", previous.to_string())
+        .add_attribute("to_version", CONTRACT_VERSION))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    let contract = env.contract.address.to_string();
     match msg {
         QueryMsg::Admin {} => to_binary(&ADMIN.query_admin(deps)?),
+        QueryMsg::Locks { min_duration } => to_binary(&query_locks(deps, contract, min_duration)?),
+        QueryMsg::Lock { id } => to_binary(&query_lock(deps, contract, id)?),
+        QueryMsg::Balances {} => to_binary(&query_balances(deps, contract)?),
+        QueryMsg::ListLocks {
+            status,
+            start_after,
+            limit,
+        } => to_binary(&query_list_locks(deps, status, start_after, limit)?),
+    }
+}
+
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+fn query_list_locks(
+    deps: Deps,
+    status: Option<LockStatus>,
+    start_after: Option<Uint64>,
+    limit: Option<u32>,
+) -> StdResult<ListLocksResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(id.u64()));
+
+    let locks = LOCKS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|item| match (item, status) {
+            (Ok((_, record)), Some(status)) => record.status == status,
+            _ => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, record)| record))
+        .collect::<StdResult<_>>()?;
+    Ok(ListLocksResponse { locks })
+}
+
+fn query_locks(
+    deps: Deps,
+    contract: String,
+    min_duration: Option<Uint64>,
+) -> StdResult<LocksResponse> {
+    let request = lockup::AccountLockedLongerDurationRequest {
+        owner: contract,
+        duration: Some(cw_osmo_proto::Duration {
+            seconds: min_duration.unwrap_or_default().u64() as i64,
+            nanos: 0,
+        }),
+    };
+    let res: lockup::AccountLockedLongerDurationResponse = query_proto(deps, request)?;
+    let locks = res
+        .locks
+        .into_iter()
+        .map(to_lock_info)
+        .collect::<StdResult<_>>()?;
+    Ok(LocksResponse { locks })
+}
+
+fn query_lock(deps: Deps, contract: String, lock_id: Uint64) -> StdResult<LockInfo> {
+    let request = lockup::LockedRequest {
+        lock_id: lock_id.u64(),
+    };
+    let res: lockup::LockedResponse = query_proto(deps, request)?;
+    match res.lock {
+        Some(lock) if lock.owner == contract => to_lock_info(lock),
+        _ => Err(StdError::not_found("lock")),
     }
 }
 
+fn query_balances(deps: Deps, contract: String) -> StdResult<BalancesResponse> {
+    let locked: lockup::AccountLockedCoinsResponse = query_proto(
+        deps,
+        lockup::AccountLockedCoinsRequest {
+            owner: contract.clone(),
+        },
+    )?;
+    let unlockable: lockup::AccountUnlockableCoinsResponse = query_proto(
+        deps,
+        lockup::AccountUnlockableCoinsRequest {
+            owner: contract.clone(),
+        },
+    )?;
+    let unlocking: lockup::AccountUnlockingCoinsResponse = query_proto(
+        deps,
+        lockup::AccountUnlockingCoinsRequest { owner: contract },
+    )?;
+
+    Ok(BalancesResponse {
+        locked: to_coins(locked.coins)?,
+        unlockable: to_coins(unlockable.coins)?,
+        unlocking: to_coins(unlocking.coins)?,
+    })
+}
+
+fn to_lock_info(lock: lockup::PeriodLock) -> StdResult<LockInfo> {
+    let duration = lock.duration.unwrap_or_default();
+    Ok(LockInfo {
+        lock_id: lock.id.into(),
+        coins: to_coins(lock.coins)?,
+        duration: (duration.seconds as u64).into(),
+        end_time: to_timestamp(lock.end_time),
+    })
+}
+
+// x/lockup sets the end time once unlocking begins, the duration before it
+fn to_lock_record(lock: &lockup::PeriodLock, locked_at: Timestamp) -> StdResult<LockRecord> {
+    let coin = to_coins(lock.coins.clone())?
+        .pop()
+        .unwrap_or_else(|| Coin::new(0, ""));
+    let duration = lock.duration.clone().unwrap_or_default().seconds.max(0) as u64;
+    let end_time = to_timestamp(lock.end_time.clone());
+    let (status, unlock_started_at) = if end_time.nanos() == 0 {
+        (LockStatus::Locked, None)
+    } else {
+        (
+            LockStatus::Unlocking,
+            Some(end_time.minus_nanos(duration * 1_000_000_000)),
+        )
+    };
+    Ok(LockRecord {
+        lock_id: lock.id.into(),
+        denom: coin.denom,
+        amount: coin.amount,
+        duration: duration.into(),
+        locked_at,
+        unlock_started_at,
+        status,
+    })
+}
+
+fn to_coins(coins: Vec<cw_osmo_proto::cosmos::base::v1beta1::Coin>) -> StdResult<Vec<Coin>> {
+    coins
+        .into_iter()
+        .map(|c| {
+            let amount = c
+                .amount
+                .parse::<u128>()
+                .map_err(|_| StdError::generic_err(format!("Invalid amount: {}", c.amount)))?;
+            Ok(Coin::new(amount, c.denom))
+        })
+        .collect()
+}
+
+// x/lockup sets the end time once unlocking begins
+fn to_timestamp(time: Option<cw_osmo_proto::Timestamp>) -> Timestamp {
+    let time = time.unwrap_or_default();
+    Timestamp::from_seconds(time.seconds.max(0) as u64).plus_nanos(time.nanos.max(0) as u64)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use cw2::get_contract_version;
+
+    use std::marker::PhantomData;
 
     use cosmwasm_std::testing::{
         mock_dependencies_with_balance, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        attr, coins, from_binary, from_slice, Binary, ContractResult, Empty, Event, OwnedDeps,
+        Querier, QuerierResult, QueryRequest, SubMsgResponse, SystemError, SystemResult,
     };
-    use cosmwasm_std::{attr, coins, from_binary, Binary, Empty, Event, OwnedDeps, SubMsgResponse};
     use cw_controllers::{AdminError, AdminResponse};
+    use cw_osmo_proto::Message;
     use cw_utils::PaymentError::NonPayable;
 
     pub fn mock_lock_events() -> Vec<Event> {
-        return vec![
+        vec![
             Event::new("begin_unlock").add_attributes(vec![
                 attr("period_lock_id", "16"),
                 attr("owner", "osmo1q4aw0vtcyyredprm4ncmr4jdj70kpgyr3"),
@@ -191,7 +559,7 @@ mod test {
                 attr("unlock_time", "0001-01-01 00:00:00 +0000 UTC"),
             ]),
             Event::new("message").add_attributes(vec![attr("action", "begin_unlocking")]),
-        ];
+        ]
     }
 
     fn setup_init() -> OwnedDeps<MockStorage, MockApi, MockQuerier, Empty> {
@@ -207,6 +575,24 @@ mod test {
         deps
     }
 
+    fn mock_lock(mut deps: DepsMut, lock_id: u64) {
+        let msg = ExecuteMsg::Lock {
+            duration: 86400u64.into(),
+        };
+        let sender = mock_info("owner", &coins(1000u128, "gamm/pool/1"));
+        let res = execute(deps.branch(), mock_env(), sender, msg).unwrap();
+
+        let data = lockup::MsgLockTokensResponse { id: lock_id }.encode_to_vec();
+        let reply_msg = Reply {
+            id: res.messages[0].id,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: mock_lock_events(),
+                data: Some(data.into()),
+            }),
+        };
+        reply(deps, mock_env(), reply_msg).unwrap();
+    }
+
     #[test]
     fn execute_lock() {
         let mut deps = setup_init();
@@ -221,15 +607,21 @@ mod test {
         let err = execute(deps.as_mut(), mock_env(), sender, msg.clone()).unwrap_err();
         assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
 
-        // lock token: Valid owner
+        // lock token: Valid owner, several locks can be in flight
         let sender = mock_info("owner", &coins(1000u128, denom));
-        let res = execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), sender, msg.clone()).unwrap();
         assert_eq!(1, res.messages.len());
+        let first_id = res.messages[0].id;
+        assert_eq!(reply_kind(first_id), LOCK_TOKEN_ID);
+        let sender = mock_info("owner", &coins(500u128, "gamm/pool/2"));
+        let res = execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
+        let second_id = res.messages[0].id;
+        assert_ne!(first_id, second_id);
 
         // Simulate reply result
         let data = Binary::from_base64("CAE=").unwrap(); // id: 1
         let reply_msg = Reply {
-            id: LOCK_TOKEN_ID,
+            id: first_id,
             result: SubMsgResult::Ok(SubMsgResponse {
                 events: mock_lock_events(),
                 data: Some(data),
@@ -240,6 +632,40 @@ mod test {
 
         let lock_res: LockResult = from_binary(&res.data.unwrap()).unwrap();
         assert_eq!(Uint64::new(1u64), lock_res.lock_id);
+
+        let record = LOCKS.load(&deps.storage, 1).unwrap();
+        let expected = LockRecord {
+            lock_id: 1u64.into(),
+            denom: denom.to_string(),
+            amount: 1000u128.into(),
+            duration: 86400u64.into(),
+            locked_at: mock_env().block.time,
+            unlock_started_at: None,
+            status: LockStatus::Locked,
+        };
+        assert_eq!(record, expected);
+        assert_eq!(
+            PENDING_LOCKS.may_load(&deps.storage, first_id).unwrap(),
+            None
+        );
+
+        // the second lock keeps its own record
+        let data = lockup::MsgLockTokensResponse { id: 2 }.encode_to_vec();
+        let reply_msg = Reply {
+            id: second_id,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: mock_lock_events(),
+                data: Some(data.into()),
+            }),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let record = LOCKS.load(&deps.storage, 2).unwrap();
+        assert_eq!(record.denom, "gamm/pool/2");
+        assert_eq!(record.amount, 500u128.into());
+        assert_eq!(
+            PENDING_LOCKS.may_load(&deps.storage, second_id).unwrap(),
+            None
+        );
     }
 
     #[test]
@@ -248,7 +674,7 @@ mod test {
         let denom = "uosmo";
 
         let msg = ExecuteMsg::Claim {
-            denom: "uatom".to_string(),
+            denoms: vec!["uatom".to_string()],
         };
 
         // Claim rewards: Invalid owner
@@ -266,9 +692,15 @@ mod test {
         let err = execute(deps.as_mut(), mock_env(), sender, msg).unwrap_err();
         assert_eq!(err, ContractError::Payment(NonPayable {}));
 
-        // Claim rewards: valid owner, valid amount
+        // Claim rewards: no denom
+        let msg = ExecuteMsg::Claim { denoms: vec![] };
+        let sender = mock_info("owner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidEmptyDenom {});
+
+        // Claim rewards: valid owner, valid amount, zero balances are skipped
         let msg = ExecuteMsg::Claim {
-            denom: denom.to_string(),
+            denoms: vec![denom.to_string(), "uatom".to_string()],
         };
 
         let sender = mock_info("owner", &[]);
@@ -281,6 +713,44 @@ mod test {
                 amount: coins(1250u128, denom),
             }))
         );
+        let claimed: Vec<Coin> = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(claimed, coins(1250u128, denom));
+    }
+
+    #[test]
+    fn execute_claim_all() {
+        let mut deps = setup_init();
+        let balances = vec![
+            Coin::new(1250u128, "gamm/pool/1"),
+            Coin::new(300u128, "uion"),
+            Coin::new(7000u128, "uosmo"),
+        ];
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, balances.clone());
+
+        // Claim all: Invalid owner
+        let sender = mock_info("any", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, ExecuteMsg::ClaimAll {}).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        // Claim all: valid owner
+        let sender = mock_info("owner", &[]);
+        let res = execute(deps.as_mut(), mock_env(), sender, ExecuteMsg::ClaimAll {}).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "owner".to_string(),
+                amount: balances.clone(),
+            }))]
+        );
+        let claimed: Vec<Coin> = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(claimed, balances);
+
+        // Claim all: empty balance
+        deps.querier.update_balance(MOCK_CONTRACT_ADDR, vec![]);
+        let sender = mock_info("owner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, ExecuteMsg::ClaimAll {}).unwrap_err();
+        assert_eq!(err, ContractError::NoBalance {});
     }
 
     #[test]
@@ -299,10 +769,122 @@ mod test {
         let err = execute(deps.as_mut(), mock_env(), sender, msg.clone()).unwrap_err();
         assert_eq!(err, ContractError::Payment(NonPayable {}));
 
+        // unlock token: Unknown lock
+        let sender = mock_info("owner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidLockId {});
+
         // unlock token: Valid owner
+        mock_lock(deps.as_mut(), 1);
+        let sender = mock_info("owner", &[]);
+        let res = execute(deps.as_mut(), mock_env(), sender, msg.clone()).unwrap();
+        assert_eq!(1, res.messages.len());
+
+        let record = LOCKS.load(&deps.storage, 1).unwrap();
+        assert_eq!(record.status, LockStatus::Unlocking);
+        assert_eq!(record.unlock_started_at, Some(mock_env().block.time));
+
+        // unlock token: Already unlocking
+        let sender = mock_info("owner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, msg).unwrap_err();
+        assert_eq!(err, ContractError::LockUnlocking { id: 1 });
+    }
+
+    #[test]
+    fn execute_extend_lock() {
+        let mut deps = setup_init();
+
+        let msg = ExecuteMsg::ExtendLock {
+            id: 1u64.into(),
+            duration: 1209600u64.into(),
+        };
+
+        // extend lock: Invalid owner
+        let sender = mock_info("any", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        // extend lock: Invalid lock id
+        let invalid_msg = ExecuteMsg::ExtendLock {
+            id: 0u64.into(),
+            duration: 1209600u64.into(),
+        };
+        let sender = mock_info("owner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, invalid_msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidLockId {});
+
+        // extend lock: Valid owner
+        mock_lock(deps.as_mut(), 1);
         let sender = mock_info("owner", &[]);
         let res = execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
         assert_eq!(1, res.messages.len());
+        let result: ExtendLockResult = from_binary(&res.data.unwrap()).unwrap();
+        let expected = ExtendLockResult {
+            lock_id: 1u64.into(),
+            duration: 1209600u64.into(),
+        };
+        assert_eq!(result, expected);
+        let record = LOCKS.load(&deps.storage, 1).unwrap();
+        assert_eq!(record.duration, Uint64::new(1209600));
+    }
+
+    #[test]
+    fn execute_unlock_all() {
+        let mut deps = setup_init();
+
+        // unlock all: Invalid owner
+        let sender = mock_info("any", &[]);
+        let err = execute(deps.as_mut(), mock_env(), sender, ExecuteMsg::UnlockAll {}).unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        // unlock all: Valid owner
+        let sender = mock_info("owner", &[]);
+        let res = execute(deps.as_mut(), mock_env(), sender, ExecuteMsg::UnlockAll {}).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(res.messages[0].id, UNLOCK_ALL_ID);
+
+        // Simulate reply result
+        let response = lockup::MsgBeginUnlockingAllResponse {
+            unlocks: vec![lockup::PeriodLock {
+                id: 16,
+                owner: "osmo1q4aw0vtcyyredprm4ncmr4jdj70kpgyr3".to_string(),
+                duration: Some(cw_osmo_proto::Duration {
+                    seconds: 1209600,
+                    nanos: 0,
+                }),
+                end_time: Some(cw_osmo_proto::Timestamp {
+                    seconds: 1660000000,
+                    nanos: 0,
+                }),
+                coins: vec![cw_osmo_proto::cosmos::base::v1beta1::Coin {
+                    denom: "gamm/pool/1".to_string(),
+                    amount: "1000".to_string(),
+                }],
+            }],
+        };
+        let reply_msg = Reply {
+            id: UNLOCK_ALL_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(response.encode_to_vec().into()),
+            }),
+        };
+        let res = reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let result: UnlockAllResult = from_binary(&res.data.unwrap()).unwrap();
+        let expected = UnlockAllResult {
+            unlocks: vec![UnlockingLock {
+                lock_id: 16u64.into(),
+                coins: coins(1000u128, "gamm/pool/1"),
+                end_time: Timestamp::from_seconds(1660000000),
+            }],
+        };
+        assert_eq!(result, expected);
+
+        // untracked locks are recorded as they unlock
+        let record = LOCKS.load(&deps.storage, 16).unwrap();
+        assert_eq!(record.status, LockStatus::Unlocking);
+        let started_at = Timestamp::from_seconds(1660000000 - 1209600);
+        assert_eq!(record.unlock_started_at, Some(started_at));
     }
 
     #[test]
@@ -314,4 +896,230 @@ mod test {
 
         assert_eq!("owner", admin.admin.unwrap().as_str());
     }
+
+    /// Answers the x/lockup account queries for two locks of the contract
+    struct LockupQuerier {
+        owner: String,
+    }
+
+    impl LockupQuerier {
+        fn lock(&self, id: u64, end_time: Option<cw_osmo_proto::Timestamp>) -> lockup::PeriodLock {
+            lockup::PeriodLock {
+                id,
+                owner: self.owner.clone(),
+                duration: Some(cw_osmo_proto::Duration {
+                    seconds: 86400 * id as i64,
+                    nanos: 0,
+                }),
+                end_time,
+                coins: vec![proto_coin(1000 * id, "gamm/pool/1")],
+            }
+        }
+    }
+
+    fn proto_coin(amount: u64, denom: &str) -> cw_osmo_proto::cosmos::base::v1beta1::Coin {
+        cw_osmo_proto::cosmos::base::v1beta1::Coin {
+            denom: denom.to_string(),
+            amount: amount.to_string(),
+        }
+    }
+
+    impl Querier for LockupQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+            let (path, data) = match request {
+                QueryRequest::Stargate { path, data } => (path, data),
+                _ => panic!("unexpected query"),
+            };
+
+            let unlocking = Some(cw_osmo_proto::Timestamp {
+                seconds: 1660000000,
+                nanos: 0,
+            });
+            let res = match path.as_str() {
+                "/osmosis.lockup.Query/AccountLockedLongerDuration" => {
+                    let req: lockup::AccountLockedLongerDurationRequest =
+                        proto_decode(data.as_slice()).unwrap();
+                    assert_eq!(req.owner, self.owner);
+                    let min = req.duration.unwrap().seconds;
+                    let locks = vec![self.lock(1, None), self.lock(2, unlocking)]
+                        .into_iter()
+                        .filter(|l| l.duration.as_ref().unwrap().seconds >= min)
+                        .collect();
+                    lockup::AccountLockedLongerDurationResponse { locks }.encode_to_vec()
+                }
+                "/osmosis.lockup.Query/LockedByID" => {
+                    let req: lockup::LockedRequest = proto_decode(data.as_slice()).unwrap();
+                    let mut lock = self.lock(req.lock_id, unlocking);
+                    if req.lock_id == 3 {
+                        lock.owner = "someone".to_string();
+                    }
+                    lockup::LockedResponse { lock: Some(lock) }.encode_to_vec()
+                }
+                "/osmosis.lockup.Query/AccountLockedCoins" => lockup::AccountLockedCoinsResponse {
+                    coins: vec![proto_coin(3000, "gamm/pool/1")],
+                }
+                .encode_to_vec(),
+                "/osmosis.lockup.Query/AccountUnlockableCoins" => {
+                    lockup::AccountUnlockableCoinsResponse { coins: vec![] }.encode_to_vec()
+                }
+                "/osmosis.lockup.Query/AccountUnlockingCoins" => {
+                    lockup::AccountUnlockingCoinsResponse {
+                        coins: vec![proto_coin(2000, "gamm/pool/1")],
+                    }
+                    .encode_to_vec()
+                }
+                _ => return SystemResult::Err(SystemError::UnsupportedRequest { kind: path }),
+            };
+            SystemResult::Ok(ContractResult::Ok(res.into()))
+        }
+    }
+
+    fn setup_with_locks() -> OwnedDeps<MockStorage, MockApi, LockupQuerier, Empty> {
+        OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier: LockupQuerier {
+                owner: MOCK_CONTRACT_ADDR.to_string(),
+            },
+            custom_query_type: PhantomData,
+        }
+    }
+
+    #[test]
+    fn query_locks_and_balances() {
+        let deps = setup_with_locks();
+        let first = LockInfo {
+            lock_id: 1u64.into(),
+            coins: coins(1000u128, "gamm/pool/1"),
+            duration: 86400u64.into(),
+            end_time: Timestamp::from_seconds(0),
+        };
+        let second = LockInfo {
+            lock_id: 2u64.into(),
+            coins: coins(2000u128, "gamm/pool/1"),
+            duration: 172800u64.into(),
+            end_time: Timestamp::from_seconds(1660000000),
+        };
+
+        let msg = QueryMsg::Locks { min_duration: None };
+        let res: LocksResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.locks, vec![first, second.clone()]);
+
+        let msg = QueryMsg::Locks {
+            min_duration: Some(100000u64.into()),
+        };
+        let res: LocksResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.locks, vec![second.clone()]);
+
+        let msg = QueryMsg::Lock { id: 2u64.into() };
+        let res: LockInfo = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res, second);
+
+        // locks of other accounts are not returned
+        let msg = QueryMsg::Lock { id: 3u64.into() };
+        let err = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, StdError::not_found("lock"));
+
+        let res: BalancesResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Balances {}).unwrap()).unwrap();
+        let expected = BalancesResponse {
+            locked: coins(3000u128, "gamm/pool/1"),
+            unlockable: vec![],
+            unlocking: coins(2000u128, "gamm/pool/1"),
+        };
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn list_locks() {
+        let mut deps = setup_init();
+        for id in 1..=3 {
+            mock_lock(deps.as_mut(), id);
+        }
+        let sender = mock_info("owner", &[]);
+        let msg = ExecuteMsg::Unlock { id: 2u64.into() };
+        execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
+
+        let list = |status, start_after: Option<u64>, limit| {
+            let msg = QueryMsg::ListLocks {
+                status,
+                start_after: start_after.map(Uint64::new),
+                limit,
+            };
+            let res: ListLocksResponse =
+                from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+            res.locks
+                .iter()
+                .map(|l| l.lock_id.u64())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(list(None, None, None), vec![1, 2, 3]);
+        assert_eq!(list(None, Some(1), Some(1)), vec![2]);
+        assert_eq!(list(Some(LockStatus::Locked), None, None), vec![1, 3]);
+        assert_eq!(list(Some(LockStatus::Unlocking), None, None), vec![2]);
+        assert_eq!(
+            list(Some(LockStatus::Unlocking), Some(2), None),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn migrate_contract() {
+        let mut deps = setup_with_locks();
+
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.0.1").unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.version, CONTRACT_VERSION);
+
+        // the existing locks are imported
+        let locks = LOCKS
+            .range(&deps.storage, None, None, Order::Ascending)
+            .map(|item| item.unwrap().1)
+            .collect::<Vec<_>>();
+        let expected = vec![
+            LockRecord {
+                lock_id: 1u64.into(),
+                denom: "gamm/pool/1".to_string(),
+                amount: 1000u128.into(),
+                duration: 86400u64.into(),
+                locked_at: mock_env().block.time,
+                unlock_started_at: None,
+                status: LockStatus::Locked,
+            },
+            LockRecord {
+                lock_id: 2u64.into(),
+                denom: "gamm/pool/1".to_string(),
+                amount: 2000u128.into(),
+                duration: 172800u64.into(),
+                locked_at: mock_env().block.time,
+                unlock_started_at: Some(Timestamp::from_seconds(1660000000 - 172800)),
+                status: LockStatus::Unlocking,
+            },
+        ];
+        assert_eq!(locks, expected);
+
+        // cannot downgrade
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrateVersion {
+                previous_version: "99.0.0".to_string()
+            }
+        );
+
+        set_contract_version(deps.as_mut().storage, "crates.io:other", "0.0.1").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate {
+                previous_contract: "crates.io:other".to_string()
+            }
+        );
+    }
 }
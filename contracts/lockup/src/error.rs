@@ -1,5 +1,6 @@
 use cosmwasm_std::StdError;
 use cw_controllers::AdminError;
+use cw_migrate::MigrateError;
 use cw_utils::PaymentError;
 use thiserror::Error;
 
@@ -20,6 +21,9 @@ pub enum ContractError {
     #[error("Invalid lock id")]
     InvalidLockId {},
 
+    #[error("Lock {id} is already unlocking")]
+    LockUnlocking { id: u64 },
+
     #[error("Invalid token denomination")]
     InvalidEmptyDenom {},
 
@@ -29,6 +33,12 @@ pub enum ContractError {
     #[error("Missing reply data")]
     NoReplyData {},
 
+    #[error("Cannot migrate from different contract type: {previous_contract}")]
+    CannotMigrate { previous_contract: String },
+
+    #[error("Cannot migrate from unsupported version: {previous_version}")]
+    CannotMigrateVersion { previous_version: String },
+
     #[error("Got a submessage reply with unknown id: {id}")]
     UnknownReplyId { id: u64 },
 
@@ -38,3 +48,17 @@ pub enum ContractError {
     #[error("Cannot get lock end time")]
     NoFoundLockEndTime {},
 }
+
+impl From<MigrateError> for ContractError {
+    fn from(err: MigrateError) -> Self {
+        match err {
+            MigrateError::Std(err) => ContractError::Std(err),
+            MigrateError::CannotMigrate { previous_contract } => {
+                ContractError::CannotMigrate { previous_contract }
+            }
+            MigrateError::CannotMigrateVersion { previous_version } => {
+                ContractError::CannotMigrateVersion { previous_version }
+            }
+        }
+    }
+}
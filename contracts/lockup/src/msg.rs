@@ -1,13 +1,18 @@
-use cosmwasm_std::Uint64;
+use cosmwasm_std::{Coin, Timestamp, Uint64};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::state::{LockRecord, LockStatus};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct InstantiateMsg {
     pub admin: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
@@ -15,8 +20,14 @@ pub enum ExecuteMsg {
     Lock { duration: Uint64 },
     /// Begin unlocking tokens
     Unlock { id: Uint64 },
-    /// Claim contract balance (only admin)
-    Claim { denom: String },
+    /// Extend the lock duration (in seconds), it cannot be shorter than the current one
+    ExtendLock { id: Uint64, duration: Uint64 },
+    /// Begin unlocking all the locked tokens
+    UnlockAll {},
+    /// Claim the contract balance of these denoms (only admin), returns the coins sent
+    Claim { denoms: Vec<String> },
+    /// Claim the whole contract balance (only admin), returns the coins sent
+    ClaimAll {},
     /// Change the admin (must be called by current admin)
     UpdateAdmin { admin: String },
 }
@@ -26,9 +37,73 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     /// Return AdminResponse
     Admin {},
+    /// Locks of the contract with a duration of at least `min_duration` seconds (all if not
+    /// set). Return type: LocksResponse.
+    Locks { min_duration: Option<Uint64> },
+    /// Returns the lock, error if not owned by the contract. Return type: LockInfo.
+    Lock { id: Uint64 },
+    /// Coins locked, unlockable (can be claimed) and unlocking by the contract.
+    /// Return type: BalancesResponse.
+    Balances {},
+    /// Locks tracked by the contract, filtered by status if set. Return type: ListLocksResponse.
+    ListLocks {
+        status: Option<LockStatus>,
+        start_after: Option<Uint64>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockInfo {
+    pub lock_id: Uint64,
+    pub coins: Vec<Coin>,
+    /// Lock duration in seconds
+    pub duration: Uint64,
+    /// When the coins can be claimed, zero until unlocking begins
+    pub end_time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LocksResponse {
+    pub locks: Vec<LockInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListLocksResponse {
+    pub locks: Vec<LockRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BalancesResponse {
+    /// Coins locked, unlocking ones included
+    pub locked: Vec<Coin>,
+    /// Coins whose unlocking ended
+    pub unlockable: Vec<Coin>,
+    /// Coins unlocking
+    pub unlocking: Vec<Coin>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LockResult {
     pub lock_id: Uint64,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExtendLockResult {
+    pub lock_id: Uint64,
+    /// New lock duration in seconds
+    pub duration: Uint64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnlockAllResult {
+    pub unlocks: Vec<UnlockingLock>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnlockingLock {
+    pub lock_id: Uint64,
+    pub coins: Vec<Coin>,
+    /// When the coins can be claimed
+    pub end_time: Timestamp,
+}
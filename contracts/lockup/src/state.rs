@@ -1,3 +1,38 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Timestamp, Uint128, Uint64};
 use cw_controllers::Admin;
+use cw_storage_plus::{Item, Map};
 
 pub const ADMIN: Admin = Admin::new("admin");
+
+/// Locks of the contract by lock id
+pub const LOCKS: Map<u64, LockRecord> = Map::new("locks");
+
+// Locks being created indexed by reply id, saved under their lock id once x/lockup returns it
+pub const PENDING_LOCKS: Map<u64, LockRecord> = Map::new("pending_locks");
+
+// Sequence giving every submessage its own reply id
+pub const REPLY_SEQ: Item<u64> = Item::new("reply_seq");
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct LockRecord {
+    pub lock_id: Uint64,
+    pub denom: String,
+    pub amount: Uint128,
+    /// Lock duration in seconds
+    pub duration: Uint64,
+    /// Block time of the lock, or of the migration for locks created before tracking them
+    pub locked_at: Timestamp,
+    pub unlock_started_at: Option<Timestamp>,
+    pub status: LockStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LockStatus {
+    Locked,
+    /// Unlocking began, the coins can be claimed once the duration has passed
+    Unlocking,
+}
@@ -0,0 +1,77 @@
+use cosmwasm_std::{StdError, Storage};
+use cw2::get_contract_version;
+use thiserror::Error;
+
+pub use semver::Version;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MigrateError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("Cannot migrate from different contract type: {previous_contract}")]
+    CannotMigrate { previous_contract: String },
+
+    #[error("Cannot migrate from unsupported version: {previous_version}")]
+    CannotMigrateVersion { previous_version: String },
+}
+
+/// Ensures the stored contract is the same one at an older or equal version. Returns the previous
+/// version, so the state migrations needed can be run from it before storing the new version.
+pub fn check_migrate_version(
+    storage: &dyn Storage,
+    name: &str,
+    new_version: &str,
+) -> Result<Version, MigrateError> {
+    let stored = get_contract_version(storage)?;
+    if stored.contract != name {
+        return Err(MigrateError::CannotMigrate {
+            previous_contract: stored.contract,
+        });
+    }
+
+    let unsupported = || MigrateError::CannotMigrateVersion {
+        previous_version: stored.version.clone(),
+    };
+    let previous: Version = stored.version.parse().map_err(|_| unsupported())?;
+    let current: Version = new_version.parse().map_err(|_| unsupported())?;
+    if previous > current {
+        return Err(unsupported());
+    }
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use cw2::set_contract_version;
+
+    #[test]
+    fn migrate_versions() {
+        let mut storage = MockStorage::new();
+        set_contract_version(&mut storage, "crates.io:contract", "0.1.0").unwrap();
+        let previous = check_migrate_version(&storage, "crates.io:contract", "0.2.0").unwrap();
+        assert_eq!(previous, Version::new(0, 1, 0));
+
+        // same version
+        set_contract_version(&mut storage, "crates.io:contract", "0.2.0").unwrap();
+        check_migrate_version(&storage, "crates.io:contract", "0.2.0").unwrap();
+
+        let err = check_migrate_version(&storage, "crates.io:contract", "0.1.1").unwrap_err();
+        assert_eq!(
+            err,
+            MigrateError::CannotMigrateVersion {
+                previous_version: "0.2.0".to_string()
+            }
+        );
+
+        let err = check_migrate_version(&storage, "crates.io:other", "0.2.0").unwrap_err();
+        assert_eq!(
+            err,
+            MigrateError::CannotMigrate {
+                previous_contract: "crates.io:contract".to_string()
+            }
+        );
+    }
+}
@@ -1,7 +1,7 @@
 pub mod proto_ext;
 pub mod query;
 pub use prost::Message;
-pub use prost_types::Duration;
+pub use prost_types::{Duration, Timestamp};
 
 /// The version (commit hash) of the Osmosis SDK used when generating this library.
 pub const OSMOSIS_VERSION: &str = include_str!("types/OSMOSIS_COMMIT");
@@ -28,10 +28,74 @@ pub mod osmosis {
             include!("types/osmosis.gamm.v1beta1.rs");
             include!("paths/osmosis.gamm.v1beta1.rs");
         }
+
+        pub mod poolmodels {
+            pub mod balancer {
+                pub mod v1beta1 {
+                    include!("types/osmosis.gamm.poolmodels.balancer.v1beta1.rs");
+                    include!("paths/osmosis.gamm.poolmodels.balancer.v1beta1.rs");
+                }
+            }
+        }
     }
 
+    #[allow(clippy::doc_lazy_continuation)]
     pub mod lockup {
         include!("types/osmosis.lockup.rs");
         include!("paths/osmosis.lockup.rs");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::cosmos::base::v1beta1::Coin;
+    use super::osmosis::gamm::poolmodels::balancer::v1beta1::{
+        MsgCreateBalancerPool, MsgCreateBalancerPoolResponse,
+    };
+    use super::osmosis::gamm::v1beta1::{PoolAsset, PoolParams, SmoothWeightChangeParams};
+    use super::{Duration, Message};
+
+    // the hand-written balancer types must keep the field numbers of the Osmosis protos
+    #[test]
+    fn balancer_pool_wire_format() {
+        let msg = MsgCreateBalancerPool {
+            sender: "a".to_string(),
+            pool_params: Some(PoolParams {
+                swap_fee: "1".to_string(),
+                exit_fee: "2".to_string(),
+                smooth_weight_change_params: Some(SmoothWeightChangeParams {
+                    start_time: None,
+                    duration: Some(Duration {
+                        seconds: 60,
+                        nanos: 0,
+                    }),
+                    initial_pool_weights: vec![],
+                    target_pool_weights: vec![],
+                }),
+            }),
+            pool_assets: vec![PoolAsset {
+                token: Some(Coin {
+                    denom: "b".to_string(),
+                    amount: "3".to_string(),
+                }),
+                weight: "4".to_string(),
+            }],
+            future_pool_governor: "5".to_string(),
+        };
+        let expected: &[u8] = &[
+            0x0a, 1, b'a', // sender
+            0x12, 12, // pool_params
+            0x0a, 1, b'1', // swap_fee
+            0x12, 1, b'2', // exit_fee
+            0x1a, 4, 0x12, 2, 0x08, 60, // smooth_weight_change_params.duration
+            0x1a, 11, // pool_assets
+            0x0a, 6, 0x0a, 1, b'b', 0x12, 1, b'3', // token
+            0x12, 1, b'4', // weight
+            0x22, 1, b'5', // future_pool_governor
+        ];
+        assert_eq!(msg.encode_to_vec(), expected);
+
+        let res = MsgCreateBalancerPoolResponse::decode(&[0x08, 7][..]).unwrap();
+        assert_eq!(res.pool_id, 7);
+    }
+}
@@ -0,0 +1,7 @@
+use crate::proto_ext::ProtoUrl;
+
+impl ProtoUrl for MsgCreateBalancerPool {
+    fn path(&self) -> &str {
+        "/osmosis.gamm.poolmodels.balancer.v1beta1.MsgCreateBalancerPool"
+    }
+}
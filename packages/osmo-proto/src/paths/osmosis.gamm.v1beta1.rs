@@ -12,12 +12,30 @@ impl ProtoUrl for QuerySwapExactAmountInRequest {
     }
 }
 
+impl ProtoUrl for QueryTotalSharesRequest {
+    fn path(&self) -> &str {
+        "/osmosis.gamm.v1beta1.Query/TotalShares"
+    }
+}
+
+impl ProtoUrl for QueryTotalPoolLiquidityRequest {
+    fn path(&self) -> &str {
+        "/osmosis.gamm.v1beta1.Query/TotalPoolLiquidity"
+    }
+}
+
 impl ProtoUrl for MsgSwapExactAmountIn {
     fn path(&self) -> &str {
         "/osmosis.gamm.v1beta1.MsgSwapExactAmountIn"
     }
 }
 
+impl ProtoUrl for MsgSwapExactAmountOut {
+    fn path(&self) -> &str {
+        "/osmosis.gamm.v1beta1.MsgSwapExactAmountOut"
+    }
+}
+
 impl ProtoUrl for MsgJoinSwapExternAmountIn {
     fn path(&self) -> &str {
         "/osmosis.gamm.v1beta1.MsgJoinSwapExternAmountIn"
@@ -29,3 +47,15 @@ impl ProtoUrl for MsgExitSwapShareAmountIn {
         "/osmosis.gamm.v1beta1.MsgExitSwapShareAmountIn"
     }
 }
+
+impl ProtoUrl for MsgJoinPool {
+    fn path(&self) -> &str {
+        "/osmosis.gamm.v1beta1.MsgJoinPool"
+    }
+}
+
+impl ProtoUrl for MsgExitPool {
+    fn path(&self) -> &str {
+        "/osmosis.gamm.v1beta1.MsgExitPool"
+    }
+}
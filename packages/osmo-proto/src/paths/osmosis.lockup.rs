@@ -17,3 +17,39 @@ impl ProtoUrl for MsgBeginUnlocking {
         "/osmosis.lockup.MsgBeginUnlocking"
     }
 }
+
+impl ProtoUrl for MsgBeginUnlockingAll {
+    fn path(&self) -> &str {
+        "/osmosis.lockup.MsgBeginUnlockingAll"
+    }
+}
+
+impl ProtoUrl for MsgExtendLockup {
+    fn path(&self) -> &str {
+        "/osmosis.lockup.MsgExtendLockup"
+    }
+}
+
+impl ProtoUrl for AccountLockedCoinsRequest {
+    fn path(&self) -> &str {
+        "/osmosis.lockup.Query/AccountLockedCoins"
+    }
+}
+
+impl ProtoUrl for AccountUnlockableCoinsRequest {
+    fn path(&self) -> &str {
+        "/osmosis.lockup.Query/AccountUnlockableCoins"
+    }
+}
+
+impl ProtoUrl for AccountUnlockingCoinsRequest {
+    fn path(&self) -> &str {
+        "/osmosis.lockup.Query/AccountUnlockingCoins"
+    }
+}
+
+impl ProtoUrl for AccountLockedLongerDurationRequest {
+    fn path(&self) -> &str {
+        "/osmosis.lockup.Query/AccountLockedLongerDuration"
+    }
+}
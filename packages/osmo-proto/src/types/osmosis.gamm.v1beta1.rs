@@ -281,3 +281,38 @@ pub struct QueryTotalLiquidityResponse {
     #[prost(message, repeated, tag = "1")]
     pub liquidity: ::prost::alloc::vec::Vec<super::super::super::cosmos::base::v1beta1::Coin>,
 }
+// Hand-written from osmosis/gamm/pool-models/balancer/balancerPool.proto at v9.0.0, replace
+// with the generated types on the next regeneration.
+/// Parameters for changing the weights in a balancer pool smoothly from
+/// a start weight and end weight over a period of time.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SmoothWeightChangeParams {
+    #[prost(message, optional, tag = "1")]
+    pub start_time: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(message, optional, tag = "2")]
+    pub duration: ::core::option::Option<::prost_types::Duration>,
+    #[prost(message, repeated, tag = "3")]
+    pub initial_pool_weights: ::prost::alloc::vec::Vec<PoolAsset>,
+    #[prost(message, repeated, tag = "4")]
+    pub target_pool_weights: ::prost::alloc::vec::Vec<PoolAsset>,
+}
+/// PoolParams defined the parameters that will be managed by the pool
+/// governance in the future.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PoolParams {
+    #[prost(string, tag = "1")]
+    pub swap_fee: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub exit_fee: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub smooth_weight_change_params: ::core::option::Option<SmoothWeightChangeParams>,
+}
+/// Pool asset is an internal struct that combines the amount of the
+/// token in the pool, and its balancer weight.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PoolAsset {
+    #[prost(message, optional, tag = "1")]
+    pub token: ::core::option::Option<super::super::super::cosmos::base::v1beta1::Coin>,
+    #[prost(string, tag = "2")]
+    pub weight: ::prost::alloc::string::String,
+}